@@ -28,7 +28,28 @@ const ESCAPED: &AsciiSet = &CONTROLS
     .add(b'"')
     .add(b'<')
     .add(b'>')
-    .add(b'`');
+    .add(b'`')
+    .add(b'/');
+
+/// Prefixes of the special documents whose ids CouchDB expects with a literal `/`.
+const RESERVED_PREFIXES: [&str; 2] = ["_design/", "_local/"];
+
+/// Encodes a document id, or a partition, as a single path segment.
+/// Any `/` is escaped, except the one following the prefix of a design or local document.
+fn encode_id<ID: AsRef<[u8]>>(id: ID) -> String {
+    let id = id.as_ref();
+    let prefix = RESERVED_PREFIXES
+        .iter()
+        .map(|prefix| prefix.as_bytes())
+        .find(|prefix| id.starts_with(prefix))
+        .unwrap_or_default();
+    let (prefix, rest) = id.split_at(prefix.len());
+    format!(
+        "{}{}",
+        percent_encode(prefix, CONTROLS),
+        percent_encode(rest, ESCAPED)
+    )
+}
 
 #[derive(Clone)]
 pub struct Database {
@@ -132,7 +153,7 @@ impl Database {
             partition,
             &self.name
         );
-        let partition = percent_encode(partition.as_bytes(), ESCAPED).to_string();
+        let partition = encode_id(partition);
         let path = format!("{}/_partition/{}", &self.name, partition);
         let part = self
             .client
@@ -148,7 +169,7 @@ impl Database {
     }
 
     pub async fn get<R: DeserializeOwned>(&self, id: &str) -> Result<Option<R>> {
-        let id = percent_encode(id.as_bytes(), ESCAPED).to_string();
+        let id = encode_id(id);
         let path = format!("{}/{}", &self.name, id);
         log::debug!("Getting {} from couch", &path);
         match self.client.get(&path, None::<bool>).await {
@@ -166,7 +187,7 @@ impl Database {
         limit: usize,
         skip: usize,
     ) -> Result<RowsResponse<R>> {
-        let partition = percent_encode(partition.as_bytes(), ESCAPED).to_string();
+        let partition = encode_id(partition);
         let path = format!("{}/_partition/{}/_all_docs", &self.name, partition);
         self.do_list(&path, limit, skip).await
     }
@@ -233,7 +254,7 @@ impl Database {
         &self,
         partition: &str,
     ) -> Result<RowsResponse<R>> {
-        let partition = percent_encode(partition.as_bytes(), ESCAPED).to_string();
+        let partition = encode_id(partition);
         let path = format!("{}/_partition/{}/_all_docs", &self.name, partition);
         self.client
             .get(&path, Some(&[("include_docs", true)]))
@@ -246,7 +267,7 @@ impl Database {
         id: ID,
         entity: T,
     ) -> Result<PutResponse> {
-        let id = percent_encode(id.as_ref(), ESCAPED).to_string();
+        let id = encode_id(id);
         let path = format!("{}/{}", &self.name, &id);
         log::debug!("Putting {} into couch", &path);
         self.client
@@ -278,7 +299,7 @@ impl Database {
         limit: usize,
         skip: usize,
     ) -> Result<FindResponse<R>> {
-        let partition = percent_encode(partition.as_bytes(), ESCAPED).to_string();
+        let partition = encode_id(partition);
         let path = format!("{}/_partition/{}/_find", &self.name, partition);
         self.do_find(&path, selector, limit, skip).await
     }
@@ -317,7 +338,7 @@ impl Database {
         partition: &str,
         selector: serde_json::Value,
     ) -> impl Stream<Item = Result<R>> {
-        let partition = percent_encode(partition.as_bytes(), ESCAPED).to_string();
+        let partition = encode_id(partition);
         let path = format!("{}/_partition/{}/_find", &self.name, partition);
         self.do_find_stream(path, selector)
     }
@@ -367,7 +388,7 @@ impl Database {
     }
 
     pub async fn delete(&self, id: &str, rev: &str) -> Result<()> {
-        let id = percent_encode(id.as_bytes(), ESCAPED).to_string();
+        let id = encode_id(id);
         let path = format!("{}/{}", &self.name, id);
         log::debug!("Deleting {} from couch", &path);
         self.client.delete(&path, Some(&[("rev", rev)])).await?;
@@ -375,7 +396,7 @@ impl Database {
     }
 
     pub async fn exists(&self, id: &str) -> Result<bool> {
-        let id = percent_encode(id.as_bytes(), ESCAPED).to_string();
+        let id = encode_id(id);
         let path = format!("{}/{}", &self.name, id);
         log::debug!("Checking {} existence from couch", &path);
         let res = self.client.exists(&path).await?;
@@ -431,3 +452,29 @@ pub(super) struct ListQuery {
     pub limit: usize,
     pub skip: usize,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_escapes_slashes_in_ids() {
+        assert_eq!(
+            encode_id("repo-org/team/alpine"),
+            "repo-org%2Fteam%2Falpine"
+        );
+        assert_eq!(encode_id("oci_blob:sha256:abc"), "oci_blob:sha256:abc");
+        assert_eq!(
+            encode_id("oci_repo:org/alpine 1"),
+            "oci_repo:org%2Falpine%201"
+        );
+    }
+
+    #[test]
+    fn it_keeps_the_slash_of_design_and_local_documents() {
+        assert_eq!(encode_id("_design/oci"), "_design/oci");
+        assert_eq!(encode_id("_local/checkpoint"), "_local/checkpoint");
+        assert_eq!(encode_id("_design/org/oci"), "_design/org%2Foci");
+        assert_eq!(encode_id("org/_design/oci"), "org%2F_design%2Foci");
+    }
+}
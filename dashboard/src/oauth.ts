@@ -36,6 +36,11 @@ export const SCOPES = {
     'oci:image:push',
     'oci:image:pull',
     'oci:image:delete',
+    'oci:catalog:read',
+    'oci:replication:read',
+    'oci:replication:manage',
+    'oci:notifications:read',
+    'oci:notifications:manage',
   ],
   maven: [
     'maven:repos:read',
@@ -43,6 +48,10 @@ export const SCOPES = {
     'maven:repos:delete',
     'maven:repos:push',
     'maven:repos:pull',
+    'maven:replication:read',
+    'maven:replication:manage',
+    'maven:groups:read',
+    'maven:groups:manage',
   ],
 };
 
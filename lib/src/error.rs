@@ -7,6 +7,8 @@ use snafu::Snafu;
 pub enum Error {
     #[snafu(display("{}", reason))]
     Conflict { reason: String },
+    #[snafu(display("{}", reason))]
+    QuotaExceeded { reason: String },
     #[snafu(display("{} '{}' not found", typ, id))]
     NotFound { typ: String, id: String },
     #[snafu(display("{}", source))]
@@ -33,6 +35,10 @@ impl Error {
         Error::Conflict { reason }
     }
 
+    pub fn quota_exceeded(reason: String) -> Self {
+        Error::QuotaExceeded { reason }
+    }
+
     pub fn new(message: &str) -> Self {
         Error::Generic {
             message: message.to_string(),
@@ -43,6 +49,7 @@ impl Error {
         match self {
             Error::Conflict { .. } => StatusCode::CONFLICT,
             Error::NotFound { .. } => StatusCode::NOT_FOUND,
+            Error::QuotaExceeded { .. } => StatusCode::INSUFFICIENT_STORAGE,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
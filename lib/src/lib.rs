@@ -9,6 +9,9 @@ pub mod expiration;
 pub mod guid;
 pub mod pagination;
 pub mod predicate;
+pub mod quota;
+pub mod replication;
+pub mod retry;
 pub mod secure;
 pub mod storage;
 pub mod urn;
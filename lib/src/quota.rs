@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+
+/// Storage limits of a repository, in bytes. Unset limits are not enforced.
+///
+/// Exceeding the soft limit is allowed, but it should be reported,
+/// while uploads that would exceed the hard limit must be rejected.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Quota {
+    soft: Option<u64>,
+    hard: Option<u64>,
+}
+
+/// How the storage usage of a repository compares to its quota.
+#[derive(Debug, PartialEq)]
+pub enum QuotaStatus {
+    Within,
+    SoftExceeded,
+    HardExceeded,
+}
+
+impl Quota {
+    pub fn new(soft: Option<u64>, hard: Option<u64>) -> Self {
+        Self { soft, hard }
+    }
+
+    pub fn soft(&self) -> Option<u64> {
+        self.soft
+    }
+
+    pub fn hard(&self) -> Option<u64> {
+        self.hard
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.soft.is_none() && self.hard.is_none()
+    }
+
+    pub fn status(&self, usage: u64) -> QuotaStatus {
+        if self.hard.map_or(false, |hard| usage > hard) {
+            QuotaStatus::HardExceeded
+        } else if self.soft.map_or(false, |soft| usage > soft) {
+            QuotaStatus::SoftExceeded
+        } else {
+            QuotaStatus::Within
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_checks_usage_against_limits() {
+        let quota = Quota::new(Some(100), Some(200));
+        assert_eq!(quota.status(100), QuotaStatus::Within);
+        assert_eq!(quota.status(101), QuotaStatus::SoftExceeded);
+        assert_eq!(quota.status(200), QuotaStatus::SoftExceeded);
+        assert_eq!(quota.status(201), QuotaStatus::HardExceeded);
+
+        let unlimited = Quota::default();
+        assert!(unlimited.is_unlimited());
+        assert_eq!(unlimited.status(u64::MAX), QuotaStatus::Within);
+
+        let hard_only = Quota::new(None, Some(10));
+        assert_eq!(hard_only.status(10), QuotaStatus::Within);
+        assert_eq!(hard_only.status(11), QuotaStatus::HardExceeded);
+    }
+}
@@ -0,0 +1,80 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::secure::EncryptedSecret;
+
+/// A remote instance artifacts are replicated to.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Remote {
+    url: String,
+    username: Option<String>,
+    password: Option<EncryptedSecret>,
+}
+
+impl Remote {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            username: None,
+            password: None,
+        }
+    }
+
+    pub fn with_credentials(mut self, username: String, password: String) -> Self {
+        self.username = Some(username);
+        self.password = Some(EncryptedSecret::new(password));
+        self
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+
+    pub fn password(&self) -> Option<&str> {
+        self.password.as_ref().map(EncryptedSecret::expose)
+    }
+}
+
+/// The outcome of the replications performed by a rule.
+/// Failures are recorded once all the attempts of a replication failed.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ReplicationStatus {
+    replicated: u64,
+    failed: u64,
+    last_replicated: Option<String>,
+    last_success_at: Option<DateTime<Utc>>,
+    last_failure_at: Option<DateTime<Utc>>,
+    last_error: Option<String>,
+}
+
+impl ReplicationStatus {
+    pub fn record_success(&mut self, artifact: String) -> &mut Self {
+        self.replicated += 1;
+        self.last_replicated = Some(artifact);
+        self.last_success_at = Some(Utc::now());
+        self
+    }
+
+    pub fn record_failure(&mut self, artifact: String, error: String) -> &mut Self {
+        self.failed += 1;
+        self.last_failure_at = Some(Utc::now());
+        self.last_error = Some(format!("{}: {}", artifact, error));
+        self
+    }
+
+    pub fn replicated(&self) -> u64 {
+        self.replicated
+    }
+
+    pub fn failed(&self) -> u64 {
+        self.failed
+    }
+
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+}
@@ -0,0 +1,86 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+
+use actix::clock::delay_for;
+
+/// Exponential backoff between the attempts of an operation.
+/// The delay doubles after each failed attempt, up to a maximum.
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    attempts: u32,
+    initial: Duration,
+    max: Duration,
+}
+
+impl Backoff {
+    pub fn new(attempts: u32, initial: Duration, max: Duration) -> Self {
+        Self {
+            attempts,
+            initial,
+            max,
+        }
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// The delay after the given failed attempt, counting from 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial
+            .checked_mul(factor)
+            .map_or(self.max, |delay| delay.min(self.max))
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(5, Duration::from_secs(2), Duration::from_secs(300))
+    }
+}
+
+/// Runs an operation until it succeeds or it runs out of attempts,
+/// waiting between attempts. Returns the error of the last attempt.
+pub async fn retry<T, E, F, Fut>(backoff: &Backoff, mut op: F) -> Result<T, E>
+where
+    E: Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut attempt = 1;
+    loop {
+        match op().await {
+            Ok(res) => return Ok(res),
+            Err(err) if attempt >= backoff.attempts() => return Err(err),
+            Err(err) => {
+                let delay = backoff.delay(attempt);
+                log::warn!(
+                    "attempt {} of {} failed, retrying in {:?}: {}",
+                    attempt,
+                    backoff.attempts(),
+                    delay,
+                    err
+                );
+                delay_for(delay).await;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_doubles_the_delay_up_to_the_max() {
+        let backoff = Backoff::new(10, Duration::from_secs(1), Duration::from_secs(10));
+        assert_eq!(backoff.delay(1), Duration::from_secs(1));
+        assert_eq!(backoff.delay(2), Duration::from_secs(2));
+        assert_eq!(backoff.delay(4), Duration::from_secs(8));
+        assert_eq!(backoff.delay(5), Duration::from_secs(10));
+        assert_eq!(backoff.delay(100), Duration::from_secs(10));
+    }
+}
@@ -1,9 +1,12 @@
-use std::fmt::{self, Display, Formatter};
+use std::fmt::{self, Debug, Display, Formatter};
+use std::sync::RwLock;
 
 pub use base64;
-use ring::digest::{Context, SHA256};
-use ring::hmac::{self, Key, HMAC_SHA512};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::digest::{Context, SHA1_FOR_LEGACY_USE_ONLY, SHA256, SHA512};
+use ring::hmac::{self, Key, HMAC_SHA256, HMAC_SHA512};
 use ring::rand::{SecureRandom, SystemRandom};
+use serde::{de, ser, Deserialize, Deserializer, Serialize, Serializer};
 
 lazy_static! {
     static ref SECURE_RANDOM: SystemRandom = SystemRandom::new();
@@ -13,6 +16,13 @@ lazy_static! {
     static ref ARGON_CONFIG: argon2::Config<'static> = argon2::Config::default();
 }
 
+lazy_static! {
+    static ref ENCRYPTION_KEY: RwLock<Option<[u8; 32]>> = RwLock::new(None);
+}
+
+/// Prefix of the secrets encrypted by `EncryptedSecret`.
+const ENCRYPTED_PREFIX: &str = "aes256gcm:";
+
 #[derive(Debug, Clone)]
 pub struct SecureSecret(Vec<u8>);
 
@@ -67,12 +77,171 @@ pub fn verify_password(hash: &str, pwd: &str) -> Result<bool, String> {
     argon2::verify_encoded(hash, pwd.as_bytes()).map_err(|err| err.to_string())
 }
 
+/// Derives the key `EncryptedSecret` values are encrypted with from the secret key
+/// of the instance. Must be called at startup, before any of them is stored or loaded.
+pub fn init_encryption_key(secret: &str) {
+    *ENCRYPTION_KEY.write().unwrap() = Some(derive_encryption_key(secret));
+}
+
+fn derive_encryption_key(secret: &str) -> [u8; 32] {
+    let key = Key::new(HMAC_SHA256, secret.as_bytes());
+    let tag = hmac::sign(&key, b"enseada encrypted secrets");
+    let mut bytes = [0; 32];
+    bytes.copy_from_slice(tag.as_ref());
+    bytes
+}
+
+fn encryption_key(bytes: &[u8; 32]) -> LessSafeKey {
+    LessSafeKey::new(UnboundKey::new(&AES_256_GCM, bytes).expect("AES-256 key"))
+}
+
+fn encrypt(key: &[u8; 32], plain: &str) -> Result<String, String> {
+    let mut nonce = [0; NONCE_LEN];
+    SECURE_RANDOM.fill(&mut nonce).map_err(|e| e.to_string())?;
+    let mut sealed = plain.as_bytes().to_vec();
+    encryption_key(key)
+        .seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::empty(),
+            &mut sealed,
+        )
+        .map_err(|_| "failed to encrypt secret".to_string())?;
+    let mut payload = nonce.to_vec();
+    payload.extend(sealed);
+    Ok(format!("{}{}", ENCRYPTED_PREFIX, base64::encode(payload)))
+}
+
+fn decrypt(key: &[u8; 32], encrypted: &str) -> Result<String, String> {
+    let payload = base64::decode(encrypted).map_err(|e| e.to_string())?;
+    if payload.len() < NONCE_LEN {
+        return Err("encrypted secret is too short".to_string());
+    }
+    let (nonce, sealed) = payload.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| "invalid nonce")?;
+    let mut sealed = sealed.to_vec();
+    let plain = encryption_key(key)
+        .open_in_place(nonce, Aad::empty(), &mut sealed)
+        .map_err(|_| "failed to decrypt secret, was the secret key changed?".to_string())?;
+    String::from_utf8(plain.to_vec()).map_err(|e| e.to_string())
+}
+
+fn configured_encryption_key() -> Result<[u8; 32], String> {
+    ENCRYPTION_KEY
+        .read()
+        .unwrap()
+        .ok_or_else(|| "no encryption key initialized".to_string())
+}
+
+/// A secret, like the password of a remote repository, that is stored encrypted
+/// with the key set by `init_encryption_key` and is only in plain text in memory.
+/// Secrets stored in plain text are still read, and encrypted when saved again.
+#[derive(Clone, PartialEq)]
+pub struct EncryptedSecret(String);
+
+impl EncryptedSecret {
+    pub fn new(plain: String) -> Self {
+        EncryptedSecret(plain)
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Debug for EncryptedSecret {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptedSecret(***)")
+    }
+}
+
+impl Serialize for EncryptedSecret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let encrypted = configured_encryption_key()
+            .and_then(|key| encrypt(&key, &self.0))
+            .map_err(ser::Error::custom)?;
+        serializer.serialize_str(&encrypted)
+    }
+}
+
+impl<'de> Deserialize<'de> for EncryptedSecret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        match value.strip_prefix(ENCRYPTED_PREFIX) {
+            Some(encrypted) => configured_encryption_key()
+                .and_then(|key| decrypt(&key, encrypted))
+                .map(EncryptedSecret)
+                .map_err(de::Error::custom),
+            None => Ok(EncryptedSecret(value)),
+        }
+    }
+}
+
+/// MD5 is broken, only use it to verify the integrity of content.
+pub fn md5sum<S: AsRef<[u8]>>(s: S) -> SecureSecret {
+    SecureSecret::new(md5::compute(s.as_ref()).0.to_vec())
+}
+
+/// SHA-1 is broken, only use it to verify the integrity of content.
+pub fn sha1sum<S: AsRef<[u8]>>(s: S) -> SecureSecret {
+    let mut ctx = Context::new(&SHA1_FOR_LEGACY_USE_ONLY);
+    ctx.update(s.as_ref());
+    SecureSecret::new(ctx.finish().as_ref())
+}
+
 pub fn sha256sum<S: AsRef<[u8]>>(s: S) -> SecureSecret {
     let mut ctx = Context::new(&SHA256);
     ctx.update(s.as_ref());
     SecureSecret::new(ctx.finish().as_ref())
 }
 
+pub fn sha512sum<S: AsRef<[u8]>>(s: S) -> SecureSecret {
+    let mut ctx = Context::new(&SHA512);
+    ctx.update(s.as_ref());
+    SecureSecret::new(ctx.finish().as_ref())
+}
+
+/// Incrementally computes a checksum over content that is not available all at once.
+#[derive(Clone)]
+pub struct Hasher(HasherContext);
+
+#[derive(Clone)]
+enum HasherContext {
+    Md5(md5::Context),
+    Ring(Context),
+}
+
+impl Hasher {
+    pub fn md5() -> Self {
+        Hasher(HasherContext::Md5(md5::Context::new()))
+    }
+
+    pub fn sha1() -> Self {
+        Hasher(HasherContext::Ring(Context::new(&SHA1_FOR_LEGACY_USE_ONLY)))
+    }
+
+    pub fn sha256() -> Self {
+        Hasher(HasherContext::Ring(Context::new(&SHA256)))
+    }
+
+    pub fn sha512() -> Self {
+        Hasher(HasherContext::Ring(Context::new(&SHA512)))
+    }
+
+    pub fn update<S: AsRef<[u8]>>(&mut self, s: S) {
+        match &mut self.0 {
+            HasherContext::Md5(ctx) => ctx.consume(s.as_ref()),
+            HasherContext::Ring(ctx) => ctx.update(s.as_ref()),
+        }
+    }
+
+    pub fn finish(self) -> SecureSecret {
+        match self.0 {
+            HasherContext::Md5(ctx) => SecureSecret::new(ctx.compute().0.to_vec()),
+            HasherContext::Ring(ctx) => SecureSecret::new(ctx.finish().as_ref()),
+        }
+    }
+}
+
 pub fn base64url_encode<S: AsRef<[u8]>>(s: S) -> String {
     base64::encode_config(s, base64::URL_SAFE_NO_PAD)
 }
@@ -86,7 +255,9 @@ pub fn pkce_challenge<V: AsRef<[u8]>>(code_verifier: V) -> String {
 #[cfg(test)]
 mod test {
     use crate::secure::{
-        generate_token, hash_password, pkce_challenge, sha256sum, verify_password,
+        decrypt, derive_encryption_key, encrypt, generate_token, hash_password,
+        init_encryption_key, md5sum, pkce_challenge, sha1sum, sha256sum, sha512sum,
+        verify_password, EncryptedSecret, Hasher, ENCRYPTED_PREFIX,
     };
 
     #[test]
@@ -108,6 +279,24 @@ mod test {
         assert!(r.unwrap());
     }
 
+    #[test]
+    fn it_generates_a_md5_checksum() {
+        let s = "this is a test string";
+        let exp_md5 = "486eb65274adb86441072afa1e2289f3";
+
+        let md5 = md5sum(s);
+        assert_eq!(exp_md5, md5.to_string());
+    }
+
+    #[test]
+    fn it_generates_a_sha1_checksum() {
+        let s = "this is a test string";
+        let exp_sha = "9a375f77abb15794900c2689812204273d757c9b";
+
+        let sha = sha1sum(s);
+        assert_eq!(exp_sha, sha.to_string());
+    }
+
     #[test]
     fn it_generates_a_sha256_checksum() {
         let s = "this is a test string";
@@ -117,6 +306,36 @@ mod test {
         assert_eq!(exp_sha, sha.to_string());
     }
 
+    #[test]
+    fn it_generates_a_sha512_checksum() {
+        let s = "this is a test string";
+        let exp_sha = "c240dd0b1a9b00c2478ab95f2184c81d0f3f923a751c71e61af36bb34fe9f240399ca3af2f061cbc1da2535ce93f6bcedead90cad16f14346cd34f394ee02f5e";
+
+        let sha = sha512sum(s);
+        assert_eq!(exp_sha, sha.to_string());
+    }
+
+    #[test]
+    fn it_generates_a_checksum_incrementally() {
+        let mut hasher = Hasher::sha256();
+        hasher.update("this is ");
+        hasher.update("a test string");
+
+        assert_eq!(
+            sha256sum("this is a test string").to_string(),
+            hasher.finish().to_string()
+        );
+
+        let mut hasher = Hasher::md5();
+        hasher.update("this is ");
+        hasher.update("a test string");
+
+        assert_eq!(
+            md5sum("this is a test string").to_string(),
+            hasher.finish().to_string()
+        );
+    }
+
     #[test]
     fn it_encodes_a_pkce_verifier() {
         let verifier = "4a52ca3f5a6c4a47bb41c0c58105c3c2d848b69537464e8f86b9fb1f45815b9e2dadd0174fa440f89899dbab9d6f1400";
@@ -124,4 +343,35 @@ mod test {
         let challenge = pkce_challenge(verifier);
         assert_eq!(exp_challenge, challenge);
     }
+
+    #[test]
+    fn it_stores_secrets_encrypted() {
+        init_encryption_key("an encryption key of the test instance");
+        let secret = EncryptedSecret::new("supersecretpassword".to_string());
+
+        let stored = serde_json::to_string(&secret).unwrap();
+        assert!(stored.contains(ENCRYPTED_PREFIX));
+        assert!(!stored.contains("supersecretpassword"));
+        assert_ne!(stored, serde_json::to_string(&secret).unwrap());
+
+        let loaded: EncryptedSecret = serde_json::from_str(&stored).unwrap();
+        assert_eq!(loaded.expose(), "supersecretpassword");
+    }
+
+    #[test]
+    fn it_reads_secrets_stored_in_plain_text() {
+        let loaded: EncryptedSecret = serde_json::from_str("\"supersecretpassword\"").unwrap();
+        assert_eq!(loaded.expose(), "supersecretpassword");
+    }
+
+    #[test]
+    fn it_fails_to_decrypt_with_another_key() {
+        let key = derive_encryption_key("an encryption key of the test instance");
+        let other = derive_encryption_key("an encryption key of another instance");
+        let encrypted = encrypt(&key, "supersecretpassword").unwrap();
+        let encrypted = encrypted.strip_prefix(ENCRYPTED_PREFIX).unwrap();
+
+        assert_eq!(decrypt(&key, encrypted).unwrap(), "supersecretpassword");
+        assert!(decrypt(&other, encrypted).is_err());
+    }
 }
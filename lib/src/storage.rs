@@ -1,11 +1,253 @@
 use std::io;
 use std::pin::Pin;
 
+use async_trait::async_trait;
 pub use bytes::*;
-use futures::Stream;
+use chrono::{DateTime, Utc};
+use futures::{future, Stream, StreamExt};
 pub use hold::*;
 
-pub type Provider = Box<dyn hold::provider::Provider + Send + Sync>;
+pub type Provider = Box<dyn StorageProvider>;
 
 pub type ByteChunk = std::result::Result<Bytes, io::Error>;
 pub type ByteStream = Pin<Box<dyn Stream<Item = ByteChunk> + Send + Sync + 'static>>;
+
+/// A storage provider, with the operations `hold` providers lack.
+#[async_trait]
+pub trait StorageProvider: hold::provider::Provider + Send + Sync {
+    /// Lists the blobs whose key starts with the given prefix.
+    async fn list_blobs(&self, prefix: &str) -> hold::Result<Vec<StoredBlob>>;
+
+    /// Fetches a byte range of a blob given its key. Providers should only read
+    /// the range from the storage, by default it's sliced out of the whole content.
+    async fn get_blob_range(
+        &self,
+        key: &str,
+        range: ByteRange,
+    ) -> hold::Result<Option<blob::Blob>> {
+        let blob = match self.get_blob(key).await? {
+            Some(blob) => blob,
+            None => return Ok(None),
+        };
+        let content = slice(blob.into_byte_stream(), range);
+        Ok(Some(blob::Blob::new(key, range.len(), content)))
+    }
+
+    /// Moves a blob to another key, replacing any blob stored there, so that content
+    /// can be staged under a temporary key and only promoted once verified.
+    /// Returns `false` if there is no blob to move.
+    /// Providers should copy the blob within the storage, by default its content
+    /// is streamed to the new key.
+    async fn move_blob(&self, from: &str, to: &str) -> hold::Result<bool> {
+        stream_blob(self, from, to).await
+    }
+}
+
+/// A blob listed by a storage provider.
+#[derive(Clone, Debug)]
+pub struct StoredBlob {
+    key: String,
+    size: usize,
+    last_modified: Option<DateTime<Utc>>,
+}
+
+impl StoredBlob {
+    pub fn new(key: &str, size: usize, last_modified: Option<DateTime<Utc>>) -> Self {
+        Self {
+            key: key.to_string(),
+            size,
+            last_modified,
+        }
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Some providers may not report when a blob was last modified.
+    pub fn last_modified(&self) -> Option<&DateTime<Utc>> {
+        self.last_modified.as_ref()
+    }
+}
+
+/// An inclusive range of bytes of a blob.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ByteRange {
+    start: usize,
+    end: usize,
+}
+
+impl ByteRange {
+    /// Parses the value of an HTTP `Range` header for a content of the given size.
+    /// Returns `None` if the value is malformed or requests multiple ranges,
+    /// in which case the whole content should be served, and `Some(Err(()))`
+    /// if the range cannot be satisfied.
+    pub fn from_header(value: &str, size: usize) -> Option<std::result::Result<Self, ()>> {
+        let spec = value.trim().strip_prefix("bytes=")?;
+        if spec.contains(',') {
+            return None;
+        }
+
+        let mut parts = spec.splitn(2, '-');
+        let start = parts.next()?.trim();
+        let end = parts.next()?.trim();
+        let range = match (start.is_empty(), end.is_empty()) {
+            // suffix range, e.g. `bytes=-500`
+            (true, false) => {
+                let suffix: usize = end.parse().ok()?;
+                if suffix == 0 || size == 0 {
+                    return Some(Err(()));
+                }
+                ByteRange {
+                    start: size.saturating_sub(suffix),
+                    end: size - 1,
+                }
+            }
+            (false, _) => {
+                let start: usize = start.parse().ok()?;
+                let end: usize = if end.is_empty() {
+                    usize::MAX
+                } else {
+                    end.parse().ok()?
+                };
+                if end < start {
+                    return None;
+                }
+                if start >= size {
+                    return Some(Err(()));
+                }
+                ByteRange {
+                    start,
+                    end: end.min(size - 1),
+                }
+            }
+            (true, true) => return None,
+        };
+        Some(Ok(range))
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start + 1
+    }
+
+    /// A parsed range always includes at least one byte.
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+}
+
+/// Moves a blob by streaming its content to another key and deleting the original.
+/// Returns `false` if there is no blob to move.
+pub async fn stream_blob<P: StorageProvider + ?Sized>(
+    provider: &P,
+    from: &str,
+    to: &str,
+) -> hold::Result<bool> {
+    let blob = match provider.get_blob(from).await? {
+        Some(blob) => blob,
+        None => return Ok(false),
+    };
+    let size = blob.size();
+    provider
+        .store_blob(blob::Blob::new(to, size, blob.into_byte_stream()))
+        .await?;
+    provider.delete_blob(from).await?;
+    Ok(true)
+}
+
+fn slice<S: Stream<Item = ByteChunk>>(
+    stream: S,
+    range: ByteRange,
+) -> impl Stream<Item = ByteChunk> {
+    stream
+        .scan(0, move |offset: &mut usize, chunk: ByteChunk| {
+            let chunk = match chunk {
+                Ok(bytes) => {
+                    let chunk_start = *offset;
+                    *offset += bytes.len();
+                    if chunk_start > range.end {
+                        // past the end of the range, the stream can stop here
+                        return future::ready(None);
+                    }
+                    let from = range.start.saturating_sub(chunk_start).min(bytes.len());
+                    let to = (range.end + 1 - chunk_start).min(bytes.len());
+                    if from < to {
+                        Some(Ok(bytes.slice(from..to)))
+                    } else {
+                        None
+                    }
+                }
+                Err(err) => Some(Err(err)),
+            };
+            future::ready(Some(chunk))
+        })
+        .filter_map(future::ready)
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{executor, stream, TryStreamExt};
+
+    use super::*;
+
+    #[test]
+    fn it_parses_a_range_header() {
+        let range = |value| ByteRange::from_header(value, 1000);
+
+        assert_eq!(
+            range("bytes=0-499"),
+            Some(Ok(ByteRange { start: 0, end: 499 }))
+        );
+        assert_eq!(
+            range("bytes=500-"),
+            Some(Ok(ByteRange {
+                start: 500,
+                end: 999
+            }))
+        );
+        assert_eq!(
+            range("bytes=-100"),
+            Some(Ok(ByteRange {
+                start: 900,
+                end: 999
+            }))
+        );
+        assert_eq!(
+            range("bytes=900-2000"),
+            Some(Ok(ByteRange {
+                start: 900,
+                end: 999
+            }))
+        );
+        assert_eq!(range("bytes=1000-"), Some(Err(())));
+        assert_eq!(range("bytes=0-1,5-10"), None);
+        assert_eq!(range("items=0-10"), None);
+        assert_eq!(range("bytes=10-5"), None);
+    }
+
+    #[test]
+    fn it_slices_a_byte_stream() {
+        let chunks: Vec<ByteChunk> = vec![
+            Ok(Bytes::from("hello")),
+            Ok(Bytes::from(" ")),
+            Ok(Bytes::from("world")),
+        ];
+        let range = ByteRange { start: 3, end: 7 };
+        let sliced: Vec<Bytes> =
+            executor::block_on(slice(stream::iter(chunks), range).try_collect()).unwrap();
+
+        assert_eq!(sliced.concat(), b"lo wo".to_vec());
+    }
+}
@@ -5,7 +5,7 @@ use crate::compare::{cmp_integer, cmp_list, cmp_null, cmp_string};
 use crate::error::Error;
 use crate::lexer::Token;
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Item {
     Integer(u64),
     String(String),
@@ -9,7 +9,7 @@ use crate::lexer::Lexer;
 use crate::parser::Item;
 use crate::parser::Parser;
 
-#[derive(Debug, Eq)]
+#[derive(Clone, Debug, Eq)]
 pub struct Version {
     value: String,
     items: Item,
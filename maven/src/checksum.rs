@@ -0,0 +1,179 @@
+use enseada::secure::Hasher;
+
+/// The checksum algorithms of the files served alongside every Maven file,
+/// e.g. `foo-1.0.jar.sha1` for `foo-1.0.jar`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChecksumAlgorithm {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl ChecksumAlgorithm {
+    pub const ALL: [ChecksumAlgorithm; 4] = [
+        ChecksumAlgorithm::Md5,
+        ChecksumAlgorithm::Sha1,
+        ChecksumAlgorithm::Sha256,
+        ChecksumAlgorithm::Sha512,
+    ];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Md5 => "md5",
+            ChecksumAlgorithm::Sha1 => "sha1",
+            ChecksumAlgorithm::Sha256 => "sha256",
+            ChecksumAlgorithm::Sha512 => "sha512",
+        }
+    }
+
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|algo| algo.extension() == extension)
+    }
+
+    fn hasher(self) -> Hasher {
+        match self {
+            ChecksumAlgorithm::Md5 => Hasher::md5(),
+            ChecksumAlgorithm::Sha1 => Hasher::sha1(),
+            ChecksumAlgorithm::Sha256 => Hasher::sha256(),
+            ChecksumAlgorithm::Sha512 => Hasher::sha512(),
+        }
+    }
+}
+
+/// Splits the name of a checksum file into the name of the checksummed file
+/// and the algorithm, e.g. `foo-1.0.jar.sha1` into `foo-1.0.jar` and SHA-1.
+pub fn checksum_target(filename: &str) -> Option<(&str, ChecksumAlgorithm)> {
+    let dot = filename.rfind('.')?;
+    let algo = ChecksumAlgorithm::from_extension(&filename[dot + 1..])?;
+    let target = &filename[..dot];
+    if target.is_empty() {
+        return None;
+    }
+    Some((target, algo))
+}
+
+/// The name of the checksum file of a file.
+pub fn checksum_file(filename: &str, algo: ChecksumAlgorithm) -> String {
+    format!("{}.{}", filename, algo.extension())
+}
+
+/// Reads the checksum from the content of a checksum file. Some clients
+/// write the name of the file after the checksum, like `sha1sum` does.
+pub fn parse_checksum(content: &[u8]) -> Option<String> {
+    let content = std::str::from_utf8(content).ok()?;
+    content
+        .split_whitespace()
+        .next()
+        .map(|checksum| checksum.to_lowercase())
+}
+
+/// The checksums of a file, with every supported algorithm.
+#[derive(Clone, Debug)]
+pub struct Checksums(Vec<(ChecksumAlgorithm, String)>);
+
+impl Checksums {
+    pub fn compute<C: AsRef<[u8]>>(content: C) -> Self {
+        let mut hasher = ChecksumHasher::new();
+        hasher.update(content);
+        hasher.finish()
+    }
+
+    pub fn get(&self, algo: ChecksumAlgorithm) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(a, _)| *a == algo)
+            .map(|(_, checksum)| checksum.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ChecksumAlgorithm, &str)> {
+        self.0
+            .iter()
+            .map(|(algo, checksum)| (*algo, checksum.as_str()))
+    }
+}
+
+/// Incrementally computes the checksums of a file while it is being stored.
+#[derive(Clone)]
+pub struct ChecksumHasher(Vec<(ChecksumAlgorithm, Hasher)>);
+
+impl ChecksumHasher {
+    pub fn new() -> Self {
+        Self(
+            ChecksumAlgorithm::ALL
+                .iter()
+                .map(|algo| (*algo, algo.hasher()))
+                .collect(),
+        )
+    }
+
+    pub fn update<C: AsRef<[u8]>>(&mut self, content: C) {
+        for (_, hasher) in self.0.iter_mut() {
+            hasher.update(content.as_ref());
+        }
+    }
+
+    pub fn finish(self) -> Checksums {
+        Checksums(
+            self.0
+                .into_iter()
+                .map(|(algo, hasher)| (algo, hasher.finish().to_string()))
+                .collect(),
+        )
+    }
+}
+
+impl Default for ChecksumHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_splits_checksum_files() {
+        assert_eq!(
+            checksum_target("test-1.0.jar.sha1"),
+            Some(("test-1.0.jar", ChecksumAlgorithm::Sha1))
+        );
+        assert_eq!(
+            checksum_target("maven-metadata.xml.sha512"),
+            Some(("maven-metadata.xml", ChecksumAlgorithm::Sha512))
+        );
+        assert_eq!(checksum_target("test-1.0.jar"), None);
+        assert_eq!(checksum_target(".md5"), None);
+    }
+
+    #[test]
+    fn it_computes_every_checksum() {
+        let checksums = Checksums::compute("this is a test string");
+        assert_eq!(
+            checksums.get(ChecksumAlgorithm::Md5),
+            Some("486eb65274adb86441072afa1e2289f3")
+        );
+        assert_eq!(
+            checksums.get(ChecksumAlgorithm::Sha1),
+            Some("9a375f77abb15794900c2689812204273d757c9b")
+        );
+        assert_eq!(checksums.iter().count(), 4);
+    }
+
+    #[test]
+    fn it_parses_checksum_files() {
+        assert_eq!(
+            parse_checksum(b"9A375F77ABB15794900C2689812204273D757C9B\n"),
+            Some("9a375f77abb15794900c2689812204273d757c9b".to_string())
+        );
+        assert_eq!(
+            parse_checksum(b"486eb65274adb86441072afa1e2289f3  test-1.0.jar"),
+            Some("486eb65274adb86441072afa1e2289f3".to_string())
+        );
+        assert_eq!(parse_checksum(b"  "), None);
+    }
+}
@@ -1,8 +1,16 @@
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
 
 use enseada::couchdb::repository::Entity;
 use enseada::guid::Guid;
-use enseada::secure;
+use enseada::quota::Quota;
+use enseada::secure::{self, EncryptedSecret};
+
+/// The directory of a proxy repository holding the files it caches for the
+/// other locations it serves as a member of a group, e.g.
+/// `org/example/central/~/org/example/foo/1.0/foo-1.0.jar`.
+pub const PROXIED_DIR: &str = "~";
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Repo {
@@ -15,6 +23,16 @@ pub struct Repo {
     decoded_location: String,
     public: bool,
     files: Vec<String>,
+    #[serde(default)]
+    file_sizes: HashMap<String, u64>,
+    #[serde(default)]
+    quota: Quota,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    snapshot_retention: Option<usize>,
+    #[serde(default)]
+    kind: RepoKind,
+    #[serde(skip)]
+    proxied_by: Option<String>,
 }
 
 impl Repo {
@@ -30,6 +48,11 @@ impl Repo {
             decoded_location,
             public,
             files: Vec::new(),
+            file_sizes: HashMap::new(),
+            quota: Quota::default(),
+            snapshot_retention: None,
+            kind: RepoKind::default(),
+            proxied_by: None,
         }
     }
 
@@ -53,11 +76,105 @@ impl Repo {
         &self.files
     }
 
-    pub fn add_file<F: ToString>(&mut self, file: F) -> &mut Self {
-        self.files.push(file.to_string());
+    pub fn add_file<F: ToString>(&mut self, file: F, size: u64) -> &mut Self {
+        let file = file.to_string();
+        self.file_sizes.insert(file.clone(), size);
+        if !self.files.contains(&file) {
+            self.files.push(file);
+        }
+        self
+    }
+
+    pub fn remove_file(&mut self, file: &str) -> &mut Self {
+        self.file_sizes.remove(file);
+        self.files.retain(|f| f != file);
+        self
+    }
+
+    /// Files stored before sizes were recorded have no size.
+    pub fn file_size(&self, file: &str) -> Option<u64> {
+        self.file_sizes.get(file).copied()
+    }
+
+    /// The bytes stored in the repository.
+    pub fn usage(&self) -> u64 {
+        self.file_sizes.values().sum()
+    }
+
+    pub fn quota(&self) -> &Quota {
+        &self.quota
+    }
+
+    pub fn set_quota(&mut self, quota: Quota) -> &mut Self {
+        self.quota = quota;
+        self
+    }
+
+    /// The number of builds kept for each snapshot version, if limited.
+    /// Older builds are deleted when a new one is deployed.
+    pub fn snapshot_retention(&self) -> Option<usize> {
+        self.snapshot_retention
+    }
+
+    pub fn set_snapshot_retention(&mut self, retention: Option<usize>) -> &mut Self {
+        self.snapshot_retention = retention;
         self
     }
 
+    pub fn kind(&self) -> &RepoKind {
+        &self.kind
+    }
+
+    pub fn set_kind(&mut self, kind: RepoKind) -> &mut Self {
+        self.kind = kind;
+        self
+    }
+
+    /// The remote repository this repository proxies, if any.
+    pub fn upstream(&self) -> Option<&Upstream> {
+        match &self.kind {
+            RepoKind::Hosted => None,
+            RepoKind::Proxy(upstream) => Some(upstream),
+        }
+    }
+
+    /// A view of another location served by this proxy repository, as a member
+    /// of a group covering it. The files of the view are fetched from the same
+    /// location of the remote repository, and cached under the [`PROXIED_DIR`]
+    /// of this repository, apart from the ones of any repository at that location.
+    /// Views are never saved.
+    pub fn proxy_location(&self, location: &str) -> Option<Repo> {
+        self.upstream()?;
+        let location = location.trim_matches('/');
+        let slash = location.rfind('/')?;
+        let mut repo = Repo::new(
+            location[..slash].replace('/', "."),
+            &location[slash + 1..],
+            self.public,
+        );
+        repo.kind = self.kind.clone();
+        repo.proxied_by = Some(self.location().to_string());
+        Some(repo)
+    }
+
+    /// The location of the proxy repository this is a view of, if any.
+    pub fn proxied_by(&self) -> Option<&str> {
+        self.proxied_by.as_deref()
+    }
+
+    /// The location the files of the repository are stored under.
+    pub fn storage_location(&self) -> String {
+        match &self.proxied_by {
+            Some(proxy) => format!("{}/{}/{}", proxy, PROXIED_DIR, self.location()),
+            None => self.location().to_string(),
+        }
+    }
+
+    #[inline]
+    pub fn is_proxy(&self) -> bool {
+        self.upstream().is_some()
+    }
+
     #[inline]
     pub fn is_private(&self) -> bool {
         !self.is_public()
@@ -72,6 +189,74 @@ impl Repo {
     }
 }
 
+/// Whether files are deployed to a repository, or pulled through from a remote one.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RepoKind {
+    Hosted,
+    Proxy(Upstream),
+}
+
+impl Default for RepoKind {
+    fn default() -> Self {
+        RepoKind::Hosted
+    }
+}
+
+/// A remote Maven repository, e.g. Maven Central, pulled through and cached
+/// by a proxy repository. Files are fetched from the same location
+/// relative to the URL of the remote repository.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Upstream {
+    url: String,
+    username: Option<String>,
+    password: Option<EncryptedSecret>,
+    metadata_ttl: u64,
+    not_found_ttl: u64,
+}
+
+impl Upstream {
+    pub fn new(url: &str, metadata_ttl: u64, not_found_ttl: u64) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            username: None,
+            password: None,
+            metadata_ttl,
+            not_found_ttl,
+        }
+    }
+
+    pub fn with_credentials(mut self, username: String, password: String) -> Self {
+        self.username = Some(username);
+        self.password = Some(EncryptedSecret::new(password));
+        self
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+
+    pub fn password(&self) -> Option<&str> {
+        self.password.as_ref().map(EncryptedSecret::expose)
+    }
+
+    /// Seconds after which cached metadata is fetched again.
+    /// Artifacts of release versions never change, so they are cached forever.
+    pub fn metadata_ttl(&self) -> u64 {
+        self.metadata_ttl
+    }
+
+    /// Seconds during which files missing from the remote repository
+    /// are not looked up again.
+    pub fn not_found_ttl(&self) -> u64 {
+        self.not_found_ttl
+    }
+}
+
 impl Entity for Repo {
     fn build_guid(location: &str) -> Guid {
         Guid::partitioned("maven_repo", secure::base64::encode(location))
@@ -105,4 +290,64 @@ mod test {
         assert_eq!(format!("maven_repo:{}", loc_b64), id.to_string());
         assert_eq!("io/enseada/test/test-repo", location);
     }
+
+    #[test]
+    fn it_defaults_to_hosted() {
+        let json = serde_json::json!({
+            "_id": "maven_repo:aW8vZW5zZWFkYS90ZXN0L3Rlc3QtcmVwbw==",
+            "group_id": "io.enseada.test",
+            "artifact_id": "test-repo",
+            "decoded_location": "io/enseada/test/test-repo",
+            "public": true,
+            "files": [],
+        });
+        let repo: Repo = serde_json::from_value(json).unwrap();
+
+        assert_eq!(&RepoKind::Hosted, repo.kind());
+        assert!(!repo.is_proxy());
+    }
+
+    #[test]
+    fn it_stores_proxied_locations_under_the_proxy() {
+        let upstream = Upstream::new("https://repo.maven.apache.org/maven2/", 1800, 300);
+        let mut proxy = Repo::new("org.example", "central", true);
+        proxy.set_kind(RepoKind::Proxy(upstream));
+
+        let view = proxy.proxy_location("/org/example/foo.bar/").unwrap();
+        assert_eq!("org.example", view.group_id());
+        assert_eq!("foo.bar", view.artifact_id());
+        assert_eq!("org/example/foo.bar", view.location());
+        assert_eq!(Some("org/example/central"), view.proxied_by());
+        assert_eq!(
+            "org/example/central/~/org/example/foo.bar",
+            view.storage_location()
+        );
+        assert!(view.is_proxy());
+        assert!(serde_json::to_value(&view)
+            .unwrap()
+            .get("proxied_by")
+            .is_none());
+
+        assert_eq!("org/example/central", proxy.storage_location());
+        assert!(Repo::new("org.example", "foo", true)
+            .proxy_location("org/example/bar")
+            .is_none());
+    }
+
+    #[test]
+    fn it_serializes_proxies() {
+        let upstream = Upstream::new("https://repo.maven.apache.org/maven2/", 1800, 300);
+        let mut repo = Repo::new("io.enseada.test", "test-repo", true);
+        repo.set_kind(RepoKind::Proxy(upstream));
+
+        let json = serde_json::to_value(&repo).unwrap();
+        assert_eq!("proxy", json["kind"]["type"]);
+        assert_eq!("https://repo.maven.apache.org/maven2", json["kind"]["url"]);
+
+        let repo: Repo = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            Some("https://repo.maven.apache.org/maven2"),
+            repo.upstream().map(Upstream::url)
+        );
+    }
 }
@@ -0,0 +1,7 @@
+use events::Event;
+
+#[derive(Debug, Event)]
+pub struct FileStored {
+    pub location: String,
+    pub path: String,
+}
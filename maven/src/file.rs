@@ -25,11 +25,11 @@ impl<'a> File<'a> {
         }
     }
 
-    pub fn filename(&self) -> &str {
+    pub fn filename(&self) -> &'a str {
         self.filename
     }
 
-    pub fn version(&self) -> Option<&Version> {
+    pub fn version(&self) -> Option<&'a Version> {
         self.version
     }
 
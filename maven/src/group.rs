@@ -0,0 +1,214 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use enseada::couchdb::db::Database;
+use enseada::couchdb::repository::{Entity, Repository};
+use enseada::error::Error;
+use enseada::guid::Guid;
+
+use crate::Result;
+
+/// A named, ordered list of repositories served under a single URL,
+/// e.g. `/maven2/public/org/example/foo/1.0/foo-1.0.jar`.
+///
+/// Each member serves the location of its repository, and proxy members can
+/// cover every location under a prefix too. A file is served by the first
+/// member, in order, serving its location and having it, while the artifact
+/// metadata is merged across all of them. A hosted repository listed before
+/// a proxy covering its location thus takes precedence over the remote one.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Group {
+    #[serde(rename = "_id")]
+    id: Guid,
+    #[serde(rename = "_rev", skip_serializing_if = "Option::is_none")]
+    rev: Option<String>,
+    name: String,
+    members: Vec<Member>,
+}
+
+/// A repository of a group, along with the locations it serves in the group.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Member {
+    repository: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    prefix: Option<String>,
+}
+
+impl Member {
+    /// A member serving the location of its repository, e.g. `org/example/foo`.
+    pub fn new(repository: &str) -> Self {
+        Self {
+            repository: repository.trim_matches('/').to_string(),
+            prefix: None,
+        }
+    }
+
+    /// A proxy member serving every location under a prefix, e.g. `org/example`,
+    /// or all of them if the prefix is empty.
+    pub fn covering(repository: &str, prefix: &str) -> Self {
+        Self {
+            repository: repository.trim_matches('/').to_string(),
+            prefix: Some(prefix.trim_matches('/').to_string()),
+        }
+    }
+
+    /// The location of the repository.
+    pub fn repository(&self) -> &str {
+        &self.repository
+    }
+
+    pub fn prefix(&self) -> Option<&str> {
+        self.prefix.as_deref()
+    }
+
+    pub fn serves(&self, location: &str) -> bool {
+        let location = location.trim_matches('/');
+        match &self.prefix {
+            None => self.repository == location,
+            Some(prefix) => {
+                prefix.is_empty()
+                    || location == prefix
+                    || (location.starts_with(prefix.as_str())
+                        && location[prefix.len()..].starts_with('/'))
+            }
+        }
+    }
+}
+
+impl Group {
+    pub fn new(name: &str, members: Vec<Member>) -> Self {
+        Self {
+            id: Self::build_guid(name),
+            rev: None,
+            name: name.to_string(),
+            members,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn members(&self) -> &[Member] {
+        &self.members
+    }
+
+    pub fn set_members(&mut self, members: Vec<Member>) -> &mut Self {
+        self.members = members;
+        self
+    }
+
+    /// The members serving a location, in order.
+    pub fn members_at<'a>(&'a self, location: &'a str) -> impl Iterator<Item = &'a Member> + 'a {
+        self.members
+            .iter()
+            .filter(move |member| member.serves(location))
+    }
+
+    /// Splits a path under `/maven2` into the name of the group it may refer to
+    /// and the path of the file within the group.
+    pub fn split_path(path: &str) -> Option<(&str, &str)> {
+        let path = path.trim_start_matches('/');
+        let slash = path.find('/')?;
+        Some((&path[..slash], &path[slash + 1..]))
+    }
+}
+
+impl Entity for Group {
+    fn build_guid(name: &str) -> Guid {
+        Guid::partitioned("maven_group", name)
+    }
+
+    fn id(&self) -> &Guid {
+        &self.id
+    }
+
+    fn rev(&self) -> Option<&str> {
+        self.rev.as_deref()
+    }
+
+    fn set_rev(&mut self, rev: String) -> &mut Self {
+        self.rev = Some(rev);
+        self
+    }
+}
+
+#[derive(Debug)]
+pub struct GroupService {
+    db: Database,
+}
+
+impl GroupService {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    pub async fn find_group(&self, name: &str) -> Result<Option<Group>> {
+        self.find(name).await.map_err(Error::from)
+    }
+}
+
+#[async_trait]
+impl Repository<Group> for GroupService {
+    fn db(&self) -> &Database {
+        &self.db
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_splits_group_paths() {
+        assert_eq!(
+            Some(("public", "org/example/foo/1.0/foo-1.0.jar")),
+            Group::split_path("/public/org/example/foo/1.0/foo-1.0.jar")
+        );
+        assert_eq!(None, Group::split_path("public"));
+    }
+
+    #[test]
+    fn it_finds_members_at_a_location_in_order() {
+        let group = Group::new(
+            "public",
+            vec![
+                Member::new("org/example/foo"),
+                Member::new("org/example/bar"),
+                Member::covering("org/example/central", "org/example"),
+                Member::covering("/org/apache/central/", ""),
+            ],
+        );
+
+        let members: Vec<&str> = group
+            .members_at("org/example/bar")
+            .map(Member::repository)
+            .collect();
+        assert_eq!(
+            vec![
+                "org/example/bar",
+                "org/example/central",
+                "org/apache/central"
+            ],
+            members
+        );
+        let members: Vec<&str> = group
+            .members_at("org/examples/baz")
+            .map(Member::repository)
+            .collect();
+        assert_eq!(vec!["org/apache/central"], members);
+    }
+
+    #[test]
+    fn it_serves_locations_under_the_prefix_of_a_member() {
+        let member = Member::covering("org/example/central", "/org/example/");
+        assert!(member.serves("org/example"));
+        assert!(member.serves("org/example/foo"));
+        assert!(!member.serves("org/examples/foo"));
+        assert!(!member.serves("org"));
+
+        let member = Member::new("org/example/foo");
+        assert!(member.serves("/org/example/foo"));
+        assert!(!member.serves("org/example/foo/bar"));
+    }
+}
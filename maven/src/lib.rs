@@ -1,8 +1,14 @@
 use enseada::error::Error;
 pub use maven_version::*;
 
+pub mod checksum;
 pub mod entity;
+pub mod events;
 pub mod file;
+pub mod group;
+pub mod metadata;
+pub mod proxy;
+pub mod replication;
 pub mod service;
 mod storage;
 
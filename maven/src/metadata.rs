@@ -0,0 +1,782 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+
+use maven_version::Version;
+
+use crate::entity::{Repo, PROXIED_DIR};
+
+pub const METADATA_FILE: &str = "maven-metadata.xml";
+
+const CHECKSUM_EXTENSIONS: [&str; 4] = ["md5", "sha1", "sha256", "sha512"];
+
+/// Whether a file is a metadata file or one of its checksums.
+/// Metadata is generated by the server, so clients can't upload it.
+pub fn is_metadata(filename: &str) -> bool {
+    filename == METADATA_FILE || filename.starts_with(&format!("{}.", METADATA_FILE))
+}
+
+/// The artifact-level `maven-metadata.xml`, listing the versions of an artifact.
+#[derive(Debug)]
+pub struct Metadata {
+    group_id: String,
+    artifact_id: String,
+    versions: Vec<String>,
+    latest: Option<String>,
+    release: Option<String>,
+    last_updated: DateTime<Utc>,
+}
+
+impl Metadata {
+    /// Builds the metadata from the files stored in a repository.
+    /// Versions are sorted according to the Maven version ordering.
+    pub fn new(repo: &Repo, last_updated: DateTime<Utc>) -> Self {
+        let versions: Vec<(Version, String)> = versioned_files(repo)
+            .into_iter()
+            .map(|(version, filenames)| {
+                let display = display_version(repo.artifact_id(), &version, &filenames);
+                (version, display)
+            })
+            .collect();
+        let latest = versions.last().map(|(_, display)| display.clone());
+        let release = versions
+            .iter()
+            .rev()
+            .find(|(version, _)| !version.is_snapshot())
+            .map(|(_, display)| display.clone());
+        Self {
+            group_id: repo.group_id().to_string(),
+            artifact_id: repo.artifact_id().to_string(),
+            versions: versions.into_iter().map(|(_, display)| display).collect(),
+            latest,
+            release,
+            last_updated,
+        }
+    }
+
+    /// Reads the metadata of an artifact, e.g. as served by a remote repository.
+    pub fn parse(xml: &str) -> Option<Self> {
+        let versions = element(xml, "versions")
+            .map(|versions| elements(versions, "version"))
+            .unwrap_or_default();
+        let last_updated = element(xml, "lastUpdated")
+            .and_then(|value| NaiveDateTime::parse_from_str(value, "%Y%m%d%H%M%S").ok())
+            .map(|value| Utc.from_utc_datetime(&value))
+            .unwrap_or_else(|| DateTime::from(std::time::UNIX_EPOCH));
+        Some(Self {
+            group_id: unescape(element(xml, "groupId")?),
+            artifact_id: unescape(element(xml, "artifactId")?),
+            versions,
+            latest: element(xml, "latest").map(unescape),
+            release: element(xml, "release").map(unescape),
+            last_updated,
+        })
+    }
+
+    /// Merges the metadata of an artifact stored in several repositories.
+    /// Versions keep the spelling of the first metadata listing them, and
+    /// the latest and release versions are computed again from the merged ones.
+    pub fn merge<I: IntoIterator<Item = Metadata>>(metadata: I) -> Option<Self> {
+        let mut metadata = metadata.into_iter();
+        let mut merged = metadata.next()?;
+        let mut displays = std::mem::take(&mut merged.versions);
+        for other in metadata {
+            merged.last_updated = merged.last_updated.max(other.last_updated);
+            displays.extend(other.versions);
+        }
+
+        let mut versions: BTreeMap<Version, String> = BTreeMap::new();
+        for display in displays {
+            if let Ok(version) = Version::parse(&display) {
+                versions.entry(version).or_insert(display);
+            }
+        }
+        merged.latest = versions.values().last().cloned();
+        merged.release = versions
+            .iter()
+            .rev()
+            .find(|(version, _)| !version.is_snapshot())
+            .map(|(_, display)| display.clone());
+        merged.versions = versions.into_values().collect();
+        Some(merged)
+    }
+
+    pub fn versions(&self) -> &[String] {
+        &self.versions
+    }
+
+    pub fn latest(&self) -> Option<&str> {
+        self.latest.as_deref()
+    }
+
+    pub fn release(&self) -> Option<&str> {
+        self.release.as_deref()
+    }
+
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::new();
+        writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+        writeln!(xml, "<metadata>").unwrap();
+        write_element(&mut xml, 1, "groupId", &self.group_id);
+        write_element(&mut xml, 1, "artifactId", &self.artifact_id);
+        writeln!(xml, "  <versioning>").unwrap();
+        if let Some(latest) = &self.latest {
+            write_element(&mut xml, 2, "latest", latest);
+        }
+        if let Some(release) = &self.release {
+            write_element(&mut xml, 2, "release", release);
+        }
+        writeln!(xml, "    <versions>").unwrap();
+        for version in &self.versions {
+            write_element(&mut xml, 3, "version", version);
+        }
+        writeln!(xml, "    </versions>").unwrap();
+        write_element(
+            &mut xml,
+            2,
+            "lastUpdated",
+            &self.last_updated.format("%Y%m%d%H%M%S").to_string(),
+        );
+        writeln!(xml, "  </versioning>").unwrap();
+        writeln!(xml, "</metadata>").unwrap();
+        xml
+    }
+}
+
+/// The snapshot-level `maven-metadata.xml`, pointing to the latest build of a snapshot version.
+#[derive(Debug)]
+pub struct SnapshotMetadata {
+    group_id: String,
+    artifact_id: String,
+    version: String,
+    snapshot: Option<(String, u32)>,
+    snapshot_versions: Vec<SnapshotVersion>,
+    last_updated: DateTime<Utc>,
+}
+
+/// The latest build of a file of a snapshot version, by classifier and extension.
+#[derive(Debug)]
+pub struct SnapshotVersion {
+    classifier: Option<String>,
+    extension: String,
+    value: String,
+    updated: String,
+}
+
+impl SnapshotVersion {
+    pub fn classifier(&self) -> Option<&str> {
+        self.classifier.as_deref()
+    }
+
+    pub fn extension(&self) -> &str {
+        &self.extension
+    }
+
+    /// The version the file is stored with, e.g. `1.0-20201018.120000-3`.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+impl SnapshotMetadata {
+    /// Builds the metadata of a snapshot version from the timestamped files
+    /// stored in a repository.
+    pub fn new(repo: &Repo, version: &Version, last_updated: DateTime<Utc>) -> Self {
+        let filenames = versioned_files(repo).remove(version).unwrap_or_default();
+        let display = display_version(repo.artifact_id(), version, &filenames);
+        let base = snapshot_base(&display).to_string();
+        let builds: Vec<SnapshotFile> = filenames
+            .iter()
+            .filter_map(|filename| SnapshotFile::parse(repo.artifact_id(), version, filename))
+            .collect();
+
+        let snapshot = builds
+            .iter()
+            .max_by(|a, b| a.sort_key().cmp(&b.sort_key()))
+            .map(|build| (build.timestamp.clone(), build.build_number));
+
+        let mut latest: BTreeMap<(Option<String>, String), &SnapshotFile> = BTreeMap::new();
+        for build in &builds {
+            let key = (build.classifier.clone(), build.extension.clone());
+            match latest.get(&key) {
+                Some(current) if current.sort_key() >= build.sort_key() => {}
+                _ => {
+                    latest.insert(key, build);
+                }
+            }
+        }
+        let snapshot_versions = latest
+            .into_iter()
+            .map(|((classifier, extension), build)| SnapshotVersion {
+                classifier,
+                extension,
+                value: build.value(&base),
+                updated: build.timestamp.replace('.', ""),
+            })
+            .collect();
+
+        Self {
+            group_id: repo.group_id().to_string(),
+            artifact_id: repo.artifact_id().to_string(),
+            version: display,
+            snapshot,
+            snapshot_versions,
+            last_updated,
+        }
+    }
+
+    /// The timestamp and build number of the latest build, if any.
+    pub fn snapshot(&self) -> Option<(&str, u32)> {
+        self.snapshot
+            .as_ref()
+            .map(|(timestamp, build_number)| (timestamp.as_str(), *build_number))
+    }
+
+    pub fn snapshot_versions(&self) -> &[SnapshotVersion] {
+        &self.snapshot_versions
+    }
+
+    /// Resolves the non-unique name of a file, e.g. `foo-1.0-SNAPSHOT.jar`,
+    /// to the name of its latest build, e.g. `foo-1.0-20201018.120000-3.jar`.
+    pub fn resolve(&self, filename: &str) -> Option<String> {
+        let (classifier, extension) =
+            parse_snapshot_name(&self.artifact_id, &self.version, filename)?;
+        self.latest(classifier, extension)
+            .map(|latest| unique_name(&self.artifact_id, &latest.value, classifier, extension))
+    }
+
+    /// The unique name a file uploaded with its non-unique name is stored with.
+    /// Files of the same deploy share the latest build, while uploading
+    /// a file that the latest build already has starts a new build.
+    pub fn unique_name(&self, filename: &str, now: DateTime<Utc>) -> Option<String> {
+        let (classifier, extension) =
+            parse_snapshot_name(&self.artifact_id, &self.version, filename)?;
+        let base = snapshot_base(&self.version);
+        let next_build = |build_number: u32| {
+            format!("{}-{}-{}", base, now.format("%Y%m%d.%H%M%S"), build_number)
+        };
+        let value = match &self.snapshot {
+            Some((timestamp, build_number)) => {
+                let latest = format!("{}-{}-{}", base, timestamp, build_number);
+                let deployed = self.latest(classifier, extension);
+                if matches!(deployed, Some(version) if version.value == latest) {
+                    next_build(build_number + 1)
+                } else {
+                    latest
+                }
+            }
+            None => next_build(1),
+        };
+        Some(unique_name(
+            &self.artifact_id,
+            &value,
+            classifier,
+            extension,
+        ))
+    }
+
+    fn latest(&self, classifier: Option<&str>, extension: &str) -> Option<&SnapshotVersion> {
+        self.snapshot_versions.iter().find(|version| {
+            version.classifier.as_deref() == classifier && version.extension == extension
+        })
+    }
+
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::new();
+        writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+        writeln!(xml, r#"<metadata modelVersion="1.1.0">"#).unwrap();
+        write_element(&mut xml, 1, "groupId", &self.group_id);
+        write_element(&mut xml, 1, "artifactId", &self.artifact_id);
+        write_element(&mut xml, 1, "version", &self.version);
+        writeln!(xml, "  <versioning>").unwrap();
+        if let Some((timestamp, build_number)) = &self.snapshot {
+            writeln!(xml, "    <snapshot>").unwrap();
+            write_element(&mut xml, 3, "timestamp", timestamp);
+            write_element(&mut xml, 3, "buildNumber", &build_number.to_string());
+            writeln!(xml, "    </snapshot>").unwrap();
+        }
+        write_element(
+            &mut xml,
+            2,
+            "lastUpdated",
+            &self.last_updated.format("%Y%m%d%H%M%S").to_string(),
+        );
+        writeln!(xml, "    <snapshotVersions>").unwrap();
+        for snapshot_version in &self.snapshot_versions {
+            writeln!(xml, "      <snapshotVersion>").unwrap();
+            if let Some(classifier) = &snapshot_version.classifier {
+                write_element(&mut xml, 4, "classifier", classifier);
+            }
+            write_element(&mut xml, 4, "extension", &snapshot_version.extension);
+            write_element(&mut xml, 4, "value", &snapshot_version.value);
+            write_element(&mut xml, 4, "updated", &snapshot_version.updated);
+            writeln!(xml, "      </snapshotVersion>").unwrap();
+        }
+        writeln!(xml, "    </snapshotVersions>").unwrap();
+        writeln!(xml, "  </versioning>").unwrap();
+        writeln!(xml, "</metadata>").unwrap();
+        xml
+    }
+}
+
+/// A file of a snapshot version deployed with a unique version,
+/// e.g. `foo-1.0-20201018.120000-3-sources.jar`.
+#[derive(Debug, PartialEq)]
+pub struct SnapshotFile {
+    timestamp: String,
+    build_number: u32,
+    classifier: Option<String>,
+    extension: String,
+}
+
+impl SnapshotFile {
+    pub fn parse(artifact_id: &str, version: &Version, filename: &str) -> Option<Self> {
+        let version = version.to_string();
+        let base = version.strip_suffix("-snapshot")?;
+        let prefix = format!("{}-{}-", artifact_id, base);
+        if filename.len() <= prefix.len()
+            || !filename.is_char_boundary(prefix.len())
+            || !filename[..prefix.len()].eq_ignore_ascii_case(&prefix)
+        {
+            return None;
+        }
+        let rest = &filename[prefix.len()..];
+
+        let timestamp = rest.get(..15)?;
+        let (date, time) = timestamp.split_at(8);
+        let is_timestamp = date.chars().all(|c| c.is_ascii_digit())
+            && time.starts_with('.')
+            && time[1..].chars().all(|c| c.is_ascii_digit());
+        if !is_timestamp {
+            return None;
+        }
+
+        let rest = rest[15..].strip_prefix('-')?;
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let build_number = rest[..digits].parse().ok()?;
+        let rest = &rest[digits..];
+        let (classifier, extension) = match rest.strip_prefix('-') {
+            Some(rest) => {
+                let dot = rest.find('.')?;
+                (Some(rest[..dot].to_string()), &rest[dot + 1..])
+            }
+            None => (None, rest.strip_prefix('.')?),
+        };
+        let is_checksum = CHECKSUM_EXTENSIONS
+            .iter()
+            .any(|checksum| extension.rsplit('.').next() == Some(*checksum));
+        if extension.is_empty() || is_checksum || classifier.as_deref() == Some("") {
+            return None;
+        }
+
+        Some(Self {
+            timestamp: timestamp.to_string(),
+            build_number,
+            classifier,
+            extension: extension.to_string(),
+        })
+    }
+
+    pub fn timestamp(&self) -> &str {
+        &self.timestamp
+    }
+
+    pub fn build_number(&self) -> u32 {
+        self.build_number
+    }
+
+    pub fn classifier(&self) -> Option<&str> {
+        self.classifier.as_deref()
+    }
+
+    pub fn extension(&self) -> &str {
+        &self.extension
+    }
+
+    /// The unique version of the file, given the version without the `-SNAPSHOT` qualifier.
+    pub fn value(&self, base: &str) -> String {
+        format!("{}-{}-{}", base, self.timestamp, self.build_number)
+    }
+
+    fn sort_key(&self) -> (u32, &str) {
+        (self.build_number, &self.timestamp)
+    }
+}
+
+/// The files of the builds of a snapshot version older than the last `keep` ones,
+/// as paths relative to the repository.
+pub fn expired_snapshot_files(repo: &Repo, version: &Version, keep: usize) -> Vec<String> {
+    let filenames = versioned_files(repo).remove(version).unwrap_or_default();
+    let builds: Vec<(&str, SnapshotFile)> = filenames
+        .iter()
+        .filter_map(|filename| {
+            SnapshotFile::parse(repo.artifact_id(), version, filename)
+                .map(|build| (*filename, build))
+        })
+        .collect();
+
+    let mut kept: Vec<(u32, &str)> = builds.iter().map(|(_, build)| build.sort_key()).collect();
+    kept.sort_unstable();
+    kept.dedup();
+    let expired = &kept[..kept.len().saturating_sub(keep)];
+    builds
+        .iter()
+        .filter(|(_, build)| expired.contains(&build.sort_key()))
+        .map(|(filename, _)| format!("{}/{}", version, filename))
+        .collect()
+}
+
+/// Parses the non-unique name of a file of a snapshot version,
+/// e.g. `foo-1.0-SNAPSHOT-sources.jar`, into its classifier and extension.
+fn parse_snapshot_name<'f>(
+    artifact_id: &str,
+    version: &str,
+    filename: &'f str,
+) -> Option<(Option<&'f str>, &'f str)> {
+    let prefix = format!("{}-{}", artifact_id, version);
+    if filename.len() <= prefix.len()
+        || !filename.is_char_boundary(prefix.len())
+        || !filename[..prefix.len()].eq_ignore_ascii_case(&prefix)
+    {
+        return None;
+    }
+
+    let rest = &filename[prefix.len()..];
+    let (classifier, extension) = match rest.strip_prefix('-') {
+        Some(rest) => {
+            let dot = rest.find('.')?;
+            (Some(&rest[..dot]), &rest[dot + 1..])
+        }
+        None => (None, rest.strip_prefix('.')?),
+    };
+    if extension.is_empty() || classifier == Some("") {
+        return None;
+    }
+    Some((classifier, extension))
+}
+
+fn unique_name(
+    artifact_id: &str,
+    value: &str,
+    classifier: Option<&str>,
+    extension: &str,
+) -> String {
+    match classifier {
+        Some(classifier) => format!("{}-{}-{}.{}", artifact_id, value, classifier, extension),
+        None => format!("{}-{}.{}", artifact_id, value, extension),
+    }
+}
+
+/// The version of a snapshot without its qualifier, e.g. `1.0` for `1.0-SNAPSHOT`.
+fn snapshot_base(version: &str) -> &str {
+    let qualifier = "-snapshot".len();
+    if version.len() > qualifier
+        && version.is_char_boundary(version.len() - qualifier)
+        && version[version.len() - qualifier..].eq_ignore_ascii_case("-snapshot")
+    {
+        &version[..version.len() - qualifier]
+    } else {
+        version
+    }
+}
+
+/// Groups the files of a repository by version, skipping metadata files
+/// and the files a proxy repository caches for other locations.
+fn versioned_files(repo: &Repo) -> BTreeMap<Version, Vec<&str>> {
+    let mut versions: HashMap<&str, Vec<&str>> = HashMap::new();
+    for path in repo.files() {
+        if let Some(slash) = path.find('/') {
+            let (version, filename) = (&path[..slash], &path[slash + 1..]);
+            if version != PROXIED_DIR && !is_metadata(filename) {
+                versions.entry(version).or_default().push(filename);
+            }
+        }
+    }
+
+    versions
+        .into_iter()
+        .filter_map(|(version, mut filenames)| {
+            filenames.sort_unstable();
+            filenames.dedup();
+            Version::parse(version)
+                .ok()
+                .map(|version| (version, filenames))
+        })
+        .collect()
+}
+
+/// Versions are stored in lowercase, so the original spelling is recovered
+/// from the names of their files, e.g. `1.0.Final` from `foo-1.0.Final.jar`.
+/// Snapshots of unique versions use the conventional `-SNAPSHOT` qualifier.
+pub(crate) fn display_version(artifact_id: &str, version: &Version, filenames: &[&str]) -> String {
+    let value = version.to_string();
+    let prefix = format!("{}-", artifact_id);
+    filenames
+        .iter()
+        .filter_map(|filename| filename.strip_prefix(&prefix))
+        .filter_map(|rest| rest.get(..value.len()))
+        .find(|candidate| candidate.to_lowercase() == value)
+        .map(str::to_string)
+        .unwrap_or_else(|| match value.strip_suffix("-snapshot") {
+            Some(base) => format!("{}-SNAPSHOT", base),
+            None => value,
+        })
+}
+
+fn write_element(xml: &mut String, depth: usize, name: &str, value: &str) {
+    writeln!(
+        xml,
+        "{}<{}>{}</{}>",
+        "  ".repeat(depth),
+        name,
+        escape(value),
+        name
+    )
+    .unwrap();
+}
+
+/// The content of the first element with a name, for the few elements read from metadata.
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{}>", name);
+    let close = format!("</{}>", name);
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find(&close)?;
+    Some(xml[start..end].trim())
+}
+
+fn elements(xml: &str, name: &str) -> Vec<String> {
+    let close = format!("</{}>", name);
+    let mut values = Vec::new();
+    let mut rest = xml;
+    while let Some(value) = element(rest, name) {
+        values.push(unescape(value));
+        match rest.find(&close) {
+            Some(end) => rest = &rest[end + close.len()..],
+            None => break,
+        }
+    }
+    values
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn repo(files: &[&str]) -> Repo {
+        let mut repo = Repo::new("io.enseada", "test", true);
+        for file in files {
+            repo.add_file(file, 1);
+        }
+        repo
+    }
+
+    #[test]
+    fn it_recognizes_metadata_files() {
+        assert!(is_metadata("maven-metadata.xml"));
+        assert!(is_metadata("maven-metadata.xml.sha1"));
+        assert!(!is_metadata("maven-metadata.xmlx"));
+        assert!(!is_metadata("test-1.0.pom"));
+    }
+
+    #[test]
+    fn it_sorts_versions_by_maven_ordering() {
+        let repo = repo(&[
+            "1.9/test-1.9.jar",
+            "1.10/test-1.10.jar",
+            "1.10/test-1.10.pom",
+            "1.0.final/test-1.0.Final.jar",
+            "2.0-snapshot/test-2.0-20201018.120000-1.jar",
+            "2.0-snapshot/maven-metadata.xml",
+        ]);
+        let metadata = Metadata::new(&repo, Utc::now());
+
+        assert_eq!(
+            metadata.versions(),
+            ["1.0.Final", "1.9", "1.10", "2.0-SNAPSHOT"]
+        );
+        assert_eq!(metadata.latest(), Some("2.0-SNAPSHOT"));
+        assert_eq!(metadata.release(), Some("1.10"));
+        assert!(metadata.to_xml().contains("<version>1.10</version>"));
+    }
+
+    #[test]
+    fn it_merges_metadata_of_several_repositories() {
+        let hosted = Metadata::new(
+            &repo(&["1.1-snapshot/test-1.1-20201018.120000-1.jar"]),
+            Utc.ymd(2020, 10, 18).and_hms(12, 0, 0),
+        );
+        let remote = Metadata::parse(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<metadata>
+  <groupId>io.enseada</groupId>
+  <artifactId>test</artifactId>
+  <versioning>
+    <latest>1.0.Final</latest>
+    <release>1.0.Final</release>
+    <versions>
+      <version>0.9</version>
+      <version>1.0.Final</version>
+    </versions>
+    <lastUpdated>20201019080000</lastUpdated>
+  </versioning>
+</metadata>"#,
+        )
+        .unwrap();
+        assert_eq!(remote.versions(), ["0.9", "1.0.Final"]);
+
+        let merged = Metadata::merge(vec![hosted, remote]).unwrap();
+        assert_eq!(merged.versions(), ["0.9", "1.0.Final", "1.1-SNAPSHOT"]);
+        assert_eq!(merged.latest(), Some("1.1-SNAPSHOT"));
+        assert_eq!(merged.release(), Some("1.0.Final"));
+        assert!(merged
+            .to_xml()
+            .contains("<lastUpdated>20201019080000</lastUpdated>"));
+        assert!(Metadata::merge(Vec::new()).is_none());
+    }
+
+    #[test]
+    fn it_parses_snapshot_files() {
+        let version = Version::parse("1.0-SNAPSHOT").unwrap();
+        let file = SnapshotFile::parse("test", &version, "test-1.0-20201018.120000-3-sources.jar")
+            .unwrap();
+        assert_eq!(file.timestamp(), "20201018.120000");
+        assert_eq!(file.build_number(), 3);
+        assert_eq!(file.classifier(), Some("sources"));
+        assert_eq!(file.extension(), "jar");
+        assert_eq!(file.value("1.0"), "1.0-20201018.120000-3");
+
+        let file =
+            SnapshotFile::parse("test", &version, "test-1.0-20201018.120000-3.tar.gz").unwrap();
+        assert_eq!(file.classifier(), None);
+        assert_eq!(file.extension(), "tar.gz");
+
+        assert!(SnapshotFile::parse("test", &version, "test-1.0-SNAPSHOT.jar").is_none());
+        assert!(
+            SnapshotFile::parse("test", &version, "test-1.0-20201018.120000-3.jar.sha1").is_none()
+        );
+    }
+
+    #[test]
+    fn it_points_snapshot_metadata_to_the_latest_build() {
+        let repo = repo(&[
+            "1.0-snapshot/test-1.0-20201017.100000-1.jar",
+            "1.0-snapshot/test-1.0-20201017.100000-1.pom",
+            "1.0-snapshot/test-1.0-20201018.120000-2.jar",
+            "1.0-snapshot/test-1.0-20201018.120000-2.jar.sha1",
+            "1.0-snapshot/test-1.0-20201017.100000-1-sources.jar",
+        ]);
+        let version = Version::parse("1.0-SNAPSHOT").unwrap();
+        let metadata = SnapshotMetadata::new(&repo, &version, Utc::now());
+
+        assert_eq!(metadata.snapshot(), Some(("20201018.120000", 2)));
+        let values: Vec<(Option<&str>, &str, &str)> = metadata
+            .snapshot_versions()
+            .iter()
+            .map(|v| (v.classifier(), v.extension(), v.value()))
+            .collect();
+        assert_eq!(
+            values,
+            [
+                (None, "jar", "1.0-20201018.120000-2"),
+                (None, "pom", "1.0-20201017.100000-1"),
+                (Some("sources"), "jar", "1.0-20201017.100000-1"),
+            ]
+        );
+        assert!(metadata
+            .to_xml()
+            .contains("<version>1.0-SNAPSHOT</version>"));
+    }
+
+    #[test]
+    fn it_resolves_non_unique_snapshot_names() {
+        let repo = repo(&[
+            "1.0-snapshot/test-1.0-20201017.100000-1.jar",
+            "1.0-snapshot/test-1.0-20201018.120000-2.jar",
+            "1.0-snapshot/test-1.0-20201017.100000-1-sources.jar",
+        ]);
+        let version = Version::parse("1.0-SNAPSHOT").unwrap();
+        let metadata = SnapshotMetadata::new(&repo, &version, Utc::now());
+
+        assert_eq!(
+            metadata.resolve("test-1.0-SNAPSHOT.jar").as_deref(),
+            Some("test-1.0-20201018.120000-2.jar")
+        );
+        assert_eq!(
+            metadata.resolve("test-1.0-SNAPSHOT-sources.jar").as_deref(),
+            Some("test-1.0-20201017.100000-1-sources.jar")
+        );
+        assert_eq!(metadata.resolve("test-1.0-SNAPSHOT.pom"), None);
+        assert_eq!(metadata.resolve("test-1.0-20201018.120000-2.jar"), None);
+    }
+
+    #[test]
+    fn it_names_uploads_of_the_same_deploy_with_the_latest_build() {
+        let deployed = repo(&["1.0-snapshot/test-1.0-20201018.120000-2.pom"]);
+        let version = Version::parse("1.0-SNAPSHOT").unwrap();
+        let metadata = SnapshotMetadata::new(&deployed, &version, Utc::now());
+        let now = "2020-10-19T08:30:00Z".parse().unwrap();
+
+        assert_eq!(
+            metadata
+                .unique_name("test-1.0-SNAPSHOT.jar", now)
+                .as_deref(),
+            Some("test-1.0-20201018.120000-2.jar")
+        );
+        assert_eq!(
+            metadata
+                .unique_name("test-1.0-SNAPSHOT.pom", now)
+                .as_deref(),
+            Some("test-1.0-20201019.083000-3.pom")
+        );
+
+        let metadata = SnapshotMetadata::new(&repo(&[]), &version, Utc::now());
+        assert_eq!(
+            metadata
+                .unique_name("test-1.0-SNAPSHOT.pom", now)
+                .as_deref(),
+            Some("test-1.0-20201019.083000-1.pom")
+        );
+    }
+
+    #[test]
+    fn it_expires_old_snapshot_builds() {
+        let repo = repo(&[
+            "1.0-snapshot/test-1.0-20201016.100000-1.jar",
+            "1.0-snapshot/test-1.0-20201016.100000-1.pom",
+            "1.0-snapshot/test-1.0-20201017.100000-2.jar",
+            "1.0-snapshot/test-1.0-20201018.100000-3.jar",
+            "1.0/test-1.0.jar",
+        ]);
+        let version = Version::parse("1.0-SNAPSHOT").unwrap();
+
+        let mut expired = expired_snapshot_files(&repo, &version, 2);
+        expired.sort();
+        assert_eq!(
+            expired,
+            [
+                "1.0-snapshot/test-1.0-20201016.100000-1.jar",
+                "1.0-snapshot/test-1.0-20201016.100000-1.pom",
+            ]
+        );
+        assert!(expired_snapshot_files(&repo, &version, 3).is_empty());
+    }
+}
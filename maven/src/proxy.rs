@@ -0,0 +1,355 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, Utc};
+use futures::stream::BoxStream;
+use futures::{StreamExt, TryStreamExt};
+use reqwest::{Client, StatusCode};
+
+use enseada::error::Error;
+use enseada::storage::ByteChunk;
+use maven_version::Version;
+
+use crate::checksum::parse_checksum;
+use crate::entity::{Repo, Upstream};
+use crate::metadata::display_version;
+use crate::Result;
+
+/// How many URLs found missing, and metadata fetches, are remembered at most.
+const MAX_REMEMBERED: usize = 10_000;
+
+/// A file fetched from the remote repository of a proxy repository.
+pub struct RemoteFile {
+    pub size: Option<usize>,
+    pub content: BoxStream<'static, ByteChunk>,
+}
+
+/// Fetches the files missing from proxy repositories from their remote repository.
+///
+/// Files missing from the remote repository are remembered until the not found TTL
+/// of the upstream expires, so that lookups of files that don't exist, e.g. optional
+/// classifiers, are not sent to the remote repository every time. The time metadata
+/// was last fetched is kept as well, to revalidate it once its TTL expires.
+///
+/// Both are kept in memory, up to [`MAX_REMEMBERED`] entries each, by each instance
+/// of the client: they are not shared between workers and they are lost on restart,
+/// after which missing files are looked up and metadata revalidated once again.
+#[derive(Debug, Default)]
+pub struct RemoteClient {
+    client: Client,
+    misses: Timestamps,
+    fetched: Timestamps,
+}
+
+impl RemoteClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fetches a file from a remote repository. The path is relative to its URL.
+    /// Returns `None` if the file is missing, or was recently found missing.
+    pub async fn fetch(&self, upstream: &Upstream, path: &str) -> Result<Option<RemoteFile>> {
+        let url = format!("{}/{}", upstream.url(), path);
+        if self.is_missing(&url, upstream.not_found_ttl()) {
+            log::debug!("{} was recently not found, skipping lookup", &url);
+            return Ok(None);
+        }
+
+        log::debug!("fetching {} from remote repository", &url);
+        let mut req = self.client.get(&url);
+        if let Some(username) = upstream.username() {
+            req = req.basic_auth(username, upstream.password());
+        }
+        let res = req
+            .send()
+            .await
+            .map_err(|err| Error::new(&err.to_string()))?;
+
+        match res.status() {
+            StatusCode::NOT_FOUND | StatusCode::GONE => {
+                log::debug!("{} not found on remote repository", &url);
+                self.misses.record(url, upstream.not_found_ttl());
+                Ok(None)
+            }
+            status if !status.is_success() => Err(Error::new(&format!(
+                "remote repository returned {} for {}",
+                status, url
+            ))),
+            _ => {
+                self.misses.forget(&url);
+                let size = res.content_length().map(|size| size as usize);
+                let content = res.bytes_stream().map_err(io::Error::other);
+                Ok(Some(RemoteFile {
+                    size,
+                    content: content.boxed(),
+                }))
+            }
+        }
+    }
+
+    /// Fetches the checksum file of a file, if the remote repository has one.
+    pub async fn fetch_checksum(&self, upstream: &Upstream, path: &str) -> Result<Option<String>> {
+        let file = match self.fetch(upstream, path).await? {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        let content: Vec<u8> = file
+            .content
+            .map_ok(|chunk| chunk.to_vec())
+            .try_concat()
+            .await
+            .map_err(|err| Error::new(&err.to_string()))?;
+        Ok(parse_checksum(&content))
+    }
+
+    /// Tells whether the metadata stored under a key was fetched within its TTL.
+    pub fn is_fresh(&self, key: &str, ttl: u64) -> bool {
+        self.fetched.is_within(key, ttl)
+    }
+
+    /// Records a fetch of the metadata stored under a key, whose TTL is `ttl`.
+    pub fn record_fetch(&self, key: &str, ttl: u64) {
+        self.fetched.record(key.to_string(), ttl);
+    }
+
+    fn is_missing(&self, url: &str, ttl: u64) -> bool {
+        self.misses.is_within(url, ttl)
+    }
+}
+
+/// The times keys were recorded at, along with the TTL they were recorded with.
+/// Once [`MAX_REMEMBERED`] keys are recorded, the expired ones are evicted,
+/// then the oldest ones.
+#[derive(Debug)]
+struct Timestamps {
+    entries: Mutex<HashMap<String, (DateTime<Utc>, u64)>>,
+    capacity: usize,
+}
+
+impl Default for Timestamps {
+    fn default() -> Self {
+        Self::with_capacity(MAX_REMEMBERED)
+    }
+}
+
+impl Timestamps {
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            capacity,
+        }
+    }
+
+    fn record(&self, key: String, ttl: u64) {
+        let now = Utc::now();
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            entries.retain(|_, (recorded_at, ttl)| !is_expired(recorded_at, *ttl, &now));
+            if entries.len() >= self.capacity {
+                let oldest = entries
+                    .iter()
+                    .min_by_key(|(_, (recorded_at, _))| *recorded_at)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    entries.remove(&oldest);
+                }
+            }
+        }
+        entries.insert(key, (now, ttl));
+    }
+
+    /// Tells whether a key was recorded within `ttl`, forgetting it otherwise.
+    fn is_within(&self, key: &str, ttl: u64) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some((recorded_at, _)) if !is_expired(recorded_at, ttl, &Utc::now()) => true,
+            Some(_) => {
+                entries.remove(key);
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn forget(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+}
+
+fn is_expired(recorded_at: &DateTime<Utc>, ttl: u64, now: &DateTime<Utc>) -> bool {
+    *recorded_at + Duration::seconds(ttl as i64) <= *now
+}
+
+/// The path of a file relative to the URL of the remote repository. Versions are
+/// stored lowercase, so they are spelled as in the filename, e.g. `1.0-SNAPSHOT`.
+pub fn remote_path(repo: &Repo, version: Option<&Version>, filename: &str) -> String {
+    match version {
+        Some(version) => format!(
+            "{}/{}/{}",
+            repo.location(),
+            display_version(repo.artifact_id(), version, &[filename]),
+            filename
+        ),
+        None => format!("{}/{}", repo.location(), filename),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+
+    /// Serves the given files over HTTP, counting the requests received.
+    fn remote(files: Vec<(&'static str, &'static str)>) -> (Upstream, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/maven2", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request_line = String::new();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                reader.read_line(&mut request_line).unwrap();
+                let mut header = String::new();
+                while reader.read_line(&mut header).unwrap() > 2 {
+                    header.clear();
+                }
+                counter.fetch_add(1, Ordering::SeqCst);
+
+                let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+                let response = match files.iter().find(|(p, _)| *p == path) {
+                    Some((_, body)) => format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    ),
+                    None => {
+                        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                            .to_string()
+                    }
+                };
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (Upstream::new(&url, 60, 60), requests)
+    }
+
+    async fn read(file: RemoteFile) -> String {
+        let content: Vec<u8> = file
+            .content
+            .map_ok(|chunk| chunk.to_vec())
+            .try_concat()
+            .await
+            .unwrap();
+        String::from_utf8(content).unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_fetches_remote_files() {
+        let (upstream, _) = remote(vec![
+            ("/maven2/org/example/test/1.0/test-1.0.jar", "test jar"),
+            (
+                "/maven2/org/example/test/1.0/test-1.0.jar.sha1",
+                "0f4a2f1dbf7ab3a9d4b7e7c1a2d6c4fd1c8a7b1e  test-1.0.jar\n",
+            ),
+        ]);
+        let client = RemoteClient::new();
+
+        let file = client
+            .fetch(&upstream, "org/example/test/1.0/test-1.0.jar")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(Some(8), file.size);
+        assert_eq!("test jar", read(file).await);
+
+        let checksum = client
+            .fetch_checksum(&upstream, "org/example/test/1.0/test-1.0.jar.sha1")
+            .await
+            .unwrap();
+        assert_eq!(
+            Some("0f4a2f1dbf7ab3a9d4b7e7c1a2d6c4fd1c8a7b1e".to_string()),
+            checksum
+        );
+    }
+
+    #[tokio::test]
+    async fn it_caches_missing_files() {
+        let (upstream, requests) = remote(vec![]);
+        let client = RemoteClient::new();
+        let path = "org/example/test/1.0/test-1.0-sources.jar";
+
+        assert!(client.fetch(&upstream, path).await.unwrap().is_none());
+        assert!(client.fetch(&upstream, path).await.unwrap().is_none());
+        assert_eq!(1, requests.load(Ordering::SeqCst));
+
+        let expired = Upstream::new(upstream.url(), 60, 0);
+        assert!(client.fetch(&expired, path).await.unwrap().is_none());
+        assert_eq!(2, requests.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn it_spells_remote_versions_as_filenames() {
+        let repo = Repo::new("org.example", "test", true);
+        let version = Version::parse("1.0.Final").unwrap();
+        assert_eq!(
+            "org/example/test/1.0.Final/test-1.0.Final.jar",
+            remote_path(&repo, Some(&version), "test-1.0.Final.jar")
+        );
+
+        let version = Version::parse("1.0-SNAPSHOT").unwrap();
+        assert_eq!(
+            "org/example/test/1.0-SNAPSHOT/maven-metadata.xml",
+            remote_path(&repo, Some(&version), "maven-metadata.xml")
+        );
+        assert_eq!(
+            "org/example/test/maven-metadata.xml",
+            remote_path(&repo, None, "maven-metadata.xml")
+        );
+    }
+
+    #[test]
+    fn it_expires_fetched_metadata() {
+        let client = RemoteClient::new();
+        let key = "artifacts/maven/org/example/test/maven-metadata.xml";
+        assert!(!client.is_fresh(key, 60));
+
+        client.record_fetch(key, 60);
+        assert!(client.is_fresh(key, 60));
+        assert!(!client.is_fresh(key, 0));
+    }
+
+    #[test]
+    fn it_evicts_expired_then_oldest_timestamps() {
+        let timestamps = Timestamps::with_capacity(2);
+        {
+            let mut entries = timestamps.entries.lock().unwrap();
+            entries.insert(
+                "expired".to_string(),
+                (Utc::now() - Duration::minutes(2), 60),
+            );
+            entries.insert("recent".to_string(), (Utc::now(), 60));
+        }
+        timestamps.record("old".to_string(), 60);
+        assert!(!timestamps.is_within("expired", 60));
+        assert!(timestamps.is_within("old", 60));
+
+        timestamps
+            .entries
+            .lock()
+            .unwrap()
+            .insert("old".to_string(), (Utc::now() - Duration::seconds(30), 60));
+        timestamps.record("new".to_string(), 60);
+        assert_eq!(2, timestamps.entries.lock().unwrap().len());
+        assert!(!timestamps.is_within("old", 60));
+        assert!(timestamps.is_within("recent", 60));
+        assert!(timestamps.is_within("new", 60));
+    }
+}
@@ -0,0 +1,256 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use glob::Pattern;
+use reqwest::header::CONTENT_LENGTH;
+use reqwest::{Body, Client, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use enseada::couchdb::db::Database;
+use enseada::couchdb::repository::{Entity, Repository};
+use enseada::error::Error;
+use enseada::guid::Guid;
+use enseada::replication::{Remote, ReplicationStatus};
+use enseada::retry::{retry, Backoff};
+use enseada::storage::Provider;
+use events::EventHandler;
+
+use crate::events::FileStored;
+use crate::storage;
+use crate::Result;
+
+/// A rule copying the files stored in the matching repositories
+/// to a remote Enseada instance.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ReplicationRule {
+    #[serde(rename = "_id")]
+    id: Guid,
+    #[serde(rename = "_rev", skip_serializing_if = "Option::is_none")]
+    rev: Option<String>,
+    name: String,
+    repositories: Vec<String>,
+    target: Remote,
+    enabled: bool,
+    #[serde(default)]
+    status: ReplicationStatus,
+}
+
+impl ReplicationRule {
+    /// Creates an enabled rule. Repositories are selected by glob patterns
+    /// on their location, e.g. `org/example/*`.
+    pub fn new(name: &str, repositories: Vec<String>, target: Remote) -> Self {
+        Self {
+            id: Self::build_guid(name),
+            rev: None,
+            name: name.to_string(),
+            repositories,
+            target,
+            enabled: true,
+            status: ReplicationStatus::default(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn repositories(&self) -> &[String] {
+        &self.repositories
+    }
+
+    pub fn set_repositories(&mut self, repositories: Vec<String>) -> &mut Self {
+        self.repositories = repositories;
+        self
+    }
+
+    pub fn target(&self) -> &Remote {
+        &self.target
+    }
+
+    pub fn set_target(&mut self, target: Remote) -> &mut Self {
+        self.target = target;
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) -> &mut Self {
+        self.enabled = enabled;
+        self
+    }
+
+    pub fn status(&self) -> &ReplicationStatus {
+        &self.status
+    }
+
+    pub fn matches(&self, location: &str) -> bool {
+        self.repositories.iter().any(|pattern| {
+            Pattern::new(pattern)
+                .map(|pattern| pattern.matches(location))
+                .unwrap_or(false)
+        })
+    }
+}
+
+impl Entity for ReplicationRule {
+    fn build_guid(id: &str) -> Guid {
+        Guid::partitioned("maven_replication", id)
+    }
+
+    fn id(&self) -> &Guid {
+        &self.id
+    }
+
+    fn rev(&self) -> Option<&str> {
+        self.rev.as_deref()
+    }
+
+    fn set_rev(&mut self, rev: String) -> &mut Self {
+        self.rev = Some(rev);
+        self
+    }
+}
+
+/// Replicates stored files according to the enabled replication rules,
+/// uploading them to the `/maven2` endpoint of the target.
+/// Failed replications are retried with backoff, and their outcome is
+/// recorded in the status of the rule.
+#[derive(Debug)]
+pub struct ReplicationService {
+    db: Database,
+    store: Arc<Provider>,
+    client: Client,
+    backoff: Backoff,
+}
+
+impl ReplicationService {
+    pub fn new(db: Database, store: Arc<Provider>) -> Self {
+        Self {
+            db,
+            store,
+            client: Client::new(),
+            backoff: Backoff::default(),
+        }
+    }
+
+    pub async fn find_rule(&self, name: &str) -> Result<Option<ReplicationRule>> {
+        self.find(name).await.map_err(Error::from)
+    }
+
+    /// Uploads a file of a repository to the target of a rule.
+    pub async fn replicate(
+        &self,
+        rule: &ReplicationRule,
+        location: &str,
+        path: &str,
+    ) -> Result<()> {
+        let key = storage::file_key(location, path);
+        let blob = self
+            .store
+            .get_blob(&key)
+            .await?
+            .ok_or_else(|| Error::not_found("Maven file", path))?;
+
+        let target = rule.target();
+        let url = format!("{}/maven2/{}/{}", target.url(), location, path);
+        log::info!("replicating {} to {}", &key, &url);
+        let mut req = self
+            .client
+            .put(&url)
+            .header(CONTENT_LENGTH, blob.size())
+            .body(Body::wrap_stream(blob.into_byte_stream()));
+        if let Some(username) = target.username() {
+            req = req.basic_auth(username, target.password());
+        }
+
+        let res = req
+            .send()
+            .await
+            .map_err(|err| Error::new(&err.to_string()))?;
+        if res.status() == StatusCode::CONFLICT {
+            log::debug!("{} is immutable and already present on the remote", &url);
+            return Ok(());
+        }
+        if !res.status().is_success() {
+            return Err(Error::new(&format!(
+                "remote instance returned {} for {}",
+                res.status(),
+                url
+            )));
+        }
+        Ok(())
+    }
+
+    async fn record(&self, rule: &ReplicationRule, artifact: String, outcome: Result<()>) {
+        let mut rule = match self.find_rule(rule.name()).await {
+            Ok(Some(rule)) => rule,
+            Ok(None) => return,
+            Err(err) => {
+                log::error!("failed to fetch replication rule {}: {}", rule.name(), err);
+                return;
+            }
+        };
+        match outcome {
+            Ok(()) => rule.status.record_success(artifact),
+            Err(err) => {
+                log::error!("replication of {} failed: {}", &artifact, err);
+                rule.status.record_failure(artifact, err.to_string())
+            }
+        };
+        if let Err(err) = self.save(rule).await {
+            log::error!("failed to save replication status: {}", err);
+        }
+    }
+}
+
+#[async_trait]
+impl Repository<ReplicationRule> for ReplicationService {
+    fn db(&self) -> &Database {
+        &self.db
+    }
+}
+
+#[async_trait]
+impl EventHandler<FileStored> for ReplicationService {
+    async fn handle(&self, event: &FileStored) {
+        let rules: Vec<ReplicationRule> = match self
+            .find_all_stream(serde_json::json!({ "enabled": true }))
+            .try_collect()
+            .await
+        {
+            Ok(rules) => rules,
+            Err(err) => {
+                log::error!("failed to list replication rules: {}", err);
+                return;
+            }
+        };
+
+        let artifact = format!("{}/{}", &event.location, &event.path);
+        for rule in rules.iter().filter(|rule| rule.matches(&event.location)) {
+            let outcome = retry(&self.backoff, || {
+                self.replicate(rule, &event.location, &event.path)
+            })
+            .await;
+            self.record(rule, artifact.clone(), outcome).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_matches_repositories_by_location() {
+        let rule = ReplicationRule::new(
+            "dr",
+            vec!["org/example/*".to_string()],
+            Remote::new("https://dr.example.com"),
+        );
+        assert!(rule.matches("org/example/lib"));
+        assert!(!rule.matches("com/other/lib"));
+    }
+}
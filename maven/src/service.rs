@@ -1,30 +1,57 @@
-use std::sync::{Arc, RwLock};
+use std::future::Future;
+use std::sync::{Arc, Mutex, RwLock};
 
 use async_trait::async_trait;
+use chrono::Utc;
+use futures::channel::mpsc;
+use futures::stream::BoxStream;
+use futures::{future, stream, SinkExt, StreamExt, TryStreamExt};
 
 use enseada::couchdb::db::Database;
-use enseada::couchdb::repository::Repository;
+use enseada::couchdb::repository::{Entity, Repository};
 use enseada::error::Error;
+use enseada::quota::QuotaStatus;
 use enseada::storage::blob::Blob;
-use enseada::storage::Provider;
+use enseada::storage::{ByteChunk, Bytes, BytesMut, Provider};
 use events::EventBus;
 use maven_version::Version;
+use reqwest::StatusCode;
 
-use crate::entity::Repo;
+use crate::checksum::{checksum_file, ChecksumAlgorithm, ChecksumHasher, Checksums};
+use crate::entity::{Repo, Upstream, PROXIED_DIR};
+use crate::events::FileStored;
 use crate::file::File;
+use crate::metadata::{
+    expired_snapshot_files, is_metadata, Metadata, SnapshotMetadata, METADATA_FILE,
+};
+use crate::proxy::{remote_path, RemoteClient};
 use crate::storage;
 use crate::Result;
+use uuid::Uuid;
+
+/// How many chunks of a remote file are buffered while they are stored.
+const CACHE_BUFFER_SIZE: usize = 16;
+
+/// How many times a change to a repository is applied again after
+/// conflicting with a concurrent one.
+const MAX_SAVE_ATTEMPTS: usize = 10;
 
 #[derive(Debug)]
 pub struct RepoService {
     db: Database,
     bus: Arc<RwLock<EventBus>>,
     store: Arc<Provider>,
+    remote: RemoteClient,
 }
 
 impl RepoService {
     pub fn new(db: Database, bus: Arc<RwLock<EventBus>>, store: Arc<Provider>) -> Self {
-        Self { db, bus, store }
+        Self {
+            db,
+            bus,
+            store,
+            remote: RemoteClient::new(),
+        }
     }
 
     pub async fn find_by_location(&self, location: &str) -> Result<Option<Repo>> {
@@ -36,13 +63,22 @@ impl RepoService {
         .map_err(Error::from)
     }
 
+    /// The key a file of a repository is stored under.
+    pub fn file_key(&self, repo: &Repo, version: Option<&Version>, filename: &str) -> String {
+        let location = repo.storage_location();
+        match version {
+            Some(version) => storage::versioned_file_key(&location, version, filename),
+            None => storage::file_key(&location, filename),
+        }
+    }
+
     pub async fn is_file_present(
         &self,
         repo: &Repo,
-        version: &Version,
+        version: Option<&Version>,
         filename: &str,
     ) -> Result<bool> {
-        let key = storage::versioned_file_key(repo.location(), version, filename);
+        let key = self.file_key(repo, version, filename);
         self.store.is_blob_present(&key).await.map_err(Error::from)
     }
 
@@ -52,10 +88,10 @@ impl RepoService {
         version: Option<&'f Version>,
         filename: &'f str,
     ) -> Result<File<'f>> {
-        let key = match version {
-            Some(version) => storage::versioned_file_key(repo.location(), version, filename),
-            None => storage::file_key(repo.location(), filename),
-        };
+        let key = self.file_key(repo, version, filename);
+        if let Some(upstream) = repo.upstream() {
+            self.pull_file(repo, upstream, version, filename).await?;
+        }
 
         match self.store.get_blob(&key).await? {
             Some(blob) => Ok(File::new(
@@ -68,23 +104,410 @@ impl RepoService {
         }
     }
 
-    pub async fn store_file<'a, 'f>(&'f self, mut repo: Repo, file: File<'f>) -> Result<Repo> {
-        let filename = file.filename();
-        let key = match file.version() {
-            Some(version) => storage::versioned_file_key(repo.location(), version, filename),
-            None => storage::file_key(repo.location(), filename),
+    /// Reads the artifact metadata of a repository, if any file was stored in it.
+    pub async fn get_metadata(&self, repo: &Repo) -> Result<Option<Metadata>> {
+        match self.get_file(repo, None, METADATA_FILE).await {
+            Ok(file) => {
+                let content = read_stream(file.into_byte_stream().boxed()).await?;
+                Ok(Metadata::parse(&String::from_utf8_lossy(&content)))
+            }
+            Err(Error::NotFound { .. }) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Caches a file of a proxy repository if it's missing, or if it's metadata
+    /// older than its TTL. If the remote repository is unreachable,
+    /// stale metadata is served anyway.
+    async fn pull_file(
+        &self,
+        repo: &Repo,
+        upstream: &Upstream,
+        version: Option<&Version>,
+        filename: &str,
+    ) -> Result<()> {
+        let key = self.file_key(repo, version, filename);
+        let cached = self.store.is_blob_present(&key).await?;
+        if cached && (!is_metadata(filename) || self.remote.is_fresh(&key, upstream.metadata_ttl()))
+        {
+            return Ok(());
+        }
+
+        match self.cache_file(repo, upstream, version, filename).await {
+            Err(err) if cached => {
+                log::warn!(
+                    "failed to revalidate {}, serving it from cache: {}",
+                    &key,
+                    err
+                );
+                Ok(())
+            }
+            res => res,
+        }
+    }
+
+    /// Fetches a file from the remote repository of a proxy repository and stores it,
+    /// along with its checksums. The SHA-1 checksum published by the remote
+    /// repository, if any, is verified before the file replaces the cached copy.
+    async fn cache_file(
+        &self,
+        repo: &Repo,
+        upstream: &Upstream,
+        version: Option<&Version>,
+        filename: &str,
+    ) -> Result<()> {
+        let path = remote_path(repo, version, filename);
+        let remote = match self.remote.fetch(upstream, &path).await? {
+            Some(remote) => remote,
+            None => return Ok(()),
+        };
+        let expected = self
+            .remote
+            .fetch_checksum(upstream, &checksum_file(&path, ChecksumAlgorithm::Sha1))
+            .await?;
+
+        let key = self.file_key(repo, version, filename);
+        let staging_key = storage::staging_key(&Uuid::new_v4().to_string());
+        let hasher = Arc::new(Mutex::new(ChecksumHasher::new()));
+        let stream_hasher = hasher.clone();
+        let content = remote.content.inspect(move |chunk| {
+            if let Ok(bytes) = chunk {
+                stream_hasher.lock().unwrap().update(bytes);
+            }
+        });
+        let size = match remote.size {
+            Some(size) => {
+                // the remote stream is not Sync, so it is piped into storage through a channel
+                let (mut tx, rx) = mpsc::channel(CACHE_BUFFER_SIZE);
+                let mut content = content.map(Ok);
+                let forward = async move { tx.send_all(&mut content).await };
+                let store = self.store.store_blob(Blob::new(&staging_key, size, rx));
+                let (forwarded, stored) = future::join(forward, store).await;
+                let stored = forwarded
+                    .map_err(|err| Error::new(&err.to_string()))
+                    .and(stored.map_err(Error::from));
+                if let Err(err) = stored {
+                    self.discard_staged(&staging_key).await;
+                    return Err(err);
+                }
+                size
+            }
+            None => {
+                let content = read_stream(content.boxed()).await?;
+                let size = content.len();
+                let blob = Blob::new(&staging_key, size, stream::once(future::ok(content)));
+                if let Err(err) = self.store.store_blob(blob).await {
+                    self.discard_staged(&staging_key).await;
+                    return Err(err.into());
+                }
+                size
+            }
+        };
+
+        let checksums = hasher.lock().unwrap().clone().finish();
+        if let Some(expected) = expected {
+            let actual = checksums.get(ChecksumAlgorithm::Sha1).unwrap_or_default();
+            if !actual.eq_ignore_ascii_case(&expected) {
+                self.discard_staged(&staging_key).await;
+                return Err(Error::new(&format!(
+                    "{} does not match the checksum of the remote repository",
+                    &path
+                )));
+            }
+        }
+        if let Err(err) = self.store.move_blob(&staging_key, &key).await {
+            self.discard_staged(&staging_key).await;
+            return Err(err.into());
+        }
+        self.store_checksums(repo, version, filename, &checksums)
+            .await?;
+
+        if is_metadata(filename) {
+            self.remote.record_fetch(&key, upstream.metadata_ttl());
+        } else {
+            self.record_cached_file(repo, file_path(version, filename), size as u64)
+                .await;
+        }
+        Ok(())
+    }
+
+    async fn discard_staged(&self, staging_key: &str) {
+        if let Err(err) = self.store.delete_blob(staging_key).await {
+            log::warn!("failed to delete staged file {}: {}", staging_key, err);
+        }
+    }
+
+    /// Adds a cached file to its proxy repository, so that it's listed and
+    /// accounted for. The files of other locations served by the proxy are
+    /// listed under its [`PROXIED_DIR`]. The file is served from cache even if this fails.
+    async fn record_cached_file(&self, repo: &Repo, path: String, size: u64) {
+        let (location, path) = match repo.proxied_by() {
+            Some(proxy) => (
+                proxy,
+                format!("{}/{}/{}", PROXIED_DIR, repo.location(), path),
+            ),
+            None => (repo.location(), path),
+        };
+        let repo = match self.find_by_location(location).await {
+            Ok(Some(repo)) => repo,
+            Ok(None) => return,
+            Err(err) => {
+                log::error!("failed to fetch repository {}: {}", location, err);
+                return;
+            }
+        };
+        let saved = self
+            .save_change(repo, |repo| {
+                repo.add_file(path.clone(), size);
+            })
+            .await;
+        if let Err(err) = saved {
+            log::error!("failed to record cached file: {}", err);
+        }
+    }
+
+    /// Applies a change to a repository and saves it, without overwriting
+    /// the changes saved concurrently. Returns the saved repository, along
+    /// with the outcome of the change.
+    async fn save_change<T, C>(&self, repo: Repo, change: C) -> Result<(Repo, T)>
+    where
+        C: Fn(&mut Repo) -> T,
+    {
+        let location = repo.location().to_string();
+        save_change(
+            repo,
+            change,
+            || self.load_repo(&location),
+            |repo| self.put_repo(repo),
+        )
+        .await
+    }
+
+    async fn load_repo(&self, location: &str) -> Result<Repo> {
+        self.find(location)
+            .await?
+            .ok_or_else(|| Error::not_found("Maven repository", location))
+    }
+
+    /// Saves a repository with the revision it was loaded with.
+    /// Returns `None` if it was changed since.
+    async fn put_repo(&self, mut repo: Repo) -> Result<Option<Repo>> {
+        match self.db.put(repo.id().to_string(), &repo).await {
+            Ok(res) => {
+                repo.set_rev(res.rev);
+                Ok(Some(repo))
+            }
+            Err(err) if err.status() == StatusCode::CONFLICT => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Resolves the non-unique name of a file of a snapshot version to the name
+    /// of its latest build. Other names are returned as they are. Files of
+    /// proxy repositories are resolved by the remote repository.
+    pub fn resolve_filename(
+        &self,
+        repo: &Repo,
+        version: Option<&Version>,
+        filename: &str,
+    ) -> String {
+        version
+            .filter(|version| version.is_snapshot() && !repo.is_proxy())
+            .and_then(|version| SnapshotMetadata::new(repo, version, Utc::now()).resolve(filename))
+            .unwrap_or_else(|| filename.to_string())
+    }
+
+    /// Stores a file in a repository. Files of snapshot versions uploaded
+    /// with their non-unique name are stored with a timestamp and build number.
+    pub async fn store_file<'a, 'f>(&'f self, repo: Repo, file: File<'f>) -> Result<Repo> {
+        let now = Utc::now();
+        let filename = match file.version().filter(|version| version.is_snapshot()) {
+            Some(version) => SnapshotMetadata::new(&repo, version, now)
+                .unique_name(file.filename(), now)
+                .unwrap_or_else(|| file.filename().to_string()),
+            None => file.filename().to_string(),
+        };
+        let filename = filename.as_str();
+        let key = self.file_key(&repo, file.version(), filename);
+        let file_path = file_path(file.version(), filename);
+        let size = file.size() as u64;
+        let usage = repo.usage() - repo.file_size(&file_path).unwrap_or(0) + size;
+        match repo.quota().status(usage) {
+            QuotaStatus::HardExceeded => {
+                return Err(Error::quota_exceeded(format!(
+                    "storing {} would exceed the storage quota of repository {}",
+                    file_path,
+                    repo.location()
+                )));
+            }
+            QuotaStatus::SoftExceeded => log::warn!(
+                "repository {} exceeds its soft storage quota, using {} bytes",
+                repo.location(),
+                usage
+            ),
+            QuotaStatus::Within => {}
+        }
+
+        let version = file.version();
+        let hasher = Arc::new(Mutex::new(ChecksumHasher::new()));
+        let stream_hasher = hasher.clone();
+        let content = file.into_byte_stream().inspect(move |chunk| {
+            if let Ok(bytes) = chunk {
+                stream_hasher.lock().unwrap().update(bytes);
+            }
+        });
+        let blob = Blob::new(key, size as usize, content);
+        self.store.store_blob(blob).await?;
+        let checksums = hasher.lock().unwrap().clone().finish();
+        self.store_checksums(&repo, version, filename, &checksums)
+            .await?;
+        let (mut repo, expired) = self
+            .save_change(repo, |repo| {
+                repo.add_file(file_path.clone(), size);
+                let expired = match (version, repo.snapshot_retention()) {
+                    (Some(version), Some(keep)) if version.is_snapshot() => {
+                        expired_snapshot_files(repo, version, keep)
+                    }
+                    _ => Vec::new(),
+                };
+                for path in &expired {
+                    repo.remove_file(path);
+                }
+                expired
+            })
+            .await?;
+        self.delete_files(&repo, &expired).await;
+
+        // metadata written from an older revision by a concurrent store is written again
+        loop {
+            self.update_metadata(&repo, version).await?;
+            match self.find(repo.location()).await? {
+                Some(latest) if latest.rev() != repo.rev() => repo = latest,
+                _ => break,
+            }
+        }
+
+        let event = FileStored {
+            location: repo.location().to_string(),
+            path: file_path,
         };
-        let file_path = format!(
-            "{}{}",
-            file.version()
-                .map(|v| v.to_string() + "/")
-                .unwrap_or_else(String::new),
-            filename
-        );
+        let bus = self.bus.read().expect("store_file() EventBus unlock");
+        bus.broadcast(event);
+        Ok(repo)
+    }
+
+    /// Deletes stored files, along with their checksums.
+    /// Paths are relative to the repository.
+    async fn delete_files(&self, repo: &Repo, paths: &[String]) {
+        for path in paths {
+            log::debug!("deleting {} from {}", path, repo.location());
+            let checksums = ChecksumAlgorithm::ALL
+                .iter()
+                .map(|algo| checksum_file(path, *algo));
+            for path in checksums.chain(std::iter::once(path.clone())) {
+                let key = storage::file_key(&repo.storage_location(), &path);
+                if let Err(err) = self.store.delete_blob(&key).await {
+                    log::error!("{}", err)
+                }
+            }
+        }
+    }
+
+    /// Regenerates the metadata of a repository and, for snapshot versions,
+    /// the metadata of the version.
+    pub async fn update_metadata(&self, repo: &Repo, version: Option<&Version>) -> Result<()> {
+        let now = Utc::now();
+        let metadata = Metadata::new(repo, now);
+        log::debug!("updating metadata of {}", repo.location());
+        self.store_metadata(repo, None, metadata.to_xml()).await?;
+
+        if let Some(version) = version.filter(|version| version.is_snapshot()) {
+            let metadata = SnapshotMetadata::new(repo, version, now);
+            log::debug!("updating metadata of {} {}", repo.location(), version);
+            self.store_metadata(repo, Some(version), metadata.to_xml())
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn store_metadata(
+        &self,
+        repo: &Repo,
+        version: Option<&Version>,
+        xml: String,
+    ) -> Result<()> {
+        let key = self.file_key(repo, version, METADATA_FILE);
+        let checksums = Checksums::compute(&xml);
+        let file = File::from_bytes(version, METADATA_FILE, xml.into());
         let blob = Blob::new(key, file.size(), file.into_byte_stream());
         self.store.store_blob(blob).await?;
-        repo.add_file(file_path);
-        self.save(repo).await.map_err(Error::from)
+        self.store_checksums(repo, version, METADATA_FILE, &checksums)
+            .await
+    }
+
+    async fn store_checksums(
+        &self,
+        repo: &Repo,
+        version: Option<&Version>,
+        filename: &str,
+        checksums: &Checksums,
+    ) -> Result<()> {
+        for (algo, checksum) in checksums.iter() {
+            let key = self.file_key(repo, version, &checksum_file(filename, algo));
+            let content = Bytes::copy_from_slice(checksum.as_bytes());
+            let blob = Blob::new(
+                key,
+                content.len(),
+                futures::stream::once(future::ok(content)),
+            );
+            self.store.store_blob(blob).await?;
+        }
+        Ok(())
+    }
+
+    /// Returns a checksum of a file. Checksums are computed when files are stored,
+    /// so only the files stored before that are read to compute them.
+    pub async fn get_checksum(
+        &self,
+        repo: &Repo,
+        version: Option<&Version>,
+        filename: &str,
+        algo: ChecksumAlgorithm,
+    ) -> Result<String> {
+        if let Some(upstream) = repo.upstream() {
+            self.pull_file(repo, upstream, version, filename).await?;
+        }
+
+        let key = self.file_key(repo, version, &checksum_file(filename, algo));
+        if let Some(blob) = self.store.get_blob(&key).await? {
+            let content = read_content(blob).await?;
+            return String::from_utf8(content.to_vec()).map_err(|err| Error::new(&err.to_string()));
+        }
+
+        let key = self.file_key(repo, version, filename);
+        let blob = self
+            .store
+            .get_blob(&key)
+            .await?
+            .ok_or_else(|| Error::not_found("Maven file", filename))?;
+        log::debug!("computing missing checksums of {}", &key);
+        let checksums = Checksums::compute(read_content(blob).await?);
+        self.store_checksums(repo, version, filename, &checksums)
+            .await?;
+        Ok(checksums.get(algo).unwrap_or_default().to_string())
+    }
+
+    /// Checks an uploaded checksum against the stored content of a file.
+    pub async fn verify_checksum(
+        &self,
+        repo: &Repo,
+        version: Option<&Version>,
+        filename: &str,
+        algo: ChecksumAlgorithm,
+        checksum: &str,
+    ) -> Result<bool> {
+        let stored = self.get_checksum(repo, version, filename, algo).await?;
+        Ok(stored.eq_ignore_ascii_case(checksum))
     }
 }
 
@@ -95,11 +518,134 @@ impl Repository<Repo> for RepoService {
     }
 
     async fn deleted(&self, repo: &Repo) {
-        for filename in repo.files() {
-            let key = storage::file_key(repo.location(), filename);
-            if let Err(err) = self.store.delete_blob(&key).await {
-                log::error!("{}", err)
+        let metadata = Metadata::new(repo, Utc::now());
+        let metadata_files = metadata
+            .versions()
+            .iter()
+            .filter_map(|version| Version::parse(version).ok())
+            .filter(Version::is_snapshot)
+            .map(|version| format!("{}/{}", version, METADATA_FILE))
+            .chain(std::iter::once(METADATA_FILE.to_string()));
+        let files: Vec<String> = repo.files().iter().cloned().chain(metadata_files).collect();
+        self.delete_files(repo, &files).await;
+    }
+}
+
+/// Applies a change to a repository and saves it with `put`, which returns `None`
+/// if the repository was changed since it was loaded. The repository is then
+/// loaded again with `load` and the change applied again, so that concurrent
+/// changes, e.g. files stored by concurrent deploys, are not lost.
+async fn save_change<T, C, L, LF, P, PF>(
+    mut repo: Repo,
+    change: C,
+    load: L,
+    put: P,
+) -> Result<(Repo, T)>
+where
+    C: Fn(&mut Repo) -> T,
+    L: Fn() -> LF,
+    LF: Future<Output = Result<Repo>>,
+    P: Fn(Repo) -> PF,
+    PF: Future<Output = Result<Option<Repo>>>,
+{
+    let location = repo.location().to_string();
+    for _ in 0..MAX_SAVE_ATTEMPTS {
+        let outcome = change(&mut repo);
+        if let Some(saved) = put(repo).await? {
+            return Ok((saved, outcome));
+        }
+        log::debug!("repository {} changed concurrently, retrying", &location);
+        repo = load().await?;
+    }
+    Err(Error::conflict(format!(
+        "repository {} kept changing concurrently",
+        location
+    )))
+}
+
+/// The path of a file relative to its repository.
+fn file_path(version: Option<&Version>, filename: &str) -> String {
+    match version {
+        Some(version) => format!("{}/{}", version, filename),
+        None => filename.to_string(),
+    }
+}
+
+async fn read_content(blob: Blob) -> Result<Bytes> {
+    read_stream(blob.into_byte_stream().boxed()).await
+}
+
+async fn read_stream(content: BoxStream<'static, ByteChunk>) -> Result<Bytes> {
+    let content = content
+        .try_fold(BytesMut::new(), |mut content, chunk| {
+            content.extend_from_slice(&chunk);
+            future::ok(content)
+        })
+        .await
+        .map_err(|err| Error::new(&err.to_string()))?;
+    Ok(content.freeze())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Saves a repository like CouchDB does, only if its revision is the stored one.
+    struct Revisions {
+        stored: Mutex<Repo>,
+        revision: Mutex<usize>,
+    }
+
+    impl Revisions {
+        fn new(mut repo: Repo) -> Self {
+            repo.set_rev("1".to_string());
+            Self {
+                stored: Mutex::new(repo),
+                revision: Mutex::new(1),
+            }
+        }
+
+        fn load(&self) -> Repo {
+            self.stored.lock().unwrap().clone()
+        }
+
+        fn put(&self, mut repo: Repo) -> Option<Repo> {
+            let mut revision = self.revision.lock().unwrap();
+            if repo.rev() != Some(&revision.to_string()) {
+                return None;
             }
+            *revision += 1;
+            repo.set_rev(revision.to_string());
+            *self.stored.lock().unwrap() = repo.clone();
+            Some(repo)
         }
     }
+
+    async fn store(revisions: &Revisions, repo: Repo, path: &str) -> Repo {
+        let (repo, _) = save_change(
+            repo,
+            |repo| {
+                repo.add_file(path, 1);
+            },
+            || future::ok(revisions.load()),
+            |repo| future::ok(revisions.put(repo)),
+        )
+        .await
+        .unwrap();
+        repo
+    }
+
+    #[tokio::test]
+    async fn it_keeps_the_files_of_interleaved_stores() {
+        let revisions = Revisions::new(Repo::new("io.enseada", "test", true));
+        let first = revisions.load();
+        let second = revisions.load();
+
+        store(&revisions, first, "1.0/test-1.0.jar").await;
+        let saved = store(&revisions, second, "1.1/test-1.1.jar").await;
+
+        assert_eq!(saved.files(), ["1.0/test-1.0.jar", "1.1/test-1.1.jar"]);
+        assert_eq!(revisions.load().files(), saved.files());
+        assert_eq!(Metadata::new(&saved, Utc::now()).versions(), ["1.0", "1.1"]);
+    }
 }
@@ -1,5 +1,13 @@
 use maven_version::Version;
 
+/// Where files fetched from remote repositories are staged until their
+/// checksum is verified, outside of the keys of the repositories.
+pub const STAGING_PREFIX: &str = "artifacts/maven-staging/";
+
+pub fn staging_key(id: &str) -> String {
+    format!("{}{}", STAGING_PREFIX, id)
+}
+
 pub fn versioned_file_key(prefix: &str, version: &Version, filename: &str) -> String {
     format!("artifacts/maven/{}/{}/{}", prefix, version, filename)
 }
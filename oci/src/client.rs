@@ -0,0 +1,473 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+use std::sync::RwLock;
+
+use bytes::Bytes;
+use futures::{Stream, TryStreamExt};
+use http::header;
+use reqwest::{Body, Client, Method, RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+
+use enseada::replication::Remote;
+
+use crate::digest::Digest;
+use crate::entity::Upstream;
+use crate::error::{Error, ErrorCode};
+use crate::header::CONTENT_DIGEST;
+use crate::mime::MediaType;
+use crate::Result;
+
+/// A manifest fetched from an upstream registry.
+#[derive(Debug)]
+pub struct UpstreamManifest {
+    pub media_type: Option<String>,
+    pub content: Bytes,
+}
+
+/// A blob fetched from an upstream registry.
+/// Blobs checked with a `HEAD` request have no content.
+pub struct UpstreamBlob<S> {
+    pub size: Option<usize>,
+    pub content: S,
+}
+
+const PULL: &str = "pull";
+const PUSH: &str = "pull,push";
+
+/// A remote registry implementing the distribution API,
+/// together with the credentials to access it, if any.
+pub trait Registry: Sync {
+    fn url(&self) -> &str;
+    fn username(&self) -> Option<&str>;
+    fn password(&self) -> Option<&str>;
+}
+
+impl Registry for Upstream {
+    fn url(&self) -> &str {
+        Upstream::url(self)
+    }
+
+    fn username(&self) -> Option<&str> {
+        Upstream::username(self)
+    }
+
+    fn password(&self) -> Option<&str> {
+        Upstream::password(self)
+    }
+}
+
+impl Registry for Remote {
+    fn url(&self) -> &str {
+        Remote::url(self)
+    }
+
+    fn username(&self) -> Option<&str> {
+        Remote::username(self)
+    }
+
+    fn password(&self) -> Option<&str> {
+        Remote::password(self)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+/// An HTTP client for the distribution API of remote registries, pulled from
+/// by proxy repositories and pushed to by replication rules.
+///
+/// Requests are sent with the registry credentials, if any, using basic auth.
+/// Registries that answer with a `Bearer` challenge (e.g. Docker Hub) are
+/// asked for a token, which is cached and reused until it is rejected.
+#[derive(Debug, Default)]
+pub struct RegistryClient {
+    client: Client,
+    tokens: RwLock<HashMap<String, String>>,
+}
+
+impl RegistryClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolves a reference to a manifest digest, without fetching the manifest.
+    /// Registries don't count `HEAD` requests against pull rate limits,
+    /// so this is the preferred way to revalidate a cached tag.
+    pub async fn head_manifest<R: Registry>(
+        &self,
+        registry: &R,
+        name: &str,
+        reference: &str,
+    ) -> Result<Option<Digest>> {
+        let path = format!("{}/manifests/{}", name, reference);
+        let res = self.send(registry, Method::HEAD, name, &path, PULL).await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let res = check_status(res)?;
+        Ok(res
+            .headers()
+            .get(CONTENT_DIGEST)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|digest| Digest::try_from(digest).ok()))
+    }
+
+    pub async fn get_manifest<R: Registry>(
+        &self,
+        registry: &R,
+        name: &str,
+        reference: &str,
+    ) -> Result<Option<UpstreamManifest>> {
+        let path = format!("{}/manifests/{}", name, reference);
+        let res = self.send(registry, Method::GET, name, &path, PULL).await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let res = check_status(res)?;
+        let media_type = res
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|h| h.to_str().ok())
+            .map(|mime| mime.split(';').next().unwrap_or("").trim().to_string());
+        let content = res.bytes().await.map_err(upstream_error)?;
+        Ok(Some(UpstreamManifest {
+            media_type,
+            content,
+        }))
+    }
+
+    pub async fn get_blob<R: Registry>(
+        &self,
+        registry: &R,
+        name: &str,
+        digest: &Digest,
+    ) -> Result<Option<UpstreamBlob<impl Stream<Item = io::Result<Bytes>>>>> {
+        let path = format!("{}/blobs/{}", name, digest);
+        let res = self.send(registry, Method::GET, name, &path, PULL).await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let res = check_status(res)?;
+        let size = res.content_length().map(|len| len as usize);
+        let content = res
+            .bytes_stream()
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err));
+        Ok(Some(UpstreamBlob { size, content }))
+    }
+
+    pub async fn head_blob<R: Registry>(
+        &self,
+        registry: &R,
+        name: &str,
+        digest: &Digest,
+    ) -> Result<Option<UpstreamBlob<()>>> {
+        let path = format!("{}/blobs/{}", name, digest);
+        let res = self.send(registry, Method::HEAD, name, &path, PULL).await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let res = check_status(res)?;
+        let size = res
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|len| len.parse().ok());
+        Ok(Some(UpstreamBlob { size, content: () }))
+    }
+
+    /// Tells whether a manifest is present, asking for push access to the repository.
+    pub async fn has_manifest<R: Registry>(
+        &self,
+        registry: &R,
+        name: &str,
+        reference: &str,
+    ) -> Result<bool> {
+        let path = format!("{}/manifests/{}", name, reference);
+        let res = self.send(registry, Method::HEAD, name, &path, PUSH).await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        check_status(res).map(|_| true)
+    }
+
+    /// Tells whether a blob is present, asking for push access to the repository.
+    pub async fn has_blob<R: Registry>(
+        &self,
+        registry: &R,
+        name: &str,
+        digest: &Digest,
+    ) -> Result<bool> {
+        let path = format!("{}/blobs/{}", name, digest);
+        let res = self.send(registry, Method::HEAD, name, &path, PUSH).await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        check_status(res).map(|_| true)
+    }
+
+    /// Pushes a blob with a monolithic upload.
+    pub async fn push_blob<R, S>(
+        &self,
+        registry: &R,
+        name: &str,
+        digest: &Digest,
+        size: usize,
+        content: S,
+    ) -> Result<()>
+    where
+        R: Registry,
+        S: Stream<Item = io::Result<Bytes>> + Send + Sync + 'static,
+    {
+        let path = format!("{}/blobs/uploads/", name);
+        let res = self.send(registry, Method::POST, name, &path, PUSH).await?;
+        let res = check_status(res)?;
+        let location = res
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|h| h.to_str().ok())
+            .ok_or_else(|| Error::new(ErrorCode::Internal, "remote upload has no location"))?;
+        let url = if location.starts_with('/') {
+            format!("{}{}", registry.url(), location)
+        } else {
+            location.to_string()
+        };
+        let sep = if url.contains('?') { '&' } else { '?' };
+        let url = format!("{}{}digest={}", url, sep, digest);
+
+        log::debug!("pushing blob {} to {}", digest, url);
+        let res = self
+            .request(
+                registry,
+                Method::PUT,
+                &url,
+                self.token(registry, name, PUSH).as_deref(),
+            )
+            .header(header::CONTENT_TYPE, "application/octet-stream")
+            .header(header::CONTENT_LENGTH, size)
+            .body(Body::wrap_stream(content))
+            .send()
+            .await
+            .map_err(upstream_error)?;
+        check_status(res).map(|_| ())
+    }
+
+    pub async fn push_manifest<R: Registry>(
+        &self,
+        registry: &R,
+        name: &str,
+        reference: &str,
+        media_type: &str,
+        content: Bytes,
+    ) -> Result<()> {
+        let url = format!("{}/v2/{}/manifests/{}", registry.url(), name, reference);
+        log::debug!("pushing manifest to {}", url);
+        let res = self
+            .request(
+                registry,
+                Method::PUT,
+                &url,
+                self.token(registry, name, PUSH).as_deref(),
+            )
+            .header(header::CONTENT_TYPE, media_type)
+            .body(content)
+            .send()
+            .await
+            .map_err(upstream_error)?;
+        check_status(res).map(|_| ())
+    }
+
+    /// Sends a request without body, answering a `Bearer` challenge if needed.
+    /// Requests with a body can't be sent again, so they rely on the token
+    /// cached by a previous request for the same repository and actions.
+    async fn send<R: Registry>(
+        &self,
+        registry: &R,
+        method: Method,
+        name: &str,
+        path: &str,
+        actions: &str,
+    ) -> Result<Response> {
+        let url = format!("{}/v2/{}", registry.url(), path);
+        let token_key = token_key(registry, name, actions);
+        let token = self.tokens.read().unwrap().get(&token_key).cloned();
+        let retry = token.is_some();
+
+        log::debug!("sending {} {}", method, url);
+        let res = self
+            .request(registry, method.clone(), &url, token.as_deref())
+            .send()
+            .await
+            .map_err(upstream_error)?;
+        if res.status() != StatusCode::UNAUTHORIZED {
+            return Ok(res);
+        }
+
+        let challenge = res
+            .headers()
+            .get(header::WWW_AUTHENTICATE)
+            .and_then(|h| h.to_str().ok())
+            .and_then(parse_bearer_challenge);
+        let challenge = match challenge {
+            Some(challenge) => challenge,
+            None if retry => {
+                self.tokens.write().unwrap().remove(&token_key);
+                return Ok(res);
+            }
+            None => return Ok(res),
+        };
+
+        let token = self
+            .fetch_token(registry, name, actions, &challenge)
+            .await?;
+        self.tokens
+            .write()
+            .unwrap()
+            .insert(token_key, token.clone());
+        self.request(registry, method, &url, Some(&token))
+            .send()
+            .await
+            .map_err(upstream_error)
+    }
+
+    fn token<R: Registry>(&self, registry: &R, name: &str, actions: &str) -> Option<String> {
+        let token_key = token_key(registry, name, actions);
+        self.tokens.read().unwrap().get(&token_key).cloned()
+    }
+
+    fn request<R: Registry>(
+        &self,
+        registry: &R,
+        method: Method,
+        url: &str,
+        token: Option<&str>,
+    ) -> RequestBuilder {
+        let req = self
+            .client
+            .request(method, url)
+            .header(header::ACCEPT, accepted_manifest_types());
+        match (token, registry.username()) {
+            (Some(token), _) => req.bearer_auth(token),
+            (None, Some(username)) => req.basic_auth(username, registry.password()),
+            (None, None) => req,
+        }
+    }
+
+    async fn fetch_token<R: Registry>(
+        &self,
+        registry: &R,
+        name: &str,
+        actions: &str,
+        challenge: &HashMap<String, String>,
+    ) -> Result<String> {
+        let realm = challenge
+            .get("realm")
+            .ok_or_else(|| Error::new(ErrorCode::Internal, "remote challenge has no realm"))?;
+        let scope = format!("repository:{}:{}", name, actions);
+        let mut query = vec![("scope", scope.as_str())];
+        if let Some(service) = challenge.get("service") {
+            query.push(("service", service));
+        }
+
+        log::debug!("requesting token from {}", realm);
+        let mut req = self.client.get(realm).query(&query);
+        if let Some(username) = registry.username() {
+            req = req.basic_auth(username, registry.password());
+        }
+        let res = req.send().await.map_err(upstream_error)?;
+        let res: TokenResponse = check_status(res)?.json().await.map_err(upstream_error)?;
+        res.token
+            .or(res.access_token)
+            .ok_or_else(|| Error::new(ErrorCode::Internal, "remote token response is empty"))
+    }
+}
+
+fn token_key<R: Registry>(registry: &R, name: &str, actions: &str) -> String {
+    format!("{}/{}:{}", registry.url(), name, actions)
+}
+
+fn check_status(res: Response) -> Result<Response> {
+    match res.status() {
+        status if status.is_success() => Ok(res),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+            log::warn!("remote registry denied access to {}", res.url());
+            Err(Error::from(ErrorCode::Denied).with_detail("remote", res.url().to_string()))
+        }
+        status => {
+            log::warn!("remote registry returned {} for {}", status, res.url());
+            Err(Error::new(
+                ErrorCode::Internal,
+                format!("remote registry returned {}", status),
+            ))
+        }
+    }
+}
+
+fn upstream_error(err: reqwest::Error) -> Error {
+    log::error!("remote registry request failed: {}", err);
+    Error::new(ErrorCode::Internal, err)
+}
+
+fn accepted_manifest_types() -> String {
+    vec![MediaType::ImageManifest, MediaType::ImageIndex]
+        .into_iter()
+        .flat_map(|mime| mime.compatible_types())
+        .map(|mime| format!("application/{}", mime))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Parses the parameters of a `Bearer` challenge from a `WWW-Authenticate` header,
+/// e.g. `Bearer realm="https://auth.docker.io/token",service="registry.docker.io"`.
+fn parse_bearer_challenge(value: &str) -> Option<HashMap<String, String>> {
+    let value = value.trim();
+    if value.len() < 7 || !value[..7].eq_ignore_ascii_case("bearer ") {
+        return None;
+    }
+
+    let mut params = HashMap::new();
+    let mut rest = value[7..].trim_start();
+    while !rest.is_empty() {
+        let eq = rest.find('=')?;
+        let key = rest[..eq].trim().to_lowercase();
+        rest = rest[eq + 1..].trim_start();
+        let param = if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"')?;
+            rest = &quoted[end + 1..];
+            &quoted[..end]
+        } else {
+            let end = rest.find(',').unwrap_or_else(|| rest.len());
+            let param = &rest[..end];
+            rest = &rest[end..];
+            param.trim()
+        };
+        params.insert(key, param.to_string());
+        rest = rest.trim_start().trim_start_matches(',').trim_start();
+    }
+    Some(params)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_parses_a_bearer_challenge() {
+        let challenge = parse_bearer_challenge(
+            r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/alpine:pull,push""#,
+        )
+        .unwrap();
+
+        assert_eq!(challenge["realm"], "https://auth.docker.io/token");
+        assert_eq!(challenge["service"], "registry.docker.io");
+        assert_eq!(challenge["scope"], "repository:library/alpine:pull,push");
+    }
+
+    #[test]
+    fn it_ignores_other_challenges() {
+        assert!(parse_bearer_challenge(r#"Basic realm="enseada""#).is_none());
+    }
+}
@@ -3,9 +3,11 @@ use std::fmt::{self, Display, Formatter};
 
 use serde::{de::Error as SerdeError, Deserialize, Deserializer, Serialize, Serializer};
 
+use enseada::secure;
+
 use crate::error::{Error, ErrorCode};
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
 #[serde(rename_all = "lowercase")]
 pub enum DigestAlgorithm {
     Sha256,
@@ -22,7 +24,47 @@ impl Display for DigestAlgorithm {
     }
 }
 
-#[derive(Clone, Debug)]
+impl DigestAlgorithm {
+    pub fn hash<C: AsRef<[u8]>>(&self, content: C) -> String {
+        match self {
+            DigestAlgorithm::Sha256 => secure::sha256sum(content).to_string(),
+            DigestAlgorithm::Sha512 => secure::sha512sum(content).to_string(),
+        }
+    }
+
+    pub fn hasher(&self) -> DigestHasher {
+        let hasher = match self {
+            DigestAlgorithm::Sha256 => secure::Hasher::sha256(),
+            DigestAlgorithm::Sha512 => secure::Hasher::sha512(),
+        };
+        DigestHasher {
+            algo: self.clone(),
+            hasher,
+        }
+    }
+}
+
+/// Computes a digest incrementally, as content streams through.
+#[derive(Clone)]
+pub struct DigestHasher {
+    algo: DigestAlgorithm,
+    hasher: secure::Hasher,
+}
+
+impl DigestHasher {
+    pub fn update<C: AsRef<[u8]>>(&mut self, content: C) {
+        self.hasher.update(content);
+    }
+
+    pub fn finish(self) -> Digest {
+        Digest {
+            algo: self.algo,
+            digest: self.hasher.finish().to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Digest {
     algo: DigestAlgorithm,
     digest: String,
@@ -42,6 +84,25 @@ impl Digest {
             digest,
         }
     }
+
+    /// Computes the digest of the given content using the provided algorithm.
+    pub fn compute<C: AsRef<[u8]>>(algo: DigestAlgorithm, content: C) -> Self {
+        let digest = algo.hash(content);
+        Digest { algo, digest }
+    }
+
+    pub fn algo(&self) -> &DigestAlgorithm {
+        &self.algo
+    }
+
+    pub fn hex(&self) -> &str {
+        &self.digest
+    }
+
+    /// Checks if the given content matches this digest.
+    pub fn verify<C: AsRef<[u8]>>(&self, content: C) -> bool {
+        self.algo.hash(content) == self.digest
+    }
 }
 
 impl Display for Digest {
@@ -103,3 +164,48 @@ impl Serialize for Digest {
         s.serialize(serializer)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_parses_a_digest() {
+        let digest = Digest::try_from("sha256:abcdef").unwrap();
+
+        assert_eq!(&DigestAlgorithm::Sha256, digest.algo());
+        assert_eq!("abcdef", digest.hex());
+        assert_eq!("sha256:abcdef", digest.to_string());
+    }
+
+    #[test]
+    fn it_does_not_parse_an_unknown_algorithm() {
+        let digest = Digest::try_from("md5:abcdef");
+
+        assert!(digest.is_err());
+    }
+
+    #[test]
+    fn it_computes_a_sha256_digest() {
+        let digest = Digest::compute(DigestAlgorithm::Sha256, "this is a test string");
+
+        assert_eq!(
+            "sha256:f6774519d1c7a3389ef327e9c04766b999db8cdfb85d1346c471ee86d65885bc",
+            digest.to_string()
+        );
+        assert!(digest.verify("this is a test string"));
+        assert!(!digest.verify("this is another test string"));
+    }
+
+    #[test]
+    fn it_computes_a_sha512_digest_incrementally() {
+        let mut hasher = DigestAlgorithm::Sha512.hasher();
+        hasher.update("this is ");
+        hasher.update("a test string");
+
+        assert_eq!(
+            Digest::compute(DigestAlgorithm::Sha512, "this is a test string"),
+            hasher.finish()
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 use enseada::couchdb::repository::Entity;
@@ -5,6 +6,8 @@ use enseada::guid::Guid;
 
 use crate::digest::Digest;
 
+/// A blob linked to a repository. The content is stored once per digest,
+/// but each repository owning it has its own link.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Blob {
     #[serde(rename = "_id")]
@@ -13,21 +16,45 @@ pub struct Blob {
     rev: Option<String>,
     digest: Digest,
     image: String,
+    #[serde(default)]
+    size: Option<usize>,
+    #[serde(default)]
+    created_at: Option<DateTime<Utc>>,
 }
 
 impl Blob {
-    pub fn new(digest: Digest, group: &str, name: &str) -> Self {
+    pub fn new(digest: Digest, image: &str, size: usize) -> Self {
         Self {
-            id: Self::build_guid(&digest.to_string()),
+            id: Self::build_guid(&Self::build_id(image, &digest)),
             rev: None,
             digest,
-            image: format!("{}/{}", group, name),
+            image: image.to_string(),
+            size: Some(size),
+            created_at: Some(Utc::now()),
         }
     }
 
     pub fn digest(&self) -> &Digest {
         &self.digest
     }
+
+    pub fn image(&self) -> &str {
+        &self.image
+    }
+
+    /// Blobs created before sizes were recorded have no size.
+    pub fn size(&self) -> Option<usize> {
+        self.size
+    }
+
+    /// Blobs created before timestamps were recorded have no creation date.
+    pub fn created_at(&self) -> Option<&DateTime<Utc>> {
+        self.created_at.as_ref()
+    }
+
+    pub fn build_id(image: &str, digest: &Digest) -> String {
+        format!("{}@{}", image, digest)
+    }
 }
 
 impl Entity for Blob {
@@ -1,9 +1,11 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 use enseada::couchdb::repository::Entity;
 use enseada::guid::Guid;
 
-use crate::manifest::ImageManifest;
+use crate::digest::Digest;
+use crate::manifest::ManifestBody;
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Manifest {
@@ -11,28 +13,109 @@ pub struct Manifest {
     id: Guid,
     #[serde(rename = "_rev", skip_serializing_if = "Option::is_none")]
     rev: Option<String>,
-    manifest: ImageManifest,
+    manifest: ManifestBody,
     image: String,
     reference: String,
+    digest: Digest,
+    media_type: String,
+    content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    fetched_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pushed_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pulled_at: Option<DateTime<Utc>>,
 }
 
 impl Manifest {
-    pub fn new(group: &str, name: &str, reference: &str, manifest: ImageManifest) -> Self {
+    pub fn new(
+        image: &str,
+        reference: &str,
+        digest: Digest,
+        media_type: &str,
+        content: String,
+        manifest: ManifestBody,
+    ) -> Self {
         Self {
-            id: Self::build_guid(&Self::build_id(group, name, reference)),
+            id: Self::build_guid(&Self::build_id(image, reference)),
             rev: None,
-            image: format!("{}/{}", group, name),
+            image: image.to_string(),
             reference: reference.to_string(),
+            digest,
+            media_type: media_type.to_string(),
+            content,
             manifest,
+            fetched_at: None,
+            pushed_at: Some(Utc::now()),
+            pulled_at: None,
         }
     }
 
-    pub fn into_inner(self) -> ImageManifest {
+    pub fn reference(&self) -> &str {
+        &self.reference
+    }
+
+    pub fn image(&self) -> &str {
+        &self.image
+    }
+
+    /// The digest of the canonical manifest content, as pushed by the client.
+    pub fn digest(&self) -> &Digest {
+        &self.digest
+    }
+
+    pub fn media_type(&self) -> &str {
+        &self.media_type
+    }
+
+    /// The raw manifest content. It must be served as-is, because any
+    /// re-serialization would change its digest.
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    pub fn manifest(&self) -> &ManifestBody {
+        &self.manifest
+    }
+
+    /// When the manifest was last fetched from, or revalidated against,
+    /// an upstream registry. Manifests pushed to Enseada have no fetch date.
+    pub fn fetched_at(&self) -> Option<&DateTime<Utc>> {
+        self.fetched_at.as_ref()
+    }
+
+    pub fn set_fetched_at(&mut self, fetched_at: DateTime<Utc>) -> &mut Self {
+        self.fetched_at = Some(fetched_at);
+        self
+    }
+
+    /// Manifests pushed before timestamps were recorded have no push date.
+    pub fn pushed_at(&self) -> Option<&DateTime<Utc>> {
+        self.pushed_at.as_ref()
+    }
+
+    pub fn set_pushed_at(&mut self, pushed_at: DateTime<Utc>) -> &mut Self {
+        self.pushed_at = Some(pushed_at);
+        self
+    }
+
+    /// When the manifest was last pulled. It's only tracked
+    /// with a coarse granularity, to avoid a write on every pull.
+    pub fn pulled_at(&self) -> Option<&DateTime<Utc>> {
+        self.pulled_at.as_ref()
+    }
+
+    pub fn set_pulled_at(&mut self, pulled_at: DateTime<Utc>) -> &mut Self {
+        self.pulled_at = Some(pulled_at);
+        self
+    }
+
+    pub fn into_inner(self) -> ManifestBody {
         self.manifest
     }
 
-    pub fn build_id(group: &str, name: &str, reference: &str) -> String {
-        format!("{}-{}:{}", group, name, reference)
+    pub fn build_id(image: &str, reference: &str) -> String {
+        format!("{}:{}", image, reference)
     }
 }
 
@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+
+use enseada::couchdb::repository::Entity;
+use enseada::guid::Guid;
+
+use crate::digest::Digest;
+use crate::image::ImageConfig;
+use crate::manifest::Descriptor;
+
+/// What an image manifest contains, extracted from its configuration blob.
+/// Manifests are immutable, so the metadata is identified by the manifest digest
+/// and shared by all the repositories the manifest is pushed to.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ImageMetadata {
+    #[serde(rename = "_id")]
+    id: Guid,
+    #[serde(rename = "_rev", skip_serializing_if = "Option::is_none")]
+    rev: Option<String>,
+    digest: Digest,
+    /// Artifacts that are not images have a configuration in another format, or none.
+    config: Option<ImageConfig>,
+    layers: Vec<Descriptor>,
+}
+
+impl ImageMetadata {
+    pub fn new(digest: Digest, config: Option<ImageConfig>, layers: Vec<Descriptor>) -> Self {
+        Self {
+            id: Self::build_guid(&digest.to_string()),
+            rev: None,
+            digest,
+            config,
+            layers,
+        }
+    }
+
+    pub fn digest(&self) -> &Digest {
+        &self.digest
+    }
+
+    pub fn config(&self) -> Option<&ImageConfig> {
+        self.config.as_ref()
+    }
+
+    pub fn layers(&self) -> &[Descriptor] {
+        &self.layers
+    }
+
+    /// The compressed size of the image layers.
+    pub fn size(&self) -> usize {
+        self.layers.iter().map(Descriptor::size).sum()
+    }
+}
+
+impl Entity for ImageMetadata {
+    fn build_guid(id: &str) -> Guid {
+        Guid::partitioned("oci_image_metadata", id)
+    }
+
+    fn id(&self) -> &Guid {
+        &self.id
+    }
+
+    fn rev(&self) -> Option<&str> {
+        self.rev.as_deref()
+    }
+
+    fn set_rev(&mut self, rev: String) -> &mut Self {
+        self.rev = Some(rev);
+        self
+    }
+}
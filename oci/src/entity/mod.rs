@@ -1,9 +1,11 @@
 pub use blob::Blob;
 pub use manifest::Manifest;
-pub use repo::Repo;
+pub use metadata::ImageMetadata;
+pub use repo::{Repo, RetentionRule, TagPolicy, Upstream};
 pub use upload::{Upload, UploadChunk};
 
 mod blob;
 mod manifest;
+mod metadata;
 mod repo;
 mod upload;
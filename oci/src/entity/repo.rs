@@ -1,7 +1,12 @@
+use glob::Pattern;
 use serde::{Deserialize, Serialize};
 
 use enseada::couchdb::repository::Entity;
 use enseada::guid::Guid;
+use enseada::quota::Quota;
+use enseada::secure::EncryptedSecret;
+
+use crate::name;
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Repo {
@@ -12,24 +17,76 @@ pub struct Repo {
     group: String,
     name: String,
     description: Option<String>,
+    #[serde(default)]
+    public: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    upstream: Option<Upstream>,
+    #[serde(default)]
+    tag_policy: TagPolicy,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    retention: Vec<RetentionRule>,
+    #[serde(default)]
+    quota: Quota,
 }
 
 impl Repo {
-    pub fn build_id(group: &str, name: &str) -> String {
-        format!("{}-{}", group, name)
+    /// Repositories are identified by their full name, so that RBAC rules
+    /// on their GUIDs can also target whole namespaces.
+    pub fn build_id(full_name: &str) -> String {
+        full_name.to_string()
     }
 
     pub fn new(group: &str, name: &str, description: Option<String>) -> Self {
-        let id = Self::build_guid(&Self::build_id(group, name));
+        let id = Self::build_guid(&Self::build_id(&name::join(group, name)));
         Self {
             id,
             rev: None,
             group: group.to_string(),
             name: name.to_string(),
             description,
+            public: false,
+            upstream: None,
+            tag_policy: TagPolicy::default(),
+            retention: Vec::new(),
+            quota: Quota::default(),
         }
     }
 
+    pub fn set_description(&mut self, description: Option<String>) -> &mut Self {
+        self.description = description;
+        self
+    }
+
+    pub fn set_public(&mut self, public: bool) -> &mut Self {
+        self.public = public;
+        self
+    }
+
+    pub fn set_upstream(&mut self, upstream: Option<Upstream>) -> &mut Self {
+        self.upstream = upstream;
+        self
+    }
+
+    pub fn set_retention(&mut self, retention: Vec<RetentionRule>) -> &mut Self {
+        self.retention = retention;
+        self
+    }
+
+    pub fn set_tag_policy(&mut self, tag_policy: TagPolicy) -> &mut Self {
+        self.tag_policy = tag_policy;
+        self
+    }
+
+    pub fn set_quota(&mut self, quota: Quota) -> &mut Self {
+        self.quota = quota;
+        self
+    }
+
+    pub fn with_upstream(mut self, upstream: Option<Upstream>) -> Self {
+        self.upstream = upstream;
+        self
+    }
+
     pub fn group(&self) -> &str {
         &self.group
     }
@@ -39,12 +96,136 @@ impl Repo {
     }
 
     pub fn full_name(&self) -> String {
-        format!("{}/{}", &self.group, &self.name)
+        name::join(&self.group, &self.name)
     }
 
     pub fn description(&self) -> Option<&str> {
         self.description.as_deref()
     }
+
+    /// Public repositories can be pulled from without credentials.
+    pub fn is_public(&self) -> bool {
+        self.public
+    }
+
+    /// The upstream registry this repository proxies pulls to, if any.
+    /// A proxy repository also covers all the repositories in its namespace.
+    pub fn upstream(&self) -> Option<&Upstream> {
+        self.upstream.as_ref()
+    }
+
+    pub fn tag_policy(&self) -> &TagPolicy {
+        &self.tag_policy
+    }
+
+    /// The rules selecting tags and manifests to be deleted periodically.
+    pub fn retention(&self) -> &[RetentionRule] {
+        &self.retention
+    }
+
+    /// The limits of the bytes stored by the blobs of the repository.
+    pub fn quota(&self) -> &Quota {
+        &self.quota
+    }
+}
+
+/// Which tags of a repository can be moved to another manifest, or deleted, once pushed.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TagPolicy {
+    /// All tags can be overwritten.
+    Mutable,
+    /// No tag can be overwritten.
+    Immutable,
+    /// Tags matching any of the glob patterns (e.g. `v*`) cannot be overwritten.
+    Protected { patterns: Vec<String> },
+}
+
+impl TagPolicy {
+    pub fn is_protected(&self, tag: &str) -> bool {
+        match self {
+            TagPolicy::Mutable => false,
+            TagPolicy::Immutable => true,
+            TagPolicy::Protected { patterns } => patterns
+                .iter()
+                .filter_map(|pattern| Pattern::new(pattern).ok())
+                .any(|pattern| pattern.matches(tag)),
+        }
+    }
+}
+
+impl Default for TagPolicy {
+    fn default() -> Self {
+        TagPolicy::Mutable
+    }
+}
+
+/// A rule selecting the tags and manifests of a repository to be deleted.
+/// Tags protected by the repository tag policy are never deleted.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RetentionRule {
+    /// Keeps the `count` most recently pushed tags matching the glob pattern,
+    /// deleting the others.
+    KeepLastTags { pattern: String, count: usize },
+    /// Deletes the manifests without tags pushed more than `days` ago.
+    /// Manifests referenced by an image index or having a subject are kept.
+    DeleteUntagged { days: u32 },
+    /// Deletes the tags not pulled in the last `days`.
+    /// Tags never pulled are considered pulled when pushed.
+    DeleteUnpulledTags { days: u32 },
+}
+
+/// An upstream registry, pulled through and cached by a proxy repository.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Upstream {
+    url: String,
+    name: Option<String>,
+    username: Option<String>,
+    password: Option<EncryptedSecret>,
+    ttl: u64,
+}
+
+impl Upstream {
+    pub fn new(url: &str, name: Option<String>, ttl: u64) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            name: name.filter(|name| !name.is_empty()),
+            username: None,
+            password: None,
+            ttl,
+        }
+    }
+
+    pub fn with_credentials(mut self, username: String, password: String) -> Self {
+        self.username = Some(username);
+        self.password = Some(EncryptedSecret::new(password));
+        self
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// The name of the upstream repository, or namespace, proxied by the repository.
+    /// If missing, the proxy repository has the same name as the upstream one.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+
+    pub fn password(&self) -> Option<&str> {
+        self.password.as_ref().map(EncryptedSecret::expose)
+    }
+
+    /// How long, in seconds, tags are served from the cache before being
+    /// revalidated against the upstream registry.
+    pub fn ttl(&self) -> u64 {
+        self.ttl
+    }
 }
 
 impl Entity for Repo {
@@ -65,3 +246,21 @@ impl Entity for Repo {
         self
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_protects_tags() {
+        let policy = TagPolicy::Protected {
+            patterns: vec!["v*".to_string(), "release-*".to_string()],
+        };
+        assert!(policy.is_protected("v1.0.0"));
+        assert!(policy.is_protected("release-2020"));
+        assert!(!policy.is_protected("latest"));
+
+        assert!(TagPolicy::Immutable.is_protected("latest"));
+        assert!(!TagPolicy::Mutable.is_protected("v1.0.0"));
+    }
+}
@@ -1,5 +1,6 @@
 use std::fmt::{self, Debug, Formatter};
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -17,12 +18,14 @@ pub struct Upload {
     image: String,
     chunks: Vec<UploadChunk>,
     latest_offset: usize,
+    #[serde(default)]
+    updated_at: Option<DateTime<Utc>>,
 }
 
 impl Upload {
-    pub fn new(group: &str, name: &str) -> Self {
+    pub fn new(image: &str) -> Self {
         Self {
-            image: format!("{}/{}", group, name),
+            image: image.to_string(),
             ..Default::default()
         }
     }
@@ -40,6 +43,7 @@ impl Upload {
         let key = storage::chunk_key(self.id.id(), chunk.start_range);
         chunk.storage_key = Some(key);
         self.latest_offset = chunk.end_range;
+        self.updated_at = Some(Utc::now());
         self.chunks.push(chunk);
         self
     }
@@ -47,6 +51,11 @@ impl Upload {
     pub fn latest_offset(&self) -> usize {
         self.latest_offset
     }
+
+    /// Uploads created before timestamps were recorded have no update date.
+    pub fn updated_at(&self) -> Option<&DateTime<Utc>> {
+        self.updated_at.as_ref()
+    }
 }
 
 impl Default for Upload {
@@ -57,6 +66,7 @@ impl Default for Upload {
             image: String::new(),
             chunks: Vec::new(),
             latest_offset: 0,
+            updated_at: Some(Utc::now()),
         }
     }
 }
@@ -2,7 +2,8 @@ use enseada::couchdb::repository::Entity;
 use enseada::guid::Guid;
 use events::Event;
 
-use crate::entity::Repo;
+use crate::digest::Digest;
+use crate::entity::{Blob, Manifest, Repo};
 
 #[derive(Debug, Event)]
 pub struct RepoCreated {
@@ -66,3 +67,123 @@ impl From<&Repo> for RepoDeleted {
         }
     }
 }
+
+#[derive(Debug, Event)]
+pub struct ManifestDeleted {
+    pub id: Guid,
+    pub image: String,
+    pub reference: String,
+    pub digest: Digest,
+}
+
+impl From<&Manifest> for ManifestDeleted {
+    fn from(manifest: &Manifest) -> Self {
+        Self {
+            id: manifest.id().clone(),
+            image: manifest.image().to_string(),
+            reference: manifest.reference().to_string(),
+            digest: manifest.digest().clone(),
+        }
+    }
+}
+
+#[derive(Debug, Event)]
+pub struct ManifestPushed {
+    pub id: Guid,
+    pub image: String,
+    pub reference: String,
+    pub digest: Digest,
+    pub media_type: String,
+    pub size: usize,
+}
+
+impl From<&Manifest> for ManifestPushed {
+    fn from(manifest: &Manifest) -> Self {
+        Self {
+            id: manifest.id().clone(),
+            image: manifest.image().to_string(),
+            reference: manifest.reference().to_string(),
+            digest: manifest.digest().clone(),
+            media_type: manifest.media_type().to_string(),
+            size: manifest.content().len(),
+        }
+    }
+}
+
+#[derive(Debug, Event)]
+pub struct ManifestPulled {
+    pub id: Guid,
+    pub image: String,
+    pub reference: String,
+    pub digest: Digest,
+    pub media_type: String,
+    pub size: usize,
+}
+
+impl From<&Manifest> for ManifestPulled {
+    fn from(manifest: &Manifest) -> Self {
+        Self {
+            id: manifest.id().clone(),
+            image: manifest.image().to_string(),
+            reference: manifest.reference().to_string(),
+            digest: manifest.digest().clone(),
+            media_type: manifest.media_type().to_string(),
+            size: manifest.content().len(),
+        }
+    }
+}
+
+#[derive(Debug, Event)]
+pub struct BlobPushed {
+    pub id: Guid,
+    pub image: String,
+    pub digest: Digest,
+    pub size: Option<usize>,
+}
+
+impl From<&Blob> for BlobPushed {
+    fn from(blob: &Blob) -> Self {
+        Self {
+            id: blob.id().clone(),
+            image: blob.image().to_string(),
+            digest: blob.digest().clone(),
+            size: blob.size(),
+        }
+    }
+}
+
+#[derive(Debug, Event)]
+pub struct BlobPulled {
+    pub id: Guid,
+    pub image: String,
+    pub digest: Digest,
+    pub size: Option<usize>,
+}
+
+impl From<&Blob> for BlobPulled {
+    fn from(blob: &Blob) -> Self {
+        Self {
+            id: blob.id().clone(),
+            image: blob.image().to_string(),
+            digest: blob.digest().clone(),
+            size: blob.size(),
+        }
+    }
+}
+
+#[derive(Debug, Event)]
+pub struct BlobDeleted {
+    pub id: Guid,
+    pub image: String,
+    pub digest: Digest,
+}
+
+impl From<&Blob> for BlobDeleted {
+    fn from(blob: &Blob) -> Self {
+        Self {
+            id: blob.id().clone(),
+            image: blob.image().to_string(),
+            digest: blob.digest().clone(),
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, Duration, Utc};
+use futures::TryStreamExt;
+use serde::Serialize;
+
+use enseada::couchdb::db::Database;
+use enseada::couchdb::repository::{Entity, Repository};
+use enseada::storage::{Provider, StoredBlob};
+use events::EventBus;
+
+use crate::entity::Blob;
+use crate::error::Error;
+use crate::service::{BlobService, ManifestService, UploadService};
+use crate::{storage, Result};
+
+/// The outcome of a garbage collection run.
+/// In dry-run mode it lists what would have been deleted.
+#[derive(Debug, Default, Serialize)]
+pub struct GcReport {
+    dry_run: bool,
+    manifests: usize,
+    blobs: Vec<String>,
+    uploads: Vec<String>,
+    chunks: Vec<String>,
+    contents: Vec<String>,
+}
+
+impl GcReport {
+    pub fn blobs(&self) -> &[String] {
+        &self.blobs
+    }
+
+    pub fn uploads(&self) -> &[String] {
+        &self.uploads
+    }
+
+    pub fn chunks(&self) -> &[String] {
+        &self.chunks
+    }
+
+    /// The storage keys of blob contents that no repository links to.
+    pub fn contents(&self) -> &[String] {
+        &self.contents
+    }
+}
+
+/// Mark-and-sweep garbage collector for the OCI registry.
+///
+/// The mark phase walks all manifests and collects the blobs they reference.
+/// The sweep phase unlinks every other blob from its repository, once checked again
+/// against the manifests pushed in the meantime, deleting its content
+/// once no repository links to it anymore, and deletes stale uploads with their chunks.
+/// The storage is then listed, to delete the chunks under `artifacts/oci/uploads/`
+/// that belong to no upload, and the blob contents that no repository links to.
+/// Blobs, uploads and stored contents younger than the grace period are never collected,
+/// so that pushes in progress are left alone.
+#[derive(Debug)]
+pub struct GarbageCollector {
+    manifests: ManifestService,
+    blobs: BlobService,
+    uploads: UploadService,
+    store: Arc<Provider>,
+    grace_period: Duration,
+}
+
+impl GarbageCollector {
+    pub fn new(
+        db: Arc<Database>,
+        bus: Arc<RwLock<EventBus>>,
+        store: Arc<Provider>,
+        grace_period: Duration,
+    ) -> Self {
+        Self {
+            manifests: ManifestService::new(db.clone(), bus.clone()),
+            blobs: BlobService::new(db.clone(), bus.clone(), store.clone()),
+            uploads: UploadService::new(db, bus, store.clone()),
+            store,
+            grace_period,
+        }
+    }
+
+    pub async fn run(&self, dry_run: bool) -> Result<GcReport> {
+        log::info!("starting OCI garbage collection (dry run: {})", dry_run);
+        let threshold = Utc::now() - self.grace_period;
+        let mut report = GcReport {
+            dry_run,
+            ..Default::default()
+        };
+
+        let marked = self.mark(&mut report).await?;
+        self.sweep_blobs(&marked, &threshold, &mut report).await?;
+        let live_uploads = self.sweep_uploads(&threshold, &mut report).await?;
+        self.sweep_orphaned_chunks(&live_uploads, &threshold, &mut report)
+            .await?;
+        self.sweep_orphaned_contents(&threshold, &mut report)
+            .await?;
+
+        log::info!(
+            "OCI garbage collection completed: {} blobs, {} uploads, {} chunks, {} contents",
+            report.blobs.len(),
+            report.uploads.len(),
+            report.chunks.len(),
+            report.contents.len()
+        );
+        Ok(report)
+    }
+
+    async fn mark(&self, report: &mut GcReport) -> Result<HashSet<String>> {
+        let mut marked = HashSet::new();
+        let mut manifests = self.manifests.find_all_stream(all_docs());
+        while let Some(manifest) = manifests.try_next().await? {
+            report.manifests += 1;
+            for blob in manifest.manifest().blobs() {
+                marked.insert(Blob::build_id(manifest.image(), blob.digest()));
+            }
+        }
+        log::debug!("marked {} referenced blobs", marked.len());
+        Ok(marked)
+    }
+
+    async fn sweep_blobs(
+        &self,
+        marked: &HashSet<String>,
+        threshold: &DateTime<Utc>,
+        report: &mut GcReport,
+    ) -> Result<()> {
+        // candidates are collected first, because deleting documents
+        // would shift the pages of the stream
+        let unreferenced: Vec<Blob> = self
+            .blobs
+            .find_all_stream(all_docs())
+            .try_filter(|blob: &Blob| {
+                let id = Blob::build_id(blob.image(), blob.digest());
+                let expired = blob.created_at().map_or(true, |at| at < threshold);
+                futures::future::ready(expired && !marked.contains(&id))
+            })
+            .try_collect()
+            .await?;
+
+        for blob in unreferenced {
+            // a manifest pushed since the mark phase may reuse a blob already linked
+            if self
+                .manifests
+                .references_blob(blob.image(), blob.digest())
+                .await?
+            {
+                log::debug!("blob {} was referenced during the run", blob.id());
+                continue;
+            }
+            log::debug!("blob {} is unreferenced", blob.id());
+            if !report.dry_run {
+                self.blobs.delete_blob(&blob).await?;
+            }
+            report
+                .blobs
+                .push(Blob::build_id(blob.image(), blob.digest()));
+        }
+        Ok(())
+    }
+
+    /// Returns the ids of the uploads that are not stale.
+    async fn sweep_uploads(
+        &self,
+        threshold: &DateTime<Utc>,
+        report: &mut GcReport,
+    ) -> Result<HashSet<String>> {
+        let mut live = HashSet::new();
+        let mut stale = Vec::new();
+        let mut uploads = self.uploads.find_all_stream(all_docs());
+        while let Some(upload) = uploads.try_next().await? {
+            if upload.updated_at().map_or(true, |at| at < threshold) {
+                stale.push(upload);
+            } else {
+                live.insert(upload.id().id().to_string());
+            }
+        }
+
+        for upload in stale {
+            log::debug!("upload {} is stale", upload.id());
+            report.chunks.extend(
+                upload
+                    .chunks()
+                    .iter()
+                    .filter_map(|chunk| chunk.storage_key())
+                    .map(str::to_string),
+            );
+            if !report.dry_run {
+                self.uploads.delete(&upload).await.map_err(Error::from)?;
+            }
+            report.uploads.push(upload.id().id().to_string());
+        }
+        Ok(live)
+    }
+
+    /// Chunks are stored before their upload is saved, so a failed push
+    /// may leave stored keys that belong to no upload.
+    async fn sweep_orphaned_chunks(
+        &self,
+        live_uploads: &HashSet<String>,
+        threshold: &DateTime<Utc>,
+        report: &mut GcReport,
+    ) -> Result<()> {
+        let swept: HashSet<String> = report.chunks.iter().cloned().collect();
+        let prefix = storage::UPLOADS_PREFIX;
+        for blob in self.store.list_blobs(prefix).await? {
+            let upload_id = blob.key()[prefix.len()..].split('/').next();
+            let orphaned = upload_id.map_or(true, |id| !live_uploads.contains(id));
+            if !orphaned || !is_expired(&blob, threshold) || swept.contains(blob.key()) {
+                continue;
+            }
+
+            log::debug!("chunk {} belongs to no upload", blob.key());
+            if !report.dry_run {
+                self.store.delete_blob(blob.key()).await?;
+            }
+            report.chunks.push(blob.key().to_string());
+        }
+        Ok(())
+    }
+
+    /// Finds the blob contents that no repository links to, e.g. because
+    /// a failure happened between storing the content and linking it.
+    async fn sweep_orphaned_contents(
+        &self,
+        threshold: &DateTime<Utc>,
+        report: &mut GcReport,
+    ) -> Result<()> {
+        let swept: HashSet<&String> = report.blobs.iter().collect();
+        let mut linked = HashSet::new();
+        let mut blobs = self.blobs.find_all_stream(all_docs());
+        while let Some(blob) = blobs.try_next().await? {
+            if !swept.contains(&Blob::build_id(blob.image(), blob.digest())) {
+                linked.insert(storage::blob_key(blob.digest()));
+            }
+        }
+
+        for blob in self.store.list_blobs(storage::BLOBS_PREFIX).await? {
+            if linked.contains(blob.key()) || !is_expired(&blob, threshold) {
+                continue;
+            }
+
+            log::debug!("blob content {} is not linked", blob.key());
+            if !report.dry_run {
+                self.store.delete_blob(blob.key()).await?;
+            }
+            report.contents.push(blob.key().to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Stored blobs without a modification date are never collected.
+fn is_expired(blob: &StoredBlob, threshold: &DateTime<Utc>) -> bool {
+    blob.last_modified().map_or(false, |at| at < threshold)
+}
+
+fn all_docs() -> serde_json::Value {
+    serde_json::json!({
+        "_id": {
+            "$gt": null,
+        }
+    })
+}
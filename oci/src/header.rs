@@ -1,3 +1,5 @@
 pub const DISTRIBUTION_API_VERSION: &str = "docker-distribution-api-version";
 pub const CONTENT_DIGEST: &str = "docker-content-digest";
 pub const BLOB_UPLOAD_ID: &str = "blob-upload-session-id";
+pub const SUBJECT: &str = "oci-subject";
+pub const FILTERS_APPLIED: &str = "oci-filters-applied";
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, ErrorCode};
+
+/// The configuration of an image, stored in the blob referenced by the `config`
+/// descriptor of its manifest. Only the fields describing what the image contains
+/// and how it runs are kept. Both OCI and Docker configurations are supported.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ImageConfig {
+    #[serde(default)]
+    os: String,
+    #[serde(default)]
+    architecture: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    variant: Option<String>,
+    #[serde(default)]
+    created: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    author: Option<String>,
+    #[serde(default)]
+    config: Option<ContainerConfig>,
+    #[serde(default)]
+    history: Vec<History>,
+}
+
+/// The execution parameters of the containers created from an image.
+/// Field names are in PascalCase in image configurations.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ContainerConfig {
+    #[serde(default, alias = "User")]
+    user: Option<String>,
+    #[serde(default, alias = "Entrypoint")]
+    entrypoint: Option<Vec<String>>,
+    #[serde(default, alias = "Cmd")]
+    cmd: Option<Vec<String>>,
+    #[serde(default, alias = "Env")]
+    env: Option<Vec<String>>,
+    #[serde(default, alias = "WorkingDir")]
+    working_dir: Option<String>,
+    #[serde(default, alias = "Labels")]
+    labels: Option<HashMap<String, String>>,
+}
+
+/// A step of the build of an image. Steps that did not change
+/// the filesystem (e.g. `ENV`) are flagged as empty layers.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct History {
+    #[serde(default)]
+    created: Option<DateTime<Utc>>,
+    #[serde(default)]
+    created_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[serde(default)]
+    empty_layer: bool,
+}
+
+impl ImageConfig {
+    pub fn parse(content: &[u8]) -> Result<Self, Error> {
+        serde_json::from_slice(content).map_err(|err| {
+            Error::new(ErrorCode::ManifestInvalid, "invalid image configuration")
+                .with_detail("reason", err.to_string())
+        })
+    }
+
+    pub fn os(&self) -> &str {
+        &self.os
+    }
+
+    pub fn architecture(&self) -> &str {
+        &self.architecture
+    }
+
+    pub fn variant(&self) -> Option<&str> {
+        self.variant.as_deref()
+    }
+
+    pub fn created(&self) -> Option<&DateTime<Utc>> {
+        self.created.as_ref()
+    }
+
+    pub fn config(&self) -> Option<&ContainerConfig> {
+        self.config.as_ref()
+    }
+
+    pub fn history(&self) -> &[History] {
+        &self.history
+    }
+}
+
+impl ContainerConfig {
+    pub fn entrypoint(&self) -> Option<&[String]> {
+        self.entrypoint.as_deref()
+    }
+
+    pub fn cmd(&self) -> Option<&[String]> {
+        self.cmd.as_deref()
+    }
+
+    pub fn env(&self) -> Option<&[String]> {
+        self.env.as_deref()
+    }
+
+    pub fn labels(&self) -> Option<&HashMap<String, String>> {
+        self.labels.as_ref()
+    }
+}
+
+impl History {
+    pub fn created_by(&self) -> Option<&str> {
+        self.created_by.as_deref()
+    }
+
+    pub fn is_empty_layer(&self) -> bool {
+        self.empty_layer
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DOCKER_CONFIG: &str = r#"{
+        "architecture": "amd64",
+        "os": "linux",
+        "created": "2020-10-22T02:19:24.499382102Z",
+        "config": {
+            "Env": ["PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin"],
+            "Cmd": ["/bin/sh"],
+            "Entrypoint": null,
+            "Labels": { "org.opencontainers.image.title": "alpine" },
+            "ExposedPorts": { "80/tcp": {} }
+        },
+        "history": [
+            {
+                "created": "2020-10-22T02:19:24.33416307Z",
+                "created_by": "/bin/sh -c #(nop) ADD file:f17f65714f703db90 in / "
+            },
+            {
+                "created": "2020-10-22T02:19:24.499382102Z",
+                "created_by": "/bin/sh -c #(nop)  CMD [\"/bin/sh\"]",
+                "empty_layer": true
+            }
+        ],
+        "rootfs": {
+            "type": "layers",
+            "diff_ids": ["sha256:ace0eda3e3be35a979cec764a3321b4c7d0d9e4bb3094d20d3ff6782961a8d54"]
+        }
+    }"#;
+
+    #[test]
+    fn it_parses_a_docker_image_config() {
+        let config = ImageConfig::parse(DOCKER_CONFIG.as_bytes()).unwrap();
+        assert_eq!(config.os(), "linux");
+        assert_eq!(config.architecture(), "amd64");
+        assert!(config.created().is_some());
+
+        let container = config.config().unwrap();
+        assert_eq!(container.cmd().unwrap(), ["/bin/sh"]);
+        assert!(container.entrypoint().is_none());
+        assert_eq!(container.env().unwrap().len(), 1);
+        assert_eq!(
+            container.labels().unwrap()["org.opencontainers.image.title"],
+            "alpine"
+        );
+
+        assert_eq!(config.history().len(), 2);
+        assert!(!config.history()[0].is_empty_layer());
+        assert!(config.history()[1].is_empty_layer());
+    }
+
+    #[test]
+    fn it_parses_a_config_without_container_config() {
+        let config = ImageConfig::parse(br#"{"architecture":"arm64","os":"linux"}"#).unwrap();
+        assert_eq!(config.architecture(), "arm64");
+        assert!(config.config().is_none());
+        assert!(config.history().is_empty());
+    }
+}
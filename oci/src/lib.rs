@@ -1,10 +1,17 @@
+pub mod client;
 pub mod digest;
 pub mod entity;
 pub mod error;
 pub mod events;
+pub mod gc;
 pub mod header;
+pub mod image;
 pub mod manifest;
 pub mod mime;
+pub mod name;
+pub mod notification;
+pub mod replication;
+pub mod retention;
 pub mod service;
 mod storage;
 
@@ -1,30 +1,116 @@
 use std::collections::HashMap;
+use std::convert::TryFrom;
 
 use serde::{Deserialize, Serialize};
 use url::Url;
 
 use crate::digest::Digest;
+use crate::error::{Error, ErrorCode};
 use crate::mime::MediaType;
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Descriptor {
-    media_type: MediaType,
+    media_type: String,
     digest: Digest,
     #[serde(default)]
     size: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    artifact_type: Option<String>,
     #[serde(rename = "urls", skip_serializing_if = "Option::is_none")]
     urls: Option<Vec<Url>>,
     #[serde(rename = "annotations", skip_serializing_if = "Option::is_none")]
     annotations: Option<HashMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    platform: Option<Platform>,
+}
+
+impl Descriptor {
+    pub fn new<M: ToString>(media_type: M, digest: Digest, size: usize) -> Self {
+        Self {
+            media_type: media_type.to_string(),
+            digest,
+            size,
+            artifact_type: None,
+            urls: None,
+            annotations: None,
+            platform: None,
+        }
+    }
+
+    pub fn with_artifact_type(mut self, artifact_type: Option<String>) -> Self {
+        self.artifact_type = artifact_type;
+        self
+    }
+
+    pub fn with_annotations(mut self, annotations: Option<HashMap<String, String>>) -> Self {
+        self.annotations = annotations;
+        self
+    }
+
+    /// Descriptors may reference arbitrary content, like signatures or SBOMs,
+    /// so their media type is kept as-is.
+    pub fn media_type(&self) -> &str {
+        &self.media_type
+    }
+
+    pub fn digest(&self) -> &Digest {
+        &self.digest
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn artifact_type(&self) -> Option<&str> {
+        self.artifact_type.as_deref()
+    }
+
+    pub fn platform(&self) -> Option<&Platform> {
+        self.platform.as_ref()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Platform {
+    architecture: String,
+    os: String,
+    #[serde(rename = "os.version", skip_serializing_if = "Option::is_none")]
+    os_version: Option<String>,
+    #[serde(rename = "os.features", skip_serializing_if = "Option::is_none")]
+    os_features: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    variant: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    features: Option<Vec<String>>,
+}
+
+impl Platform {
+    pub fn architecture(&self) -> &str {
+        &self.architecture
+    }
+
+    pub fn os(&self) -> &str {
+        &self.os
+    }
+
+    pub fn variant(&self) -> Option<&str> {
+        self.variant.as_deref()
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ImageManifest {
     schema_version: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    media_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    artifact_type: Option<String>,
     config: Descriptor,
     layers: Vec<Descriptor>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subject: Option<Descriptor>,
     #[serde(rename = "annotations", skip_serializing_if = "Option::is_none")]
     annotations: Option<HashMap<String, String>>,
 }
@@ -33,31 +119,319 @@ impl ImageManifest {
     pub fn new(digest: Digest, size: usize) -> Self {
         Self {
             schema_version: 2,
-            config: Descriptor {
-                media_type: MediaType::ImageConfig,
-                digest,
-                size,
-                urls: None,
-                annotations: None,
-            },
+            media_type: None,
+            artifact_type: None,
+            config: Descriptor::new(MediaType::ImageConfig, digest, size),
             layers: Vec::new(),
+            subject: None,
             annotations: None,
         }
     }
 
-    pub fn digest(&self) -> &Digest {
-        &self.config.digest
+    /// The media type declared in the manifest body, if any.
+    /// Docker manifests always include it, while it is optional for OCI ones.
+    pub fn media_type(&self) -> Option<&str> {
+        self.media_type.as_deref()
+    }
+
+    pub fn config(&self) -> &Descriptor {
+        &self.config
+    }
+
+    pub fn layers(&self) -> &[Descriptor] {
+        &self.layers
+    }
+
+    /// The artifact type, falling back to the config media type
+    /// as mandated by the OCI distribution spec.
+    pub fn artifact_type(&self) -> &str {
+        self.artifact_type
+            .as_deref()
+            .unwrap_or_else(|| self.config.media_type())
+    }
+
+    pub fn subject(&self) -> Option<&Descriptor> {
+        self.subject.as_ref()
+    }
+
+    pub fn annotations(&self) -> Option<&HashMap<String, String>> {
+        self.annotations.as_ref()
     }
 
     pub fn add_layer(&mut self, digest: Digest, size: usize) -> &mut Self {
-        let layer = Descriptor {
-            media_type: MediaType::ImageLayer,
-            digest,
-            size,
-            urls: None,
-            annotations: None,
-        };
+        let layer = Descriptor::new(MediaType::ImageLayer, digest, size);
         self.layers.push(layer);
         self
     }
+
+    pub fn set_subject(&mut self, subject: Descriptor) -> &mut Self {
+        self.subject = Some(subject);
+        self
+    }
+}
+
+/// An image index (or Docker manifest list) pointing to
+/// platform-specific image manifests.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageIndex {
+    schema_version: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    media_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    artifact_type: Option<String>,
+    manifests: Vec<Descriptor>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subject: Option<Descriptor>,
+    #[serde(rename = "annotations", skip_serializing_if = "Option::is_none")]
+    annotations: Option<HashMap<String, String>>,
+}
+
+impl ImageIndex {
+    pub fn new(manifests: Vec<Descriptor>) -> Self {
+        Self {
+            schema_version: 2,
+            media_type: Some(MediaType::ImageIndex.to_string()),
+            artifact_type: None,
+            manifests,
+            subject: None,
+            annotations: None,
+        }
+    }
+
+    pub fn artifact_type(&self) -> Option<&str> {
+        self.artifact_type.as_deref()
+    }
+
+    pub fn subject(&self) -> Option<&Descriptor> {
+        self.subject.as_ref()
+    }
+
+    pub fn annotations(&self) -> Option<&HashMap<String, String>> {
+        self.annotations.as_ref()
+    }
+
+    pub fn media_type(&self) -> Option<&str> {
+        self.media_type.as_deref()
+    }
+
+    pub fn manifests(&self) -> &[Descriptor] {
+        &self.manifests
+    }
+
+    /// Finds the manifest matching the given OS and architecture, if any.
+    pub fn find_platform(&self, os: &str, architecture: &str) -> Option<&Descriptor> {
+        self.manifests.iter().find(|m| {
+            m.platform()
+                .map(|p| p.os() == os && p.architecture() == architecture)
+                .unwrap_or(false)
+        })
+    }
+}
+
+/// Any manifest body accepted by the registry.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ManifestBody {
+    Image(ImageManifest),
+    Index(ImageIndex),
+}
+
+impl ManifestBody {
+    /// Parses a manifest body. The media type, taken from either the request
+    /// or the body itself, decides which kind of manifest is expected.
+    pub fn parse(media_type: Option<&str>, content: &[u8]) -> Result<Self, Error> {
+        let invalid = |err: serde_json::Error| {
+            Error::from(ErrorCode::ManifestInvalid).with_detail("reason", err.to_string())
+        };
+        let body: ManifestBody = serde_json::from_slice(content).map_err(invalid)?;
+        let media_type = match media_type.or_else(|| body.media_type()) {
+            Some(media_type) => media_type.to_string(),
+            None => return Ok(body),
+        };
+
+        match (MediaType::try_from(media_type)?, &body) {
+            (MediaType::ImageManifest, ManifestBody::Image(_))
+            | (MediaType::ImageIndex, ManifestBody::Index(_)) => Ok(body),
+            _ => Err(Error::new(
+                ErrorCode::ManifestInvalid,
+                "manifest content does not match its media type",
+            )),
+        }
+    }
+
+    pub fn media_type(&self) -> Option<&str> {
+        match self {
+            ManifestBody::Image(manifest) => manifest.media_type(),
+            ManifestBody::Index(index) => index.media_type(),
+        }
+    }
+
+    pub fn is_index(&self) -> bool {
+        matches!(self, ManifestBody::Index(_))
+    }
+
+    pub fn subject(&self) -> Option<&Descriptor> {
+        match self {
+            ManifestBody::Image(manifest) => manifest.subject(),
+            ManifestBody::Index(index) => index.subject(),
+        }
+    }
+
+    pub fn artifact_type(&self) -> Option<&str> {
+        match self {
+            ManifestBody::Image(manifest) => Some(manifest.artifact_type()),
+            ManifestBody::Index(index) => index.artifact_type(),
+        }
+    }
+
+    pub fn annotations(&self) -> Option<&HashMap<String, String>> {
+        match self {
+            ManifestBody::Image(manifest) => manifest.annotations(),
+            ManifestBody::Index(index) => index.annotations(),
+        }
+    }
+
+    /// The descriptors of the blobs referenced by this manifest.
+    /// Image indexes only reference other manifests, so they have none.
+    pub fn blobs(&self) -> Vec<&Descriptor> {
+        match self {
+            ManifestBody::Image(manifest) => {
+                let mut blobs = vec![manifest.config()];
+                blobs.extend(manifest.layers());
+                blobs
+            }
+            ManifestBody::Index(_) => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DOCKER_MANIFEST_LIST: &str = r#"{
+        "schemaVersion": 2,
+        "mediaType": "application/vnd.docker.distribution.manifest.list.v2+json",
+        "manifests": [
+            {
+                "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+                "digest": "sha256:e692418e4cbaf90ca69d05a66403747baa33ee08806650b51fab815ad7fc331f",
+                "size": 7143,
+                "platform": { "architecture": "ppc64le", "os": "linux" }
+            },
+            {
+                "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+                "digest": "sha256:5b0bcabd1ed22e9fb1310cf6c2dec7cdef19f0ad69efa1f392e94a4333501270",
+                "size": 7682,
+                "platform": { "architecture": "amd64", "os": "linux", "features": ["sse4"] }
+            }
+        ]
+    }"#;
+
+    const OCI_MANIFEST: &str = r#"{
+        "schemaVersion": 2,
+        "config": {
+            "mediaType": "application/vnd.oci.image.config.v1+json",
+            "digest": "sha256:b5b2b2c507a0944348e0303114d8d93aaaa081732b86451d9bce1f432a537bc7",
+            "size": 7023
+        },
+        "layers": [
+            {
+                "mediaType": "application/vnd.oci.image.layer.v1.tar+gzip",
+                "digest": "sha256:9834876dcfb05cb167a5c24953eba58c4ac89b1adf57f28f2f9d09af107ee8f0",
+                "size": 32654
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn it_parses_a_docker_manifest_list() {
+        let body = ManifestBody::parse(None, DOCKER_MANIFEST_LIST.as_bytes()).unwrap();
+
+        match body {
+            ManifestBody::Index(index) => {
+                assert_eq!(2, index.manifests().len());
+                let amd64 = index.find_platform("linux", "amd64").unwrap();
+                assert_eq!(
+                    "sha256:5b0bcabd1ed22e9fb1310cf6c2dec7cdef19f0ad69efa1f392e94a4333501270",
+                    amd64.digest().to_string()
+                );
+            }
+            ManifestBody::Image(_) => panic!("expected an image index"),
+        }
+    }
+
+    #[test]
+    fn it_parses_an_oci_manifest() {
+        let mime = MediaType::ImageManifest.to_string();
+        let body = ManifestBody::parse(Some(&mime), OCI_MANIFEST.as_bytes()).unwrap();
+
+        assert!(!body.is_index());
+    }
+
+    #[test]
+    fn it_lists_the_blobs_of_a_manifest() {
+        let body = ManifestBody::parse(None, OCI_MANIFEST.as_bytes()).unwrap();
+        let blobs: Vec<String> = body
+            .blobs()
+            .iter()
+            .map(|b| b.digest().to_string())
+            .collect();
+
+        assert_eq!(
+            vec![
+                "sha256:b5b2b2c507a0944348e0303114d8d93aaaa081732b86451d9bce1f432a537bc7",
+                "sha256:9834876dcfb05cb167a5c24953eba58c4ac89b1adf57f28f2f9d09af107ee8f0",
+            ],
+            blobs
+        );
+
+        let index = ManifestBody::parse(None, DOCKER_MANIFEST_LIST.as_bytes()).unwrap();
+        assert!(index.blobs().is_empty());
+    }
+
+    #[test]
+    fn it_parses_an_artifact_manifest_with_a_subject() {
+        let content = r#"{
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.manifest.v1+json",
+            "artifactType": "application/vnd.example.sbom.v1",
+            "config": {
+                "mediaType": "application/vnd.oci.empty.v1+json",
+                "digest": "sha256:44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a",
+                "size": 2
+            },
+            "layers": [
+                {
+                    "mediaType": "application/spdx+json",
+                    "digest": "sha256:9834876dcfb05cb167a5c24953eba58c4ac89b1adf57f28f2f9d09af107ee8f0",
+                    "size": 32654
+                }
+            ],
+            "subject": {
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "digest": "sha256:5b0bcabd1ed22e9fb1310cf6c2dec7cdef19f0ad69efa1f392e94a4333501270",
+                "size": 7682
+            }
+        }"#;
+        let body = ManifestBody::parse(None, content.as_bytes()).unwrap();
+
+        assert_eq!(
+            Some("application/vnd.example.sbom.v1"),
+            body.artifact_type()
+        );
+        assert_eq!(
+            "sha256:5b0bcabd1ed22e9fb1310cf6c2dec7cdef19f0ad69efa1f392e94a4333501270",
+            body.subject().unwrap().digest().to_string()
+        );
+    }
+
+    #[test]
+    fn it_rejects_a_manifest_with_a_mismatching_media_type() {
+        let mime = MediaType::ImageManifest.to_string();
+        let body = ManifestBody::parse(Some(&mime), DOCKER_MANIFEST_LIST.as_bytes());
+
+        assert!(body.is_err());
+    }
 }
@@ -0,0 +1,110 @@
+use crate::error::{Error, ErrorCode};
+use crate::Result;
+
+const MAX_NAME_LENGTH: usize = 255;
+
+/// Validates a repository name against the distribution spec grammar.
+/// A name is made of one or more `/`-separated path components, each one
+/// made of lowercase alphanumerics optionally separated by `.`, `_`, `__` or
+/// any number of `-`.
+pub fn validate(name: &str) -> Result<()> {
+    if name.is_empty() || name.len() > MAX_NAME_LENGTH || !name.split('/').all(is_valid_component) {
+        return Err(Error::from(ErrorCode::NameInvalid).with_detail("name", name));
+    }
+    Ok(())
+}
+
+/// Splits a full repository name into its namespace and its last path component.
+/// Single-component names have an empty namespace.
+pub fn split(name: &str) -> (&str, &str) {
+    match name.rfind('/') {
+        Some(idx) => (&name[..idx], &name[idx + 1..]),
+        None => ("", name),
+    }
+}
+
+/// Joins a namespace and a name into a full repository name.
+pub fn join(group: &str, name: &str) -> String {
+    if group.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", group, name)
+    }
+}
+
+fn is_valid_component(component: &str) -> bool {
+    let bytes = component.as_bytes();
+    let is_alphanumeric = |b: &u8| b.is_ascii_lowercase() || b.is_ascii_digit();
+    match (bytes.first(), bytes.last()) {
+        (Some(first), Some(last)) if is_alphanumeric(first) && is_alphanumeric(last) => {}
+        _ => return false,
+    }
+
+    let mut separator = String::new();
+    for b in bytes {
+        if is_alphanumeric(b) {
+            if !is_valid_separator(&separator) {
+                return false;
+            }
+            separator.clear();
+        } else {
+            separator.push(*b as char);
+        }
+    }
+    true
+}
+
+fn is_valid_separator(separator: &str) -> bool {
+    match separator {
+        "" | "." | "_" | "__" => true,
+        sep => sep.chars().all(|c| c == '-'),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_accepts_valid_names() {
+        for name in &[
+            "alpine",
+            "library/alpine",
+            "org/team/service",
+            "my-org/my.app_v2/sub__dir/a--b",
+            "retention/alpine",
+            "org/manifest",
+            "org/retention",
+            "org/manifests/alpine",
+            "manifests",
+        ] {
+            assert!(validate(name).is_ok(), "{} should be valid", name);
+        }
+    }
+
+    #[test]
+    fn it_rejects_invalid_names() {
+        for name in &[
+            "",
+            "Alpine",
+            "/alpine",
+            "alpine/",
+            "org//alpine",
+            "-alpine",
+            "alpine..v2",
+            "alpine___v2",
+            "alpine_-v2",
+            "org/-/retention",
+        ] {
+            assert!(validate(name).is_err(), "{} should be invalid", name);
+        }
+    }
+
+    #[test]
+    fn it_splits_a_name() {
+        assert_eq!(split("org/team/service"), ("org/team", "service"));
+        assert_eq!(split("alpine"), ("", "alpine"));
+        assert_eq!(join("org/team", "service"), "org/team/service");
+        assert_eq!(join("", "alpine"), "alpine");
+    }
+}
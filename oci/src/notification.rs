@@ -0,0 +1,786 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::{future, TryStreamExt};
+use glob::Pattern;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use enseada::couchdb::db::Database;
+use enseada::couchdb::repository::{Entity, Repository};
+use enseada::guid::Guid;
+use enseada::pagination::Page;
+use enseada::retry::{retry, Backoff};
+use events::EventHandler;
+
+use crate::digest::Digest;
+use crate::error::{Error, ErrorCode};
+use crate::events::{
+    BlobDeleted, BlobPulled, BlobPushed, ManifestDeleted, ManifestPulled, ManifestPushed,
+};
+use crate::Result;
+
+/// The media type of the notification envelopes of the Docker distribution registry.
+pub const ENVELOPE_MEDIA_TYPE: &str = "application/vnd.docker.distribution.events.v1+json";
+
+/// The media type of blobs in notifications, as blobs carry no media type of their own.
+const BLOB_MEDIA_TYPE: &str = "application/octet-stream";
+
+/// How long the list of enabled endpoints is reused before being queried again.
+const ENDPOINTS_TTL: Duration = Duration::from_secs(60);
+
+/// How long deliveries are kept in the log.
+const DELIVERY_MAX_AGE_DAYS: i64 = 7;
+
+/// How often deliveries older than [`DELIVERY_MAX_AGE_DAYS`] are pruned.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Push,
+    Pull,
+    Delete,
+}
+
+/// An HTTP endpoint notified of the registry events it is interested in.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Endpoint {
+    #[serde(rename = "_id")]
+    id: Guid,
+    #[serde(rename = "_rev", skip_serializing_if = "Option::is_none")]
+    rev: Option<String>,
+    name: String,
+    url: String,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    actions: Vec<Action>,
+    #[serde(default)]
+    repositories: Vec<String>,
+    #[serde(default)]
+    ignored_media_types: Vec<String>,
+    timeout: u64,
+    enabled: bool,
+}
+
+impl Endpoint {
+    /// Creates an enabled endpoint, notified of every event.
+    pub fn new(name: &str, url: &str) -> Self {
+        Self {
+            id: Self::build_guid(name),
+            rev: None,
+            name: name.to_string(),
+            url: url.to_string(),
+            headers: HashMap::new(),
+            actions: Vec::new(),
+            repositories: Vec::new(),
+            ignored_media_types: Vec::new(),
+            timeout: 5,
+            enabled: true,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn set_url(&mut self, url: &str) -> &mut Self {
+        self.url = url.to_string();
+        self
+    }
+
+    /// Headers added to every notification request, e.g. for authentication.
+    pub fn headers(&self) -> &HashMap<String, String> {
+        &self.headers
+    }
+
+    pub fn set_headers(&mut self, headers: HashMap<String, String>) -> &mut Self {
+        self.headers = headers;
+        self
+    }
+
+    /// The actions the endpoint is notified of. All of them if empty.
+    pub fn actions(&self) -> &[Action] {
+        &self.actions
+    }
+
+    pub fn set_actions(&mut self, actions: Vec<Action>) -> &mut Self {
+        self.actions = actions;
+        self
+    }
+
+    /// Glob patterns on the full name of the repositories the endpoint
+    /// is notified of, e.g. `library/*`. All of them if empty.
+    pub fn repositories(&self) -> &[String] {
+        &self.repositories
+    }
+
+    pub fn set_repositories(&mut self, repositories: Vec<String>) -> &mut Self {
+        self.repositories = repositories;
+        self
+    }
+
+    /// Media types of the targets the endpoint is not notified of,
+    /// e.g. `application/octet-stream` to skip blob events.
+    pub fn ignored_media_types(&self) -> &[String] {
+        &self.ignored_media_types
+    }
+
+    pub fn set_ignored_media_types(&mut self, media_types: Vec<String>) -> &mut Self {
+        self.ignored_media_types = media_types;
+        self
+    }
+
+    /// Timeout of each delivery attempt, in seconds.
+    pub fn timeout(&self) -> u64 {
+        self.timeout
+    }
+
+    pub fn set_timeout(&mut self, timeout: u64) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) -> &mut Self {
+        self.enabled = enabled;
+        self
+    }
+
+    pub fn accepts(&self, event: &Notification) -> bool {
+        let action = self.actions.is_empty() || self.actions.contains(&event.action);
+        let repository = self.repositories.is_empty()
+            || self.repositories.iter().any(|pattern| {
+                Pattern::new(pattern)
+                    .map(|pattern| pattern.matches(&event.target.repository))
+                    .unwrap_or(false)
+            });
+        let media_type = match &event.target.media_type {
+            Some(media_type) => !self.ignored_media_types.contains(media_type),
+            None => true,
+        };
+        action && repository && media_type
+    }
+}
+
+impl Entity for Endpoint {
+    fn build_guid(id: &str) -> Guid {
+        Guid::partitioned("oci_notification_endpoint", id)
+    }
+
+    fn id(&self) -> &Guid {
+        &self.id
+    }
+
+    fn rev(&self) -> Option<&str> {
+        self.rev.as_deref()
+    }
+
+    fn set_rev(&mut self, rev: String) -> &mut Self {
+        self.rev = Some(rev);
+        self
+    }
+}
+
+/// The body of a notification request. It can hold several events,
+/// but each request currently carries a single one.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Envelope {
+    events: Vec<Notification>,
+}
+
+impl Envelope {
+    pub fn events(&self) -> &[Notification] {
+        &self.events
+    }
+}
+
+/// A registry event, in the format of the Docker distribution registry.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Notification {
+    id: String,
+    timestamp: DateTime<Utc>,
+    action: Action,
+    target: Target,
+    source: Source,
+}
+
+impl Notification {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn action(&self) -> Action {
+        self.action
+    }
+
+    pub fn target(&self) -> &Target {
+        &self.target
+    }
+}
+
+/// The manifest or blob an event refers to.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Target {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    media_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<usize>,
+    digest: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    length: Option<usize>,
+    repository: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag: Option<String>,
+}
+
+impl Target {
+    pub fn digest(&self) -> &str {
+        &self.digest
+    }
+
+    pub fn repository(&self) -> &str {
+        &self.repository
+    }
+
+    pub fn tag(&self) -> Option<&str> {
+        self.tag.as_deref()
+    }
+}
+
+/// The registry instance that generated an event.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Source {
+    addr: String,
+    #[serde(rename = "instanceID")]
+    instance_id: String,
+}
+
+/// The outcome of the delivery of an event to an endpoint.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Delivery {
+    #[serde(rename = "_id")]
+    id: Guid,
+    #[serde(rename = "_rev", skip_serializing_if = "Option::is_none")]
+    rev: Option<String>,
+    endpoint: String,
+    event: String,
+    action: Action,
+    repository: String,
+    digest: String,
+    attempts: u32,
+    delivered: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+impl Delivery {
+    fn new(endpoint: &Endpoint, event: &Notification, attempts: u32, outcome: Result<()>) -> Self {
+        Self {
+            id: Self::build_guid(&Uuid::new_v4().to_string()),
+            rev: None,
+            endpoint: endpoint.name().to_string(),
+            event: event.id.clone(),
+            action: event.action,
+            repository: event.target.repository.clone(),
+            digest: event.target.digest.clone(),
+            attempts,
+            delivered: outcome.is_ok(),
+            error: outcome.err().map(|err| err.to_string()),
+            created_at: Utc::now(),
+        }
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    pub fn event(&self) -> &str {
+        &self.event
+    }
+
+    pub fn action(&self) -> Action {
+        self.action
+    }
+
+    pub fn repository(&self) -> &str {
+        &self.repository
+    }
+
+    pub fn digest(&self) -> &str {
+        &self.digest
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    pub fn is_delivered(&self) -> bool {
+        self.delivered
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    pub fn created_at(&self) -> &DateTime<Utc> {
+        &self.created_at
+    }
+}
+
+impl Entity for Delivery {
+    fn build_guid(id: &str) -> Guid {
+        Guid::partitioned("oci_notification_delivery", id)
+    }
+
+    fn id(&self) -> &Guid {
+        &self.id
+    }
+
+    fn rev(&self) -> Option<&str> {
+        self.rev.as_deref()
+    }
+
+    fn set_rev(&mut self, rev: String) -> &mut Self {
+        self.rev = Some(rev);
+        self
+    }
+}
+
+#[derive(Debug)]
+struct DeliveryLog {
+    db: Arc<Database>,
+}
+
+#[async_trait]
+impl Repository<Delivery> for DeliveryLog {
+    fn db(&self) -> &Database {
+        self.db.as_ref()
+    }
+}
+
+/// Notifies the registered endpoints of pushes, pulls and deletions of
+/// manifests and blobs. Failed deliveries are retried with backoff, and
+/// the outcome of every delivery is logged for [`DELIVERY_MAX_AGE_DAYS`].
+///
+/// The enabled endpoints are cached for [`ENDPOINTS_TTL`], or until they
+/// are changed through this service.
+#[derive(Debug)]
+pub struct NotificationService {
+    db: Arc<Database>,
+    deliveries: DeliveryLog,
+    endpoints: RwLock<Option<(Instant, Arc<Vec<Endpoint>>)>>,
+    pruned_at: Mutex<Option<Instant>>,
+    client: Client,
+    backoff: Backoff,
+    registry_url: String,
+    source: Source,
+}
+
+impl NotificationService {
+    /// Creates the service of the registry served at `registry_url`,
+    /// e.g. `https://containers.example.com`.
+    pub fn new(db: Arc<Database>, registry_url: &str) -> Self {
+        let addr = url::Url::parse(registry_url)
+            .ok()
+            .and_then(|url| {
+                url.host_str().map(|host| match url.port() {
+                    Some(port) => format!("{}:{}", host, port),
+                    None => host.to_string(),
+                })
+            })
+            .unwrap_or_else(|| registry_url.to_string());
+        Self {
+            deliveries: DeliveryLog { db: db.clone() },
+            db,
+            endpoints: RwLock::new(None),
+            pruned_at: Mutex::new(None),
+            client: Client::new(),
+            backoff: Backoff::default(),
+            registry_url: registry_url.trim_end_matches('/').to_string(),
+            source: Source {
+                addr,
+                instance_id: Uuid::new_v4().to_string(),
+            },
+        }
+    }
+
+    pub async fn find_endpoint(&self, name: &str) -> Result<Option<Endpoint>> {
+        self.find(name).await.map_err(Error::from)
+    }
+
+    /// Lists the deliveries to an endpoint.
+    pub async fn list_deliveries(
+        &self,
+        endpoint: &str,
+        limit: usize,
+        offset: usize,
+    ) -> Result<Page<Delivery>> {
+        self.deliveries
+            .find_all(limit, offset, serde_json::json!({ "endpoint": endpoint }))
+            .await
+            .map_err(Error::from)
+    }
+
+    /// Deletes the deliveries logged before `cutoff` and returns their count.
+    pub async fn prune_deliveries(&self, cutoff: &DateTime<Utc>) -> Result<usize> {
+        let expired: Vec<Delivery> = self
+            .deliveries
+            .find_all_stream(serde_json::json!({ "created_at": { "$lt": cutoff } }))
+            .try_collect()
+            .await?;
+        for delivery in &expired {
+            self.deliveries.delete(delivery).await?;
+        }
+        Ok(expired.len())
+    }
+
+    /// Sends an event to an endpoint, without retrying.
+    pub async fn send(&self, endpoint: &Endpoint, event: &Notification) -> Result<()> {
+        let envelope = Envelope {
+            events: vec![event.clone()],
+        };
+        let mut req = self
+            .client
+            .post(endpoint.url())
+            .timeout(Duration::from_secs(endpoint.timeout()))
+            .header(CONTENT_TYPE, ENVELOPE_MEDIA_TYPE)
+            .json(&envelope);
+        for (name, value) in endpoint.headers() {
+            req = req.header(name.as_str(), value.as_str());
+        }
+
+        let res = req.send().await.map_err(|err| {
+            Error::new(
+                ErrorCode::Internal,
+                format!("notification request failed: {}", err),
+            )
+        })?;
+        if !res.status().is_success() {
+            return Err(Error::new(
+                ErrorCode::Internal,
+                format!("endpoint returned {}", res.status()),
+            ));
+        }
+        Ok(())
+    }
+
+    async fn deliver(&self, endpoint: &Endpoint, event: &Notification) {
+        let attempts = AtomicU32::new(0);
+        let outcome = retry(&self.backoff, || {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            self.send(endpoint, event)
+        })
+        .await;
+        if let Err(err) = &outcome {
+            log::error!(
+                "notification {} not delivered to {}: {}",
+                event.id(),
+                endpoint.name(),
+                err
+            );
+        }
+
+        let delivery = Delivery::new(endpoint, event, attempts.into_inner(), outcome);
+        if let Err(err) = self.deliveries.save(delivery).await {
+            log::error!("failed to log notification delivery: {}", err);
+        }
+    }
+
+    async fn enabled_endpoints(&self) -> Result<Arc<Vec<Endpoint>>> {
+        if let Some((fetched_at, endpoints)) = &*self
+            .endpoints
+            .read()
+            .expect("enabled_endpoints() endpoints unlock")
+        {
+            if fetched_at.elapsed() < ENDPOINTS_TTL {
+                return Ok(endpoints.clone());
+            }
+        }
+
+        let endpoints: Vec<Endpoint> = self
+            .find_all_stream(serde_json::json!({ "enabled": true }))
+            .try_collect()
+            .await?;
+        let endpoints = Arc::new(endpoints);
+        *self
+            .endpoints
+            .write()
+            .expect("enabled_endpoints() endpoints unlock") =
+            Some((Instant::now(), endpoints.clone()));
+        Ok(endpoints)
+    }
+
+    fn invalidate_endpoints(&self) {
+        *self
+            .endpoints
+            .write()
+            .expect("invalidate_endpoints() endpoints unlock") = None;
+    }
+
+    async fn prune_expired_deliveries(&self) {
+        {
+            let mut pruned_at = self
+                .pruned_at
+                .lock()
+                .expect("prune_expired_deliveries() pruned_at unlock");
+            if pruned_at.map_or(false, |at| at.elapsed() < PRUNE_INTERVAL) {
+                return;
+            }
+            *pruned_at = Some(Instant::now());
+        }
+
+        let cutoff = Utc::now() - chrono::Duration::days(DELIVERY_MAX_AGE_DAYS);
+        match self.prune_deliveries(&cutoff).await {
+            Ok(0) => {}
+            Ok(count) => log::debug!("pruned {} notification deliveries", count),
+            Err(err) => log::error!("failed to prune notification deliveries: {}", err),
+        }
+    }
+
+    async fn notify(&self, action: Action, target: Target) {
+        let endpoints = match self.enabled_endpoints().await {
+            Ok(endpoints) => endpoints,
+            Err(err) => {
+                log::error!("failed to list notification endpoints: {}", err);
+                return;
+            }
+        };
+
+        let event = Notification {
+            id: Uuid::new_v4().to_string(),
+            timestamp: Utc::now(),
+            action,
+            target,
+            source: self.source.clone(),
+        };
+        let deliveries = endpoints
+            .iter()
+            .filter(|endpoint| endpoint.accepts(&event))
+            .map(|endpoint| self.deliver(endpoint, &event));
+        future::join_all(deliveries).await;
+
+        self.prune_expired_deliveries().await;
+    }
+
+    fn manifest_target(
+        &self,
+        image: &str,
+        reference: &str,
+        digest: &Digest,
+        media_type: Option<&str>,
+        size: Option<usize>,
+    ) -> Target {
+        let tag = if Digest::try_from(reference).is_err() {
+            Some(reference.to_string())
+        } else {
+            None
+        };
+        Target {
+            media_type: media_type.map(str::to_string),
+            size,
+            digest: digest.to_string(),
+            length: size,
+            repository: image.to_string(),
+            url: Some(format!(
+                "{}/v2/{}/manifests/{}",
+                &self.registry_url, image, digest
+            )),
+            tag,
+        }
+    }
+
+    fn blob_target(&self, image: &str, digest: &Digest, size: Option<usize>) -> Target {
+        Target {
+            media_type: Some(BLOB_MEDIA_TYPE.to_string()),
+            size,
+            digest: digest.to_string(),
+            length: size,
+            repository: image.to_string(),
+            url: Some(format!(
+                "{}/v2/{}/blobs/{}",
+                &self.registry_url, image, digest
+            )),
+            tag: None,
+        }
+    }
+}
+
+#[async_trait]
+impl Repository<Endpoint> for NotificationService {
+    fn db(&self) -> &Database {
+        self.db.as_ref()
+    }
+
+    async fn created(&self, _endpoint: &Endpoint) {
+        self.invalidate_endpoints();
+    }
+
+    async fn updated(&self, _endpoint: &Endpoint) {
+        self.invalidate_endpoints();
+    }
+
+    async fn deleted(&self, _endpoint: &Endpoint) {
+        self.invalidate_endpoints();
+    }
+}
+
+#[async_trait]
+impl EventHandler<ManifestPushed> for NotificationService {
+    async fn handle(&self, event: &ManifestPushed) {
+        let target = self.manifest_target(
+            &event.image,
+            &event.reference,
+            &event.digest,
+            Some(&event.media_type),
+            Some(event.size),
+        );
+        self.notify(Action::Push, target).await
+    }
+}
+
+#[async_trait]
+impl EventHandler<ManifestPulled> for NotificationService {
+    async fn handle(&self, event: &ManifestPulled) {
+        let target = self.manifest_target(
+            &event.image,
+            &event.reference,
+            &event.digest,
+            Some(&event.media_type),
+            Some(event.size),
+        );
+        self.notify(Action::Pull, target).await
+    }
+}
+
+#[async_trait]
+impl EventHandler<ManifestDeleted> for NotificationService {
+    async fn handle(&self, event: &ManifestDeleted) {
+        let target =
+            self.manifest_target(&event.image, &event.reference, &event.digest, None, None);
+        self.notify(Action::Delete, target).await
+    }
+}
+
+#[async_trait]
+impl EventHandler<BlobPushed> for NotificationService {
+    async fn handle(&self, event: &BlobPushed) {
+        let target = self.blob_target(&event.image, &event.digest, event.size);
+        self.notify(Action::Push, target).await
+    }
+}
+
+#[async_trait]
+impl EventHandler<BlobPulled> for NotificationService {
+    async fn handle(&self, event: &BlobPulled) {
+        let target = self.blob_target(&event.image, &event.digest, event.size);
+        self.notify(Action::Pull, target).await
+    }
+}
+
+#[async_trait]
+impl EventHandler<BlobDeleted> for NotificationService {
+    async fn handle(&self, event: &BlobDeleted) {
+        let target = self.blob_target(&event.image, &event.digest, None);
+        self.notify(Action::Delete, target).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn event(action: Action, repository: &str, media_type: &str) -> Notification {
+        Notification {
+            id: "1".to_string(),
+            timestamp: Utc::now(),
+            action,
+            target: Target {
+                media_type: Some(media_type.to_string()),
+                repository: repository.to_string(),
+                ..Target::default()
+            },
+            source: Source {
+                addr: "containers.localhost".to_string(),
+                instance_id: "1".to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn it_filters_events_by_action_repository_and_media_type() {
+        let mut endpoint = Endpoint::new("scanner", "https://scanner.example.com/events");
+        assert!(endpoint.accepts(&event(Action::Pull, "library/alpine", BLOB_MEDIA_TYPE)));
+
+        endpoint
+            .set_actions(vec![Action::Push])
+            .set_repositories(vec!["library/*".to_string()])
+            .set_ignored_media_types(vec![BLOB_MEDIA_TYPE.to_string()]);
+        let manifest = "application/vnd.oci.image.manifest.v1+json";
+        assert!(endpoint.accepts(&event(Action::Push, "library/alpine", manifest)));
+        assert!(!endpoint.accepts(&event(Action::Pull, "library/alpine", manifest)));
+        assert!(!endpoint.accepts(&event(Action::Push, "other/alpine", manifest)));
+        assert!(!endpoint.accepts(&event(Action::Push, "library/alpine", BLOB_MEDIA_TYPE)));
+    }
+
+    #[test]
+    fn it_serializes_events_in_the_distribution_format() {
+        let service_url = "https://containers.example.com:5000";
+        let digest = Digest::compute(crate::digest::DigestAlgorithm::Sha256, b"{}");
+        let target = Target {
+            media_type: Some("application/vnd.docker.distribution.manifest.v2+json".to_string()),
+            size: Some(2),
+            digest: digest.to_string(),
+            length: Some(2),
+            repository: "library/alpine".to_string(),
+            url: Some(format!(
+                "{}/v2/library/alpine/manifests/{}",
+                service_url, digest
+            )),
+            tag: Some("latest".to_string()),
+        };
+        let envelope = Envelope {
+            events: vec![Notification {
+                id: "1".to_string(),
+                timestamp: Utc::now(),
+                action: Action::Push,
+                target,
+                source: Source {
+                    addr: "containers.example.com:5000".to_string(),
+                    instance_id: "1".to_string(),
+                },
+            }],
+        };
+
+        let json = serde_json::to_value(&envelope).unwrap();
+        let event = &json["events"][0];
+        assert_eq!(event["action"], "push");
+        assert_eq!(
+            event["target"]["mediaType"],
+            "application/vnd.docker.distribution.manifest.v2+json"
+        );
+        assert_eq!(event["target"]["repository"], "library/alpine");
+        assert_eq!(event["target"]["tag"], "latest");
+        assert_eq!(event["target"]["length"], 2);
+        assert_eq!(event["source"]["instanceID"], "1");
+    }
+}
@@ -0,0 +1,324 @@
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::TryStreamExt;
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
+
+use enseada::couchdb::db::Database;
+use enseada::couchdb::repository::{Entity, Repository};
+use enseada::guid::Guid;
+use enseada::replication::{Remote, ReplicationStatus};
+use enseada::retry::{retry, Backoff};
+use enseada::storage::Provider;
+use events::{EventBus, EventHandler};
+
+use crate::client::RegistryClient;
+use crate::entity::Manifest;
+use crate::error::{Error, ErrorCode};
+use crate::events::ManifestPushed;
+use crate::manifest::ManifestBody;
+use crate::service::{BlobService, ManifestService};
+use crate::{name, Result};
+
+/// A rule copying the manifests pushed to the matching repositories,
+/// along with their blobs, to a remote registry.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ReplicationRule {
+    #[serde(rename = "_id")]
+    id: Guid,
+    #[serde(rename = "_rev", skip_serializing_if = "Option::is_none")]
+    rev: Option<String>,
+    name: String,
+    repositories: Vec<String>,
+    target: Remote,
+    namespace: Option<String>,
+    enabled: bool,
+    #[serde(default)]
+    status: ReplicationStatus,
+}
+
+impl ReplicationRule {
+    /// Creates an enabled rule. Repositories are selected by glob patterns
+    /// on their full name, e.g. `library/*`.
+    pub fn new(name: &str, repositories: Vec<String>, target: Remote) -> Self {
+        Self {
+            id: Self::build_guid(name),
+            rev: None,
+            name: name.to_string(),
+            repositories,
+            target,
+            namespace: None,
+            enabled: true,
+            status: ReplicationStatus::default(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn repositories(&self) -> &[String] {
+        &self.repositories
+    }
+
+    pub fn set_repositories(&mut self, repositories: Vec<String>) -> &mut Self {
+        self.repositories = repositories;
+        self
+    }
+
+    pub fn target(&self) -> &Remote {
+        &self.target
+    }
+
+    pub fn set_target(&mut self, target: Remote) -> &mut Self {
+        self.target = target;
+        self
+    }
+
+    /// The namespace images are pushed under on the remote registry, if any.
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    pub fn set_namespace(&mut self, namespace: Option<String>) -> &mut Self {
+        self.namespace = namespace;
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) -> &mut Self {
+        self.enabled = enabled;
+        self
+    }
+
+    pub fn status(&self) -> &ReplicationStatus {
+        &self.status
+    }
+
+    pub fn matches(&self, image: &str) -> bool {
+        self.repositories.iter().any(|pattern| {
+            Pattern::new(pattern)
+                .map(|pattern| pattern.matches(image))
+                .unwrap_or(false)
+        })
+    }
+
+    /// The name of an image on the remote registry.
+    pub fn remote_name(&self, image: &str) -> String {
+        match &self.namespace {
+            Some(namespace) => name::join(namespace, image),
+            None => image.to_string(),
+        }
+    }
+}
+
+impl Entity for ReplicationRule {
+    fn build_guid(id: &str) -> Guid {
+        Guid::partitioned("oci_replication", id)
+    }
+
+    fn id(&self) -> &Guid {
+        &self.id
+    }
+
+    fn rev(&self) -> Option<&str> {
+        self.rev.as_deref()
+    }
+
+    fn set_rev(&mut self, rev: String) -> &mut Self {
+        self.rev = Some(rev);
+        self
+    }
+}
+
+/// Replicates pushed manifests according to the enabled replication rules.
+/// Failed replications are retried with backoff, and their outcome is
+/// recorded in the status of the rule.
+#[derive(Debug)]
+pub struct ReplicationService {
+    db: Arc<Database>,
+    manifests: ManifestService,
+    blobs: BlobService,
+    client: RegistryClient,
+    backoff: Backoff,
+}
+
+impl ReplicationService {
+    pub fn new(db: Arc<Database>, bus: Arc<RwLock<EventBus>>, store: Arc<Provider>) -> Self {
+        Self {
+            manifests: ManifestService::new(db.clone(), bus.clone()),
+            blobs: BlobService::new(db.clone(), bus.clone(), store),
+            db,
+            client: RegistryClient::new(),
+            backoff: Backoff::default(),
+        }
+    }
+
+    pub async fn find_rule(&self, name: &str) -> Result<Option<ReplicationRule>> {
+        self.find(name).await.map_err(Error::from)
+    }
+
+    /// Copies a manifest to the target of a rule. The blobs and, for image
+    /// indexes, the child manifests already present on the remote are skipped.
+    pub async fn replicate(
+        &self,
+        rule: &ReplicationRule,
+        image: &str,
+        reference: &str,
+    ) -> Result<()> {
+        let manifest = self
+            .manifests
+            .find_by_ref(image, reference)
+            .await?
+            .ok_or_else(|| Error::from(ErrorCode::ManifestUnknown))?;
+        let remote_name = rule.remote_name(image);
+        log::info!(
+            "replicating {}:{} to {}/{}",
+            image,
+            reference,
+            rule.target().url(),
+            &remote_name
+        );
+
+        if let ManifestBody::Index(index) = manifest.manifest() {
+            for child in index.manifests() {
+                let digest = child.digest().to_string();
+                let child = self
+                    .manifests
+                    .find_by_ref(image, &digest)
+                    .await?
+                    .ok_or_else(|| {
+                        Error::from(ErrorCode::ManifestBlobUnknown).with_detail("digest", digest)
+                    })?;
+                self.push_manifest(rule.target(), &remote_name, &child)
+                    .await?;
+            }
+        }
+        self.push_manifest(rule.target(), &remote_name, &manifest)
+            .await
+    }
+
+    async fn push_manifest(
+        &self,
+        target: &Remote,
+        remote_name: &str,
+        manifest: &Manifest,
+    ) -> Result<()> {
+        let digest = manifest.digest().to_string();
+        let tagged = manifest.reference() != digest;
+        if !tagged
+            && self
+                .client
+                .has_manifest(target, remote_name, &digest)
+                .await?
+        {
+            log::debug!("manifest {} is already replicated", &digest);
+            return Ok(());
+        }
+
+        for blob in manifest.manifest().blobs() {
+            if self
+                .client
+                .has_blob(target, remote_name, blob.digest())
+                .await?
+            {
+                continue;
+            }
+            let content = self.blobs.fetch_content(blob.digest()).await?;
+            self.client
+                .push_blob(target, remote_name, blob.digest(), blob.size(), content)
+                .await?;
+        }
+
+        let content = Bytes::from(manifest.content().to_string());
+        self.client
+            .push_manifest(
+                target,
+                remote_name,
+                manifest.reference(),
+                manifest.media_type(),
+                content,
+            )
+            .await
+    }
+
+    async fn record(&self, rule: &ReplicationRule, artifact: String, outcome: Result<()>) {
+        let mut rule = match self.find_rule(rule.name()).await {
+            Ok(Some(rule)) => rule,
+            Ok(None) => return,
+            Err(err) => {
+                log::error!("failed to fetch replication rule {}: {}", rule.name(), err);
+                return;
+            }
+        };
+        match outcome {
+            Ok(()) => rule.status.record_success(artifact),
+            Err(err) => {
+                log::error!("replication of {} failed: {}", &artifact, err);
+                rule.status.record_failure(artifact, err.to_string())
+            }
+        };
+        if let Err(err) = self.save(rule).await {
+            log::error!("failed to save replication status: {}", err);
+        }
+    }
+}
+
+#[async_trait]
+impl Repository<ReplicationRule> for ReplicationService {
+    fn db(&self) -> &Database {
+        self.db.as_ref()
+    }
+}
+
+#[async_trait]
+impl EventHandler<ManifestPushed> for ReplicationService {
+    async fn handle(&self, event: &ManifestPushed) {
+        let rules: Vec<ReplicationRule> = match self
+            .find_all_stream(serde_json::json!({ "enabled": true }))
+            .try_collect()
+            .await
+        {
+            Ok(rules) => rules,
+            Err(err) => {
+                log::error!("failed to list replication rules: {}", err);
+                return;
+            }
+        };
+
+        let artifact = format!("{}:{}", &event.image, &event.reference);
+        for rule in rules.iter().filter(|rule| rule.matches(&event.image)) {
+            let outcome = retry(&self.backoff, || {
+                self.replicate(rule, &event.image, &event.reference)
+            })
+            .await;
+            self.record(rule, artifact.clone(), outcome).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_matches_repositories_and_prefixes_the_namespace() {
+        let mut rule = ReplicationRule::new(
+            "dr",
+            vec!["library/*".to_string()],
+            Remote::new("https://dr.example.com/"),
+        );
+        assert_eq!(rule.target().url(), "https://dr.example.com");
+        assert!(rule.matches("library/alpine"));
+        assert!(!rule.matches("team/alpine"));
+        assert_eq!(rule.remote_name("library/alpine"), "library/alpine");
+
+        rule.set_namespace(Some("mirror".to_string()));
+        assert_eq!(rule.remote_name("library/alpine"), "mirror/library/alpine");
+    }
+}
@@ -0,0 +1,412 @@
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, Duration, Utc};
+use futures::TryStreamExt;
+use glob::Pattern;
+use serde::Serialize;
+
+use enseada::couchdb::db::Database;
+use enseada::couchdb::repository::Repository;
+use events::EventBus;
+
+use crate::digest::Digest;
+use crate::entity::{Manifest, Repo, RetentionRule};
+use crate::manifest::ManifestBody;
+use crate::service::{ManifestService, RepoService};
+use crate::Result;
+
+/// What the retention rules of a repository would delete.
+#[derive(Debug, Default, Serialize)]
+pub struct RetentionPlan {
+    tags: Vec<String>,
+    manifests: Vec<String>,
+    #[serde(skip)]
+    deletions: Vec<Manifest>,
+}
+
+impl RetentionPlan {
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    pub fn manifests(&self) -> &[String] {
+        &self.manifests
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deletions.is_empty()
+    }
+}
+
+/// The outcome of applying the retention rules of all repositories.
+/// In dry-run mode it lists what would have been deleted.
+#[derive(Debug, Default, Serialize)]
+pub struct RetentionReport {
+    dry_run: bool,
+    repositories: Vec<RepoRetention>,
+}
+
+/// The retention of a repository. If it failed, the error is reported
+/// and the other repositories are processed anyway.
+#[derive(Debug, Serialize)]
+pub struct RepoRetention {
+    name: String,
+    #[serde(flatten)]
+    plan: RetentionPlan,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Applies the retention rules configured on repositories.
+/// Deletions go through the manifest service, so that they are broadcast as events.
+#[derive(Debug)]
+pub struct RetentionService {
+    repos: RepoService,
+    manifests: ManifestService,
+}
+
+impl RetentionService {
+    pub fn new(db: Arc<Database>, bus: Arc<RwLock<EventBus>>) -> Self {
+        Self {
+            repos: RepoService::new(db.clone(), bus.clone()),
+            manifests: ManifestService::new(db, bus),
+        }
+    }
+
+    /// Evaluates the retention rules of a repository, without deleting anything.
+    pub async fn plan(&self, repo: &Repo) -> Result<RetentionPlan> {
+        if repo.retention().is_empty() {
+            return Ok(RetentionPlan::default());
+        }
+
+        let manifests: Vec<Manifest> = self
+            .manifests
+            .find_all_stream(serde_json::json!({
+                "image": repo.full_name(),
+            }))
+            .try_collect()
+            .await?;
+        Ok(plan_retention(repo, manifests, &Utc::now()))
+    }
+
+    /// Deletes the tags and manifests selected by a retention plan.
+    pub async fn apply(&self, repo: &Repo, plan: &RetentionPlan) -> Result<()> {
+        for manifest in &plan.deletions {
+            log::debug!(
+                "deleting manifest {}:{} by retention policy",
+                manifest.image(),
+                manifest.reference()
+            );
+            self.manifests.delete_manifest(repo, manifest).await?;
+        }
+        Ok(())
+    }
+
+    /// Applies the retention rules of all repositories.
+    pub async fn run(&self, dry_run: bool) -> Result<RetentionReport> {
+        log::info!("applying OCI retention policies (dry run: {})", dry_run);
+        let mut report = RetentionReport {
+            dry_run,
+            ..Default::default()
+        };
+
+        // repositories are collected first, because deletions could
+        // shift the pages of the stream
+        let repos: Vec<Repo> = self
+            .repos
+            .find_all_stream(serde_json::json!({
+                "_id": {
+                    "$gt": null,
+                }
+            }))
+            .try_filter(|repo: &Repo| futures::future::ready(!repo.retention().is_empty()))
+            .try_collect()
+            .await?;
+
+        for repo in repos {
+            let name = repo.full_name();
+            let plan = match self.plan(&repo).await {
+                Ok(plan) => plan,
+                Err(err) => {
+                    log::error!(
+                        "failed to evaluate the retention rules of {}: {}",
+                        name,
+                        err
+                    );
+                    report.repositories.push(RepoRetention {
+                        name,
+                        plan: RetentionPlan::default(),
+                        error: Some(err.to_string()),
+                    });
+                    continue;
+                }
+            };
+            if plan.is_empty() {
+                continue;
+            }
+            let error = if dry_run {
+                None
+            } else {
+                self.apply(&repo, &plan).await.err().map(|err| {
+                    log::error!("failed to apply the retention rules of {}: {}", name, err);
+                    err.to_string()
+                })
+            };
+            report
+                .repositories
+                .push(RepoRetention { name, plan, error });
+        }
+
+        log::info!(
+            "OCI retention policies applied to {} repositories",
+            report.repositories.len()
+        );
+        Ok(report)
+    }
+}
+
+/// Selects the tags and manifests of a repository to be deleted by its retention rules.
+fn plan_retention(repo: &Repo, manifests: Vec<Manifest>, now: &DateTime<Utc>) -> RetentionPlan {
+    let mut plan = RetentionPlan::default();
+    let (tags, untagged): (Vec<Manifest>, Vec<Manifest>) = manifests
+        .into_iter()
+        .partition(|manifest| Digest::try_from(manifest.reference()).is_err());
+
+    let mut expired_tags = HashSet::new();
+    for rule in repo.retention() {
+        match rule {
+            RetentionRule::KeepLastTags { pattern, count } => {
+                let pattern = match Pattern::new(pattern) {
+                    Ok(pattern) => pattern,
+                    Err(err) => {
+                        log::warn!("invalid retention pattern '{}': {}", pattern, err);
+                        continue;
+                    }
+                };
+                let mut matching: Vec<&Manifest> = tags
+                    .iter()
+                    .filter(|tag| pattern.matches(tag.reference()))
+                    .collect();
+                matching.sort_by(|a, b| b.pushed_at().cmp(&a.pushed_at()));
+                expired_tags.extend(
+                    matching
+                        .into_iter()
+                        .skip(*count)
+                        .map(|tag| tag.reference().to_string()),
+                );
+            }
+            RetentionRule::DeleteUnpulledTags { days } => {
+                let threshold = days_ago(now, *days);
+                expired_tags.extend(
+                    tags.iter()
+                        .filter(|tag| {
+                            tag.pulled_at()
+                                .or_else(|| tag.pushed_at())
+                                .map_or(false, |at| at < &threshold)
+                        })
+                        .map(|tag| tag.reference().to_string()),
+                );
+            }
+            RetentionRule::DeleteUntagged { .. } => {}
+        }
+    }
+
+    let policy = repo.tag_policy();
+    let (expired, kept): (Vec<Manifest>, Vec<Manifest>) = tags.into_iter().partition(|tag| {
+        expired_tags.contains(tag.reference()) && !policy.is_protected(tag.reference())
+    });
+    for tag in expired {
+        plan.tags.push(tag.reference().to_string());
+        plan.deletions.push(tag);
+    }
+
+    let untagged_threshold = repo
+        .retention()
+        .iter()
+        .filter_map(|rule| match rule {
+            RetentionRule::DeleteUntagged { days } => Some(days_ago(now, *days)),
+            _ => None,
+        })
+        .max();
+    if let Some(threshold) = untagged_threshold {
+        let tagged: HashSet<&Digest> = kept.iter().map(Manifest::digest).collect();
+        let referenced: HashSet<&Digest> = untagged
+            .iter()
+            .chain(kept.iter())
+            .filter_map(|manifest| match manifest.manifest() {
+                ManifestBody::Index(index) => Some(index.manifests()),
+                ManifestBody::Image(_) => None,
+            })
+            .flatten()
+            .map(|child| child.digest())
+            .collect();
+        let expired: Vec<Manifest> = untagged
+            .iter()
+            .filter(|manifest| {
+                !tagged.contains(manifest.digest())
+                    && !referenced.contains(manifest.digest())
+                    && manifest.manifest().subject().is_none()
+                    && manifest.pushed_at().map_or(false, |at| at < &threshold)
+            })
+            .cloned()
+            .collect();
+        for manifest in expired {
+            plan.manifests.push(manifest.digest().to_string());
+            plan.deletions.push(manifest);
+        }
+    }
+
+    plan
+}
+
+fn days_ago(now: &DateTime<Utc>, days: u32) -> DateTime<Utc> {
+    *now - Duration::days(days as i64)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::digest::DigestAlgorithm;
+    use crate::entity::TagPolicy;
+    use crate::manifest::{Descriptor, ImageIndex, ImageManifest};
+
+    use super::*;
+
+    const IMAGE_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
+
+    fn digest(content: &str) -> Digest {
+        Digest::compute(DigestAlgorithm::Sha256, content)
+    }
+
+    fn repo(rules: Vec<RetentionRule>) -> Repo {
+        let mut repo = Repo::new("org", "app", None);
+        repo.set_retention(rules);
+        repo
+    }
+
+    fn manifest(
+        reference: &str,
+        digest: Digest,
+        body: ManifestBody,
+        pushed_days_ago: u32,
+    ) -> Manifest {
+        let mut manifest = Manifest::new(
+            "org/app",
+            reference,
+            digest,
+            IMAGE_MANIFEST,
+            String::new(),
+            body,
+        );
+        manifest.set_pushed_at(days_ago(&Utc::now(), pushed_days_ago));
+        manifest
+    }
+
+    fn image(content: &str) -> ManifestBody {
+        ManifestBody::Image(ImageManifest::new(digest(content), 0))
+    }
+
+    fn tag(reference: &str, content: &str, pushed_days_ago: u32) -> Manifest {
+        manifest(reference, digest(content), image(content), pushed_days_ago)
+    }
+
+    fn untagged(content: &str, body: ManifestBody, pushed_days_ago: u32) -> Manifest {
+        let digest = digest(content);
+        manifest(&digest.to_string(), digest, body, pushed_days_ago)
+    }
+
+    #[test]
+    fn it_keeps_the_last_tags() {
+        let repo = repo(vec![RetentionRule::KeepLastTags {
+            pattern: "v*".to_string(),
+            count: 2,
+        }]);
+        let manifests = vec![
+            tag("v1", "1", 3),
+            tag("v3", "3", 1),
+            tag("v2", "2", 2),
+            tag("latest", "3", 10),
+        ];
+
+        let plan = plan_retention(&repo, manifests, &Utc::now());
+        assert_eq!(plan.tags(), &["v1".to_string()]);
+        assert!(plan.manifests().is_empty());
+    }
+
+    #[test]
+    fn it_deletes_unpulled_tags() {
+        let repo = repo(vec![RetentionRule::DeleteUnpulledTags { days: 5 }]);
+        let mut pulled = tag("pulled", "1", 10);
+        pulled.set_pulled_at(days_ago(&Utc::now(), 1));
+        let mut stale = tag("stale", "2", 10);
+        stale.set_pulled_at(days_ago(&Utc::now(), 6));
+        let manifests = vec![pulled, stale, tag("never", "3", 10), tag("new", "4", 1)];
+
+        let plan = plan_retention(&repo, manifests, &Utc::now());
+        assert_eq!(plan.tags(), &["stale".to_string(), "never".to_string()]);
+    }
+
+    #[test]
+    fn it_never_deletes_protected_tags() {
+        let mut repo = repo(vec![RetentionRule::KeepLastTags {
+            pattern: "*".to_string(),
+            count: 0,
+        }]);
+        repo.set_tag_policy(TagPolicy::Protected {
+            patterns: vec!["release-*".to_string()],
+        });
+        let manifests = vec![tag("release-1", "1", 2), tag("dev", "2", 1)];
+
+        let plan = plan_retention(&repo, manifests, &Utc::now());
+        assert_eq!(plan.tags(), &["dev".to_string()]);
+    }
+
+    #[test]
+    fn it_deletes_untagged_manifests() {
+        let repo = repo(vec![RetentionRule::DeleteUntagged { days: 7 }]);
+        let child = Descriptor::new(IMAGE_MANIFEST, digest("child"), 0);
+        let mut referrer = ImageManifest::new(digest("config"), 0);
+        referrer.set_subject(Descriptor::new(IMAGE_MANIFEST, digest("tagged"), 0));
+        let manifests = vec![
+            tag("latest", "tagged", 30),
+            untagged("tagged", image("tagged"), 30),
+            untagged("orphan", image("orphan"), 30),
+            untagged("recent", image("recent"), 1),
+            untagged("child", image("child"), 30),
+            untagged(
+                "index",
+                ManifestBody::Index(ImageIndex::new(vec![child])),
+                30,
+            ),
+            untagged("referrer", ManifestBody::Image(referrer), 30),
+        ];
+
+        let plan = plan_retention(&repo, manifests, &Utc::now());
+        assert!(plan.tags().is_empty());
+        assert_eq!(
+            plan.manifests(),
+            &[digest("orphan").to_string(), digest("index").to_string()]
+        );
+    }
+
+    #[test]
+    fn it_deletes_the_manifests_of_expired_tags() {
+        let repo = repo(vec![
+            RetentionRule::KeepLastTags {
+                pattern: "*".to_string(),
+                count: 1,
+            },
+            RetentionRule::DeleteUntagged { days: 7 },
+        ]);
+        let manifests = vec![
+            tag("v1", "1", 20),
+            tag("v2", "2", 10),
+            untagged("1", image("1"), 20),
+        ];
+
+        let plan = plan_retention(&repo, manifests, &Utc::now());
+        assert_eq!(plan.tags(), &["v1".to_string()]);
+        assert_eq!(plan.manifests(), &[digest("1").to_string()]);
+    }
+}
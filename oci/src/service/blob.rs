@@ -1,28 +1,36 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, RwLock};
 
 use async_trait::async_trait;
 
 use enseada::couchdb::db::Database;
 use enseada::couchdb::repository::{Entity, Repository};
-use enseada::storage::{ByteChunk, Provider};
-use events::EventHandler;
+use enseada::storage::blob::Blob as StorageBlob;
+use enseada::storage::{ByteChunk, ByteRange, Provider};
+use events::{EventBus, EventHandler};
 
 use crate::digest::Digest;
 use crate::entity::Blob;
 use crate::error::{Error, ErrorCode};
-use crate::events::RepoDeleted;
-use crate::{storage, Result};
-use futures::{Stream, StreamExt};
+use crate::events::{BlobDeleted, BlobPulled, BlobPushed, RepoDeleted};
+use crate::{name, storage, Result};
+use futures::{future, Stream, StreamExt, TryStreamExt};
+use uuid::Uuid;
 
 #[derive(Debug)]
 pub struct BlobService {
     db: Arc<Database>,
+    bus: Arc<RwLock<EventBus>>,
     store: Arc<Provider>,
 }
 
 impl BlobService {
-    pub fn new(db: Arc<Database>, store: Arc<Provider>) -> Self {
-        Self { db, store }
+    pub fn new(db: Arc<Database>, bus: Arc<RwLock<EventBus>>, store: Arc<Provider>) -> Self {
+        Self { db, bus, store }
+    }
+
+    /// The key the content of a blob is stored under.
+    pub fn content_key(&self, digest: &Digest) -> String {
+        storage::blob_key(digest)
     }
 
     pub async fn fetch_content(&self, digest: &Digest) -> Result<impl Stream<Item = ByteChunk>> {
@@ -34,11 +42,144 @@ impl BlobService {
         }
     }
 
-    pub async fn delete_blob(&self, blob: &Blob) -> Result<()> {
+    /// Fetches a byte range of the content of a blob.
+    pub async fn fetch_content_range(
+        &self,
+        digest: &Digest,
+        range: ByteRange,
+    ) -> Result<impl Stream<Item = ByteChunk>> {
+        let storage_key = storage::blob_key(digest);
+        let blob = self.store.get_blob_range(&storage_key, range).await?;
+        match blob {
+            Some(blob) => Ok(blob.into_byte_stream()),
+            None => Err(Error::from(ErrorCode::BlobUnknown)),
+        }
+    }
+
+    /// Returns the size of the content of a blob, falling back to the storage
+    /// for blobs that have no recorded size.
+    pub async fn content_size(&self, blob: &Blob) -> Result<usize> {
+        if let Some(size) = blob.size() {
+            return Ok(size);
+        }
         let storage_key = storage::blob_key(blob.digest());
-        self.delete(blob).await?;
-        self.store.delete_blob(&storage_key).await?;
-        Ok(())
+        let content = self.store.get_blob(&storage_key).await?;
+        match content {
+            Some(content) => Ok(content.size()),
+            None => Err(Error::from(ErrorCode::BlobUnknown)),
+        }
+    }
+
+    pub async fn find_by_digest(&self, image: &str, digest: &Digest) -> Result<Option<Blob>> {
+        log::debug!("finding blob {} in {}", digest, image);
+        let id = Blob::build_id(image, digest);
+        self.find(&id).await.map_err(Error::from)
+    }
+
+    /// Sums the sizes of the blobs linked to a repository.
+    /// Blobs created before sizes were recorded are not counted.
+    pub async fn usage(&self, image: &str) -> Result<u64> {
+        self.find_all_stream(serde_json::json!({
+            "image": image,
+        }))
+        .try_fold(0, |usage, blob| {
+            future::ok(usage + blob.size().unwrap_or(0) as u64)
+        })
+        .await
+        .map_err(Error::from)
+    }
+
+    /// Links an existing blob to another repository, without copying its content.
+    pub async fn mount_blob(&self, blob: &Blob, image: &str) -> Result<Blob> {
+        log::debug!(
+            "mounting blob {} from {} into {}",
+            blob.digest(),
+            blob.image(),
+            image
+        );
+        let blob = Blob::new(blob.digest().clone(), image, self.content_size(blob).await?);
+        self.save(blob).await.map_err(Error::from)
+    }
+
+    /// Stores the content of a blob fetched from elsewhere and links it to its repository.
+    /// The content is staged until verified, and discarded if it doesn't match the blob
+    /// digest or it's incomplete. Content already stored is only linked, the new copy
+    /// is drained without being stored.
+    pub async fn cache_blob<S: Stream<Item = ByteChunk> + Send + Sync + 'static>(
+        &self,
+        blob: Blob,
+        content: S,
+    ) -> Result<Blob> {
+        let digest = blob.digest().clone();
+        let storage_key = storage::blob_key(&digest);
+        if self.store.is_blob_present(&storage_key).await? {
+            log::debug!("blob {} is already cached", &storage_key);
+            content.for_each(|_| future::ready(())).await;
+            return self.save(blob).await.map_err(Error::from);
+        }
+
+        let hasher = Arc::new(Mutex::new(digest.algo().hasher()));
+        let stream_hasher = hasher.clone();
+        let content = content.inspect(move |chunk| {
+            if let Ok(bytes) = chunk {
+                stream_hasher.lock().unwrap().update(bytes);
+            }
+        });
+
+        let staging_key = storage::staging_key(&Uuid::new_v4().to_string());
+        log::debug!("caching blob {} as {}", &storage_key, &staging_key);
+        let size = blob.size().unwrap_or_default();
+        let stored = self
+            .store
+            .store_blob(StorageBlob::new(staging_key.clone(), size, content))
+            .await;
+        let computed = hasher.lock().unwrap().clone().finish();
+        if stored.is_err() || computed != digest {
+            log::warn!("blob {} was not cached, its content is invalid", &digest);
+            if let Err(err) = self.store.delete_blob(&staging_key).await {
+                log::warn!("failed to delete staged blob {}: {}", &staging_key, err);
+            }
+            stored?;
+            return Err(Error::from(ErrorCode::DigestInvalid).with_detail("digest", digest));
+        }
+
+        self.store.move_blob(&staging_key, &storage_key).await?;
+        self.save(blob).await.map_err(Error::from)
+    }
+
+    /// Links a blob uploaded by a client to its repository.
+    pub async fn push_blob(&self, blob: Blob) -> Result<Blob> {
+        let blob = self.save(blob).await?;
+        let event = BlobPushed::from(&blob);
+        let bus = self.bus.read().expect("push_blob() EventBus unlock");
+        bus.broadcast(event);
+        Ok(blob)
+    }
+
+    /// Records a download of the content of a blob.
+    pub fn mark_pulled(&self, blob: &Blob) {
+        let event = BlobPulled::from(blob);
+        let bus = self.bus.read().expect("mark_pulled() EventBus unlock");
+        bus.broadcast(event);
+    }
+
+    /// Records a download of a blob streamed from an upstream registry,
+    /// whose size is unknown when the upstream sends no `Content-Length`.
+    pub fn mark_streamed(&self, image: &str, digest: &Digest, size: Option<usize>) {
+        let event = BlobPulled {
+            id: Blob::build_guid(&Blob::build_id(image, digest)),
+            image: image.to_string(),
+            digest: digest.clone(),
+            size,
+        };
+        let bus = self.bus.read().expect("mark_streamed() EventBus unlock");
+        bus.broadcast(event);
+    }
+
+    /// Unlinks a blob from its repository. The content is deleted
+    /// once no other repository links to it.
+    pub async fn delete_blob(&self, blob: &Blob) -> Result<()> {
+        self.delete(blob).await.map_err(Error::from)
     }
 }
 
@@ -49,9 +190,33 @@ impl Repository<Blob> for BlobService {
     }
 
     async fn deleted(&self, blob: &Blob) {
-        let storage_key = storage::blob_key(blob.digest());
-        if let Err(err) = self.store.delete_blob(&storage_key).await {
-            log::error!("blob deletion failed: {}", err);
+        {
+            let event = BlobDeleted::from(blob);
+            let bus = self.bus.read().expect("deleted() EventBus unlock");
+            bus.broadcast(event);
+        }
+
+        let linked = self
+            .find_one(serde_json::json!({
+                "digest": blob.digest(),
+            }))
+            .await;
+        match linked {
+            Ok(Some(_)) => log::debug!(
+                "blob {} is still linked to other repositories",
+                blob.digest()
+            ),
+            Ok(None) => {
+                let storage_key = storage::blob_key(blob.digest());
+                if let Err(err) = self.store.delete_blob(&storage_key).await {
+                    log::error!("blob deletion failed: {}", err);
+                }
+            }
+            Err(err) => log::error!(
+                "failed to look up links for blob {}: {}",
+                blob.digest(),
+                err
+            ),
         }
     }
 }
@@ -59,7 +224,7 @@ impl Repository<Blob> for BlobService {
 #[async_trait]
 impl EventHandler<RepoDeleted> for BlobService {
     async fn handle(&self, event: &RepoDeleted) {
-        let image = format!("{}/{}", &event.group, &event.name);
+        let image = name::join(&event.group, &event.name);
         if let Err(err) = self
             .delete_all(serde_json::json!({
               "image": image,
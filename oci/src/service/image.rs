@@ -0,0 +1,87 @@
+use std::convert::TryFrom;
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use futures::{future, TryStreamExt};
+
+use enseada::couchdb::db::Database;
+use enseada::couchdb::repository::Repository;
+use enseada::storage::Provider;
+use events::EventBus;
+
+use crate::entity::{ImageMetadata, Manifest};
+use crate::error::{Error, ErrorCode};
+use crate::image::ImageConfig;
+use crate::manifest::ManifestBody;
+use crate::mime::MediaType;
+use crate::service::BlobService;
+use crate::Result;
+
+/// Extracts the metadata of image manifests from their configuration blob.
+/// The metadata is cached, so that the configuration is only read once.
+#[derive(Debug)]
+pub struct ImageService {
+    db: Arc<Database>,
+    blobs: BlobService,
+}
+
+impl ImageService {
+    pub fn new(db: Arc<Database>, bus: Arc<RwLock<EventBus>>, store: Arc<Provider>) -> Self {
+        Self {
+            blobs: BlobService::new(db.clone(), bus, store),
+            db,
+        }
+    }
+
+    /// Returns the metadata of an image manifest, or `None` for image indexes.
+    pub async fn metadata(&self, manifest: &Manifest) -> Result<Option<ImageMetadata>> {
+        let image = match manifest.manifest() {
+            ManifestBody::Image(image) => image,
+            ManifestBody::Index(_) => return Ok(None),
+        };
+        let digest = manifest.digest();
+        if let Some(metadata) = self.find(&digest.to_string()).await? {
+            return Ok(Some(metadata));
+        }
+
+        let config = image.config();
+        let is_image_config = matches!(
+            MediaType::try_from(config.media_type().to_string()),
+            Ok(MediaType::ImageConfig)
+        );
+        let parsed = if is_image_config {
+            log::debug!("reading config {} of manifest {}", config.digest(), digest);
+            let content = self
+                .blobs
+                .fetch_content(config.digest())
+                .await?
+                .try_fold(BytesMut::new(), |mut content, chunk| {
+                    content.extend_from_slice(&chunk);
+                    future::ok(content)
+                })
+                .await
+                .map_err(|err| Error::new(ErrorCode::Internal, err))?;
+            Some(ImageConfig::parse(&content)?)
+        } else {
+            log::debug!("manifest {} is not an image, its config is skipped", digest);
+            None
+        };
+
+        let metadata = ImageMetadata::new(digest.clone(), parsed, image.layers().to_vec());
+        match self.save(metadata.clone()).await {
+            Ok(metadata) => Ok(Some(metadata)),
+            Err(err) => {
+                log::warn!("metadata of manifest {} not cached: {}", digest, err);
+                Ok(Some(metadata))
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Repository<ImageMetadata> for ImageService {
+    fn db(&self) -> &Database {
+        self.db.as_ref()
+    }
+}
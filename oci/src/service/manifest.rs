@@ -1,48 +1,346 @@
-use std::sync::Arc;
+use std::convert::TryFrom;
+use std::sync::{Arc, RwLock};
 
 use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Duration, Utc};
+use futures::TryStreamExt;
 
 use enseada::couchdb::db::Database;
-use enseada::couchdb::repository::Repository;
-use events::EventHandler;
+use enseada::couchdb::repository::{Entity, Repository};
+use enseada::couchdb::responses::RowsResponse;
+use enseada::couchdb::view::View;
+use events::{EventBus, EventHandler};
 
-use crate::entity::Manifest;
-use crate::error::Error;
-use crate::events::RepoDeleted;
-use crate::Result;
+use crate::digest::{Digest, DigestAlgorithm};
+use crate::entity::{Manifest, Repo};
+use crate::error::{Error, ErrorCode};
+use crate::events::{ManifestDeleted, ManifestPulled, ManifestPushed, RepoDeleted};
+use crate::manifest::{Descriptor, ManifestBody};
+use crate::mime::MediaType;
+use crate::{name, Result};
 
 #[derive(Debug)]
 pub struct ManifestService {
     db: Arc<Database>,
+    bus: Arc<RwLock<EventBus>>,
+    referrers_view: View,
 }
 
 impl ManifestService {
-    pub fn new(db: Arc<Database>) -> Self {
-        Self { db }
+    pub fn new(db: Arc<Database>, bus: Arc<RwLock<EventBus>>) -> Self {
+        let id = Manifest::build_guid("");
+        let part = id.partition().unwrap();
+        let referrers_view = db.partitioned_view("image_views", part, "image_referrers");
+        Self {
+            db,
+            bus,
+            referrers_view,
+        }
     }
 
-    pub async fn find_by_ref(
-        &self,
-        group: &str,
-        name: &str,
-        reference: &str,
-    ) -> Result<Option<Manifest>> {
+    pub async fn find_by_ref(&self, image: &str, reference: &str) -> Result<Option<Manifest>> {
         log::debug!("finding manifest by ref '{}'", reference);
-        let id = Manifest::build_id(group, name, reference);
+        let id = Manifest::build_id(image, reference);
         self.find(&id).await.map_err(Error::from)
     }
+
+    /// Tells whether any manifest of an image references a blob,
+    /// either as its configuration or as one of its layers.
+    pub async fn references_blob(&self, image: &str, digest: &Digest) -> Result<bool> {
+        let digest = digest.to_string();
+        let manifest = self
+            .find_one(serde_json::json!({
+                "image": image,
+                "$or": [
+                    { "manifest.config.digest": &digest },
+                    { "manifest.layers": { "$elemMatch": { "digest": &digest } } },
+                ],
+            }))
+            .await?;
+        Ok(manifest.is_some())
+    }
+
+    /// Stores the raw manifest content under its digest and, if the reference
+    /// is a tag, under the tag as well. Tags protected by the repository policy
+    /// can only be pushed again with the same content.
+    pub async fn put_manifest(
+        &self,
+        repo: &Repo,
+        reference: &str,
+        media_type: Option<&str>,
+        content: Bytes,
+    ) -> Result<Manifest> {
+        let image = &repo.full_name();
+        let media_type = media_type.filter(|mime| !mime.is_empty());
+        let manifest = ManifestBody::parse(media_type, &content)?;
+        if Digest::try_from(reference).is_err() && repo.tag_policy().is_protected(reference) {
+            if let Some(existing) = self.find_by_ref(image, reference).await? {
+                let digest = Digest::compute(existing.digest().algo().clone(), &content);
+                if &digest != existing.digest() {
+                    log::debug!("tag {} of {} is protected", reference, image);
+                    return Err(protected_tag(reference));
+                }
+            }
+        }
+        if let ManifestBody::Index(index) = &manifest {
+            for child in index.manifests() {
+                let digest = child.digest().to_string();
+                if self.find_by_ref(image, &digest).await?.is_none() {
+                    log::debug!("image index references unknown manifest {}", &digest);
+                    return Err(
+                        Error::from(ErrorCode::ManifestBlobUnknown).with_detail("digest", digest)
+                    );
+                }
+            }
+        }
+
+        let manifest = self
+            .store_manifest(image, reference, media_type, manifest, content, None)
+            .await?;
+        let event = ManifestPushed::from(&manifest);
+        let bus = self.bus.read().expect("put_manifest() EventBus unlock");
+        bus.broadcast(event);
+        Ok(manifest)
+    }
+
+    /// Stores a manifest fetched from an upstream registry.
+    /// Unlike pushed manifests, the children of an image index are not required
+    /// to be present, as they are fetched on demand.
+    pub async fn cache_manifest(
+        &self,
+        image: &str,
+        reference: &str,
+        media_type: Option<&str>,
+        content: Bytes,
+    ) -> Result<Manifest> {
+        let media_type = media_type.filter(|mime| !mime.is_empty());
+        let manifest = ManifestBody::parse(media_type, &content)?;
+        self.store_manifest(
+            image,
+            reference,
+            media_type,
+            manifest,
+            content,
+            Some(Utc::now()),
+        )
+        .await
+    }
+
+    /// Marks a cached manifest as just revalidated against its upstream registry.
+    pub async fn refresh_manifest(&self, mut manifest: Manifest) -> Result<Manifest> {
+        manifest.set_fetched_at(Utc::now());
+        self.save(manifest).await.map_err(Error::from)
+    }
+
+    /// Records a pull of the manifest. Every pull is broadcast, while the pull date
+    /// is stored at most once per tracking interval.
+    /// Concurrent updates of the manifest always win over the pull date.
+    pub async fn mark_pulled(&self, manifest: &Manifest) {
+        {
+            let event = ManifestPulled::from(manifest);
+            let bus = self.bus.read().expect("mark_pulled() EventBus unlock");
+            bus.broadcast(event);
+        }
+
+        let now = Utc::now();
+        let tracked = manifest.pulled_at().map_or(false, |pulled_at| {
+            *pulled_at + pull_tracking_interval() > now
+        });
+        if tracked {
+            return;
+        }
+
+        let mut manifest = manifest.clone();
+        manifest.set_pulled_at(now);
+        if let Err(err) = self.db.put(manifest.id().to_string(), &manifest).await {
+            log::debug!("pull of manifest {} not recorded: {}", manifest.id(), err);
+        }
+    }
+
+    async fn store_manifest(
+        &self,
+        image: &str,
+        reference: &str,
+        media_type: Option<&str>,
+        manifest: ManifestBody,
+        content: Bytes,
+        fetched_at: Option<DateTime<Utc>>,
+    ) -> Result<Manifest> {
+        let digest = match Digest::try_from(reference) {
+            Ok(expected) => {
+                let digest = Digest::compute(expected.algo().clone(), &content);
+                if digest != expected {
+                    return Err(Error::from(ErrorCode::DigestInvalid)
+                        .with_detail("digest", expected.to_string()));
+                }
+                digest
+            }
+            Err(_) => Digest::compute(DigestAlgorithm::Sha256, &content),
+        };
+        log::debug!("manifest '{}' has digest {}", reference, &digest);
+
+        let media_type = media_type
+            .or_else(|| manifest.media_type())
+            .map(str::to_string)
+            .unwrap_or_else(|| {
+                if manifest.is_index() {
+                    MediaType::ImageIndex.to_string()
+                } else {
+                    MediaType::ImageManifest.to_string()
+                }
+            });
+        let content = String::from_utf8(content.to_vec()).map_err(|err| {
+            Error::from(ErrorCode::ManifestInvalid).with_detail("reason", err.to_string())
+        })?;
+
+        let digest_ref = digest.to_string();
+        let mut by_digest = Manifest::new(
+            image,
+            &digest_ref,
+            digest.clone(),
+            &media_type,
+            content.clone(),
+            manifest.clone(),
+        );
+        if let Some(fetched_at) = fetched_at {
+            by_digest.set_fetched_at(fetched_at);
+        }
+        let by_digest = self.save(by_digest).await?;
+        if reference == digest_ref {
+            return Ok(by_digest);
+        }
+
+        let mut by_tag = Manifest::new(image, reference, digest, &media_type, content, manifest);
+        if let Some(fetched_at) = fetched_at {
+            by_tag.set_fetched_at(fetched_at);
+        }
+        self.save(by_tag).await.map_err(Error::from)
+    }
+
+    /// Resolves an image index to the manifest of the given platform,
+    /// falling back to the first manifest in the index.
+    pub async fn resolve_platform(
+        &self,
+        image: &str,
+        manifest: Manifest,
+        os: &str,
+        architecture: &str,
+    ) -> Result<Manifest> {
+        let index = match manifest.manifest() {
+            ManifestBody::Index(index) => index,
+            ManifestBody::Image(_) => return Ok(manifest),
+        };
+
+        let child = index
+            .find_platform(os, architecture)
+            .or_else(|| index.manifests().first())
+            .ok_or_else(|| Error::from(ErrorCode::ManifestUnknown))?;
+        self.find_by_ref(image, &child.digest().to_string())
+            .await?
+            .ok_or_else(|| Error::from(ErrorCode::ManifestUnknown))
+    }
+
+    /// Lists the descriptors of all manifests having the given digest as subject,
+    /// optionally filtered by artifact type. Manifests listed in an image index
+    /// pushed under the fallback tag (`<alg>-<hex>`) are included as well.
+    pub async fn list_referrers(
+        &self,
+        image: &str,
+        digest: &Digest,
+        artifact_type: Option<&str>,
+    ) -> Result<Vec<Descriptor>> {
+        let key = format!("{}@{}", image, digest);
+        let res: RowsResponse<Manifest> = self.referrers_view.list_all_for_key(key, true).await?;
+        let mut referrers: Vec<Descriptor> = res
+            .rows
+            .into_iter()
+            .filter_map(|row| row.doc)
+            .map(|manifest| {
+                let body = manifest.manifest();
+                Descriptor::new(
+                    manifest.media_type(),
+                    manifest.digest().clone(),
+                    manifest.content().len(),
+                )
+                .with_artifact_type(body.artifact_type().map(str::to_string))
+                .with_annotations(body.annotations().cloned())
+            })
+            .collect();
+
+        let fallback_tag = format!("{}-{}", digest.algo(), digest.hex());
+        if let Some(fallback) = self.find_by_ref(image, &fallback_tag).await? {
+            if let ManifestBody::Index(index) = fallback.manifest() {
+                for descriptor in index.manifests() {
+                    if !referrers.iter().any(|r| r.digest() == descriptor.digest()) {
+                        referrers.push(descriptor.clone());
+                    }
+                }
+            }
+        }
+
+        Ok(referrers
+            .into_iter()
+            .filter(|r| artifact_type.is_none() || r.artifact_type() == artifact_type)
+            .collect())
+    }
+
+    /// Deletes a manifest. Deleting by digest also removes every tag
+    /// pointing to it, while deleting by tag only removes the tag itself.
+    /// Tags protected by the repository policy cannot be deleted.
+    pub async fn delete_manifest(&self, repo: &Repo, manifest: &Manifest) -> Result<()> {
+        let policy = repo.tag_policy();
+        if manifest.reference() != manifest.digest().to_string() {
+            if policy.is_protected(manifest.reference()) {
+                return Err(protected_tag(manifest.reference()));
+            }
+            return self.delete(manifest).await.map_err(Error::from);
+        }
+
+        let selector = serde_json::json!({
+            "image": manifest.image(),
+            "digest": manifest.digest(),
+        });
+        let tagged: Vec<Manifest> = self.find_all_stream(selector.clone()).try_collect().await?;
+        if let Some(tag) = tagged.iter().map(Manifest::reference).find(|reference| {
+            Digest::try_from(*reference).is_err() && policy.is_protected(reference)
+        }) {
+            return Err(protected_tag(tag));
+        }
+
+        self.delete_all(selector).await.map_err(Error::from)
+    }
 }
 
+fn pull_tracking_interval() -> Duration {
+    Duration::hours(1)
+}
+
+fn protected_tag(tag: &str) -> Error {
+    Error::new(
+        ErrorCode::Denied,
+        format!("tag '{}' is protected by the repository policy", tag),
+    )
+    .with_detail("tag", tag)
+}
+
+#[async_trait]
 impl Repository<Manifest> for ManifestService {
     fn db(&self) -> &Database {
         &self.db
     }
+
+    async fn deleted(&self, manifest: &Manifest) {
+        let event = ManifestDeleted::from(manifest);
+        let bus = self.bus.read().expect("deleted() EventBus unlock");
+        bus.broadcast(event);
+    }
 }
 
 #[async_trait]
 impl EventHandler<RepoDeleted> for ManifestService {
     async fn handle(&self, event: &RepoDeleted) {
-        let image = format!("{}/{}", &event.group, &event.name);
+        let image = name::join(&event.group, &event.name);
         if let Err(err) = self
             .delete_all(serde_json::json!({
               "image": image,
@@ -1,9 +1,13 @@
 pub use blob::BlobService;
+pub use image::ImageService;
 pub use manifest::ManifestService;
+pub use proxy::{ProxiedBlob, ProxyService};
 pub use repo::RepoService;
 pub use upload::UploadService;
 
 mod blob;
+mod image;
 mod manifest;
+mod proxy;
 mod repo;
 mod upload;
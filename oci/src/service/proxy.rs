@@ -0,0 +1,391 @@
+use std::convert::TryFrom;
+use std::sync::{Arc, RwLock};
+
+use chrono::{Duration, Utc};
+use futures::channel::mpsc;
+use futures::future::BoxFuture;
+use futures::stream::{self, BoxStream};
+use futures::{FutureExt, SinkExt, StreamExt};
+
+use enseada::couchdb::db::Database;
+use enseada::couchdb::repository::Repository;
+use enseada::storage::{ByteChunk, Provider};
+use events::EventBus;
+
+use crate::client::RegistryClient;
+use crate::digest::Digest;
+use crate::entity::{Blob, Manifest, Repo, Upstream};
+use crate::error::{Error, ErrorCode};
+use crate::service::{BlobService, ManifestService, RepoService};
+use crate::{name, Result};
+
+/// Number of chunks buffered for the cache while it stores a blob. When the storage
+/// is slower than the upstream registry, the client download is slowed down as well.
+const CACHE_BUFFER_SIZE: usize = 16;
+
+/// A blob pulled through a proxy repository.
+pub enum ProxiedBlob {
+    /// The blob content was already cached, and it has been linked to the repository.
+    Cached(Blob),
+    /// The blob content is streamed from the upstream registry. The `cache` future,
+    /// if any, stores the content as it is consumed and it must be run concurrently.
+    Streamed {
+        size: Option<usize>,
+        content: BoxStream<'static, ByteChunk>,
+        cache: Option<BoxFuture<'static, Result<Blob>>>,
+    },
+}
+
+/// Pull-through cache for repositories proxying an upstream registry.
+///
+/// Manifests and blobs missing from a proxy repository, or from any repository
+/// in its namespace, are fetched from upstream and cached. Tags are revalidated
+/// once their TTL expires, while digests are immutable and cached forever.
+/// Repositories of the namespace are only created once upstream returns
+/// one of their manifests or blobs.
+#[derive(Debug)]
+pub struct ProxyService {
+    repos: RepoService,
+    manifests: ManifestService,
+    blobs: Arc<BlobService>,
+    client: RegistryClient,
+}
+
+impl ProxyService {
+    pub fn new(db: Arc<Database>, bus: Arc<RwLock<EventBus>>, store: Arc<Provider>) -> Self {
+        Self {
+            repos: RepoService::new(db.clone(), bus.clone()),
+            manifests: ManifestService::new(db.clone(), bus.clone()),
+            blobs: Arc::new(BlobService::new(db, bus, store)),
+            client: RegistryClient::new(),
+        }
+    }
+
+    /// Finds a repository by name. Repositories missing from the namespace
+    /// of a proxy repository are returned without being saved, with the same
+    /// visibility as the proxy repository.
+    pub async fn find_repo(&self, name: &str) -> Result<Option<Repo>> {
+        if let Some(repo) = self.repos.find(&Repo::build_id(name)).await? {
+            return Ok(Some(repo));
+        }
+        let proxy = self.find_proxy(name).await?;
+        Ok(proxy.map(|proxy| new_repo(&proxy, name)))
+    }
+
+    /// Tells whether a repository can be pulled from without credentials.
+    /// Repositories yet to be created in the namespace of a proxy repository
+    /// are as visible as the proxy repository.
+    pub async fn is_public(&self, name: &str) -> Result<bool> {
+        if let Some(repo) = self.repos.find(&Repo::build_id(name)).await? {
+            return Ok(repo.is_public());
+        }
+        let proxy = self.find_proxy(name).await?;
+        Ok(proxy.map_or(false, |proxy| proxy.is_public()))
+    }
+
+    /// Fetches a manifest from the cache, or from upstream if it's missing or stale.
+    /// Returns `None` if the repository is not proxied. If the upstream registry is
+    /// unreachable, stale manifests are served anyway.
+    pub async fn fetch_manifest(&self, image: &str, reference: &str) -> Result<Option<Manifest>> {
+        let proxied = match self.resolve(image).await? {
+            Some(proxied) => proxied,
+            None => return Ok(None),
+        };
+
+        let cached = match self.manifests.find_by_ref(image, reference).await? {
+            Some(manifest)
+                if Digest::try_from(reference).is_ok()
+                    || is_fresh(&manifest, &proxied.upstream) =>
+            {
+                return Ok(Some(manifest));
+            }
+            cached => cached,
+        };
+
+        match self
+            .pull_manifest(&proxied, image, reference, cached.clone())
+            .await
+        {
+            Err(err) if cached.is_some() => {
+                log::warn!(
+                    "failed to revalidate manifest {}:{}, serving it from cache: {}",
+                    image,
+                    reference,
+                    err
+                );
+                Ok(cached)
+            }
+            res => res,
+        }
+    }
+
+    /// Fetches a blob missing from a proxied repository.
+    /// Returns `None` if the repository is not proxied.
+    pub async fn fetch_blob(&self, image: &str, digest: &Digest) -> Result<Option<ProxiedBlob>> {
+        let proxied = match self.resolve(image).await? {
+            Some(proxied) => proxied,
+            None => return Ok(None),
+        };
+        if let Some(blob) = self.link_cached_blob(&proxied, image, digest).await? {
+            return Ok(Some(ProxiedBlob::Cached(blob)));
+        }
+
+        let fetched = self
+            .client
+            .get_blob(&proxied.upstream, &proxied.remote, digest)
+            .await?
+            .ok_or_else(|| Error::from(ErrorCode::BlobUnknown))?;
+        self.create_repo(&proxied.proxy, image).await?;
+        let size = match fetched.size {
+            Some(size) => size,
+            None => {
+                log::warn!("upstream blob {} has no length, it won't be cached", digest);
+                return Ok(Some(ProxiedBlob::Streamed {
+                    size: None,
+                    content: fetched.content.boxed(),
+                    cache: None,
+                }));
+            }
+        };
+
+        // the client drives the upstream stream, while the cache receives a copy of each chunk
+        let (tx, rx) = mpsc::channel(CACHE_BUFFER_SIZE);
+        let content = fetched.content.then(move |chunk| {
+            let mut tx = tx.clone();
+            async move {
+                if let Ok(bytes) = &chunk {
+                    // a failed cache drops its receiver, the download goes on regardless
+                    tx.send(Ok(bytes.clone())).await.ok();
+                }
+                chunk
+            }
+        });
+        let blobs = self.blobs.clone();
+        let blob = Blob::new(digest.clone(), image, size);
+        let cache = async move { blobs.cache_blob(blob, rx).await };
+        Ok(Some(ProxiedBlob::Streamed {
+            size: Some(size),
+            content: content.boxed(),
+            cache: Some(cache.boxed()),
+        }))
+    }
+
+    /// Checks a blob missing from a proxied repository, without fetching its content.
+    /// Returns `None` if the repository is not proxied.
+    pub async fn head_blob(&self, image: &str, digest: &Digest) -> Result<Option<ProxiedBlob>> {
+        let proxied = match self.resolve(image).await? {
+            Some(proxied) => proxied,
+            None => return Ok(None),
+        };
+        if let Some(blob) = self.link_cached_blob(&proxied, image, digest).await? {
+            return Ok(Some(ProxiedBlob::Cached(blob)));
+        }
+
+        let fetched = self
+            .client
+            .head_blob(&proxied.upstream, &proxied.remote, digest)
+            .await?
+            .ok_or_else(|| Error::from(ErrorCode::BlobUnknown))?;
+        Ok(Some(ProxiedBlob::Streamed {
+            size: fetched.size,
+            content: stream::empty().boxed(),
+            cache: None,
+        }))
+    }
+
+    /// Finds the upstream registry covering a repository, either configured on the
+    /// repository itself or on the closest namespace, together with the name
+    /// of the corresponding upstream repository.
+    async fn resolve(&self, image: &str) -> Result<Option<Proxied>> {
+        let proxy = match self.find_proxy(image).await? {
+            Some(proxy) => proxy,
+            None => return Ok(None),
+        };
+        let upstream = match proxy.upstream() {
+            Some(upstream) => upstream.clone(),
+            None => return Ok(None),
+        };
+        let remote = remote_name(&upstream, &proxy.full_name(), image);
+        log::debug!("{} is proxied to {}/{}", image, upstream.url(), remote);
+        Ok(Some(Proxied {
+            proxy,
+            upstream,
+            remote,
+        }))
+    }
+
+    /// Saves a repository of the namespace of a proxy repository, if missing.
+    async fn create_repo(&self, proxy: &Repo, image: &str) -> Result<()> {
+        let id = Repo::build_id(image);
+        if self.repos.find(&id).await?.is_some() {
+            return Ok(());
+        }
+
+        log::debug!("creating repo {} for its proxy namespace", image);
+        match self.repos.save(new_repo(proxy, image)).await {
+            Ok(_) => Ok(()),
+            // a concurrent pull may have created it in the meantime
+            Err(err) => match self.repos.find(&id).await? {
+                Some(_) => Ok(()),
+                None => Err(err.into()),
+            },
+        }
+    }
+
+    /// Finds the proxy repository covering a repository, i.e. the repository itself
+    /// or the closest namespace having an upstream registry.
+    async fn find_proxy(&self, image: &str) -> Result<Option<Repo>> {
+        let mut namespace = image;
+        while !namespace.is_empty() {
+            if let Some(repo) = self.repos.find(&Repo::build_id(namespace)).await? {
+                if repo.upstream().is_some() {
+                    return Ok(Some(repo));
+                }
+            }
+            namespace = name::split(namespace).0;
+        }
+        Ok(None)
+    }
+
+    async fn pull_manifest(
+        &self,
+        proxied: &Proxied,
+        image: &str,
+        reference: &str,
+        cached: Option<Manifest>,
+    ) -> Result<Option<Manifest>> {
+        let (upstream, remote) = (&proxied.upstream, proxied.remote.as_str());
+        if let Some(cached) = cached {
+            match self
+                .client
+                .head_manifest(upstream, remote, reference)
+                .await?
+            {
+                Some(digest) if &digest == cached.digest() => {
+                    log::debug!("manifest {}:{} is still up to date", image, reference);
+                    return self.manifests.refresh_manifest(cached).await.map(Some);
+                }
+                Some(_) => {}
+                None => return Ok(None),
+            }
+        }
+
+        log::debug!("pulling manifest {}:{} from upstream", image, reference);
+        let fetched = match self
+            .client
+            .get_manifest(upstream, remote, reference)
+            .await?
+        {
+            Some(fetched) => fetched,
+            None => return Ok(None),
+        };
+        self.create_repo(&proxied.proxy, image).await?;
+        self.manifests
+            .cache_manifest(
+                image,
+                reference,
+                fetched.media_type.as_deref(),
+                fetched.content,
+            )
+            .await
+            .map(Some)
+    }
+
+    /// Links a blob whose content was already cached for another repository of the
+    /// same proxy namespace. Blobs of repositories outside of it are never linked, as they
+    /// may be private. If the repository doesn't exist yet, upstream must have the blob.
+    async fn link_cached_blob(
+        &self,
+        proxied: &Proxied,
+        image: &str,
+        digest: &Digest,
+    ) -> Result<Option<Blob>> {
+        let namespace = proxied.proxy.full_name();
+        let linked = self
+            .blobs
+            .find_one(serde_json::json!({
+                "digest": digest,
+                "image": { "$regex": format!("^{}(/|$)", namespace.replace('.', "\\.")) },
+            }))
+            .await?;
+        let linked = match linked {
+            Some(linked) => linked,
+            None => return Ok(None),
+        };
+
+        if self.repos.find(&Repo::build_id(image)).await?.is_none() {
+            let remote = self
+                .client
+                .head_blob(&proxied.upstream, &proxied.remote, digest)
+                .await?;
+            if remote.is_none() {
+                return Ok(None);
+            }
+            self.create_repo(&proxied.proxy, image).await?;
+        }
+        self.blobs.mount_blob(&linked, image).await.map(Some)
+    }
+}
+
+/// A repository covered by a proxy repository, i.e. the proxy repository itself
+/// or one of its namespace, with the name of the corresponding upstream repository.
+struct Proxied {
+    proxy: Repo,
+    upstream: Upstream,
+    remote: String,
+}
+
+/// A repository of the namespace of a proxy repository, as visible as the proxy repository.
+fn new_repo(proxy: &Repo, image: &str) -> Repo {
+    let (group, name) = name::split(image);
+    let mut repo = Repo::new(group, name, None);
+    repo.set_public(proxy.is_public());
+    repo
+}
+
+fn is_fresh(manifest: &Manifest, upstream: &Upstream) -> bool {
+    let ttl = Duration::seconds(upstream.ttl() as i64);
+    manifest
+        .fetched_at()
+        .map_or(true, |fetched_at| *fetched_at + ttl > Utc::now())
+}
+
+/// Maps a repository to its upstream counterpart. Repositories in the namespace of
+/// the proxy repository are mapped relatively to the upstream name, if any.
+fn remote_name(upstream: &Upstream, proxy: &str, image: &str) -> String {
+    let suffix = image[proxy.len()..].trim_start_matches('/');
+    match (upstream.name(), suffix) {
+        (Some(remote), "") => remote.to_string(),
+        (Some(remote), suffix) => name::join(remote, suffix),
+        (None, "") => image.to_string(),
+        (None, suffix) => suffix.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_maps_names_to_upstream() {
+        let hub = Upstream::new("https://registry-1.docker.io", None, 3600);
+        assert_eq!(
+            remote_name(&hub, "hub", "hub/library/alpine"),
+            "library/alpine"
+        );
+        assert_eq!(
+            remote_name(&hub, "library/alpine", "library/alpine"),
+            "library/alpine"
+        );
+
+        let library = Upstream::new("https://registry-1.docker.io", Some("library".into()), 3600);
+        assert_eq!(remote_name(&library, "hub", "hub/alpine"), "library/alpine");
+
+        let alpine = Upstream::new(
+            "https://registry-1.docker.io",
+            Some("library/alpine".into()),
+            3600,
+        );
+        assert_eq!(remote_name(&alpine, "alpine", "alpine"), "library/alpine");
+    }
+}
@@ -1,6 +1,8 @@
 use std::sync::{Arc, RwLock};
 
 use async_trait::async_trait;
+use futures::stream::BoxStream;
+use futures::TryStreamExt;
 
 use enseada::couchdb::db::Database;
 use enseada::couchdb::repository::{Entity, Repository};
@@ -29,6 +31,17 @@ impl RepoService {
         Self { db, bus, tags_view }
     }
 
+    /// Streams all repositories ordered by name, starting after the given one.
+    pub fn list_after(&self, last: Option<&str>) -> BoxStream<'_, Result<Repo>> {
+        let after = Repo::build_guid(&Repo::build_id(last.unwrap_or_default()));
+        let stream = self.find_all_stream(serde_json::json!({
+            "_id": {
+                "$gt": after.to_string(),
+            }
+        }));
+        Box::pin(stream.map_err(Error::from))
+    }
+
     pub async fn list_all_repo_tags(&self, repo: &Repo) -> Result<Vec<String>> {
         let res: RowsResponse<Manifest> = self
             .tags_view
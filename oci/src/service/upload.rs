@@ -1,48 +1,62 @@
-use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 
 use async_trait::async_trait;
-use futures::{stream, Stream, StreamExt};
+use futures::{future, stream, Stream, StreamExt, TryStreamExt};
 
 use enseada::couchdb::db::Database;
 use enseada::couchdb::repository::{Entity, Repository};
+use enseada::quota::QuotaStatus;
 use enseada::storage::blob::Blob as StorageBlob;
 use enseada::storage::{ByteChunk, Provider};
-use events::EventHandler;
+use events::{EventBus, EventHandler};
 
 use crate::digest::Digest;
 use crate::entity::{Repo, Upload, UploadChunk};
 use crate::error::{Error, ErrorCode};
 use crate::events::RepoDeleted;
-use crate::{storage, Result};
-use bytes::Bytes;
+use crate::service::BlobService;
+use crate::{name, storage, Result};
+use bytes::BytesMut;
+
+/// Upper bound of the memory used to buffer bodies of unknown size.
+const PART_SIZE: usize = 16 * 1024 * 1024;
 
 #[derive(Debug)]
 pub struct UploadService {
     db: Arc<Database>,
     store: Arc<Provider>,
+    blobs: BlobService,
 }
 
 impl UploadService {
-    pub fn new(db: Arc<Database>, store: Arc<Provider>) -> Self {
-        Self { db, store }
+    pub fn new(db: Arc<Database>, bus: Arc<RwLock<EventBus>>, store: Arc<Provider>) -> Self {
+        Self {
+            blobs: BlobService::new(db.clone(), bus, store.clone()),
+            db,
+            store,
+        }
     }
 
     pub async fn start_upload(&self, repo: &Repo) -> Result<Upload> {
-        let upload = Upload::new(repo.group(), repo.name());
+        let upload = Upload::new(&repo.full_name());
         self.save(upload).await.map_err(Error::from)
     }
 
-    // pub async fn push_chunk<B: Stream<Item = ByteChunk> + Send + Sync + 'static>(
-    pub async fn push_chunk(
+    /// Streams a chunk body into storage. If the body size is not known in advance
+    /// (e.g. chunked transfer encoding), the body is split into parts of bounded
+    /// size, each one stored as a separate chunk.
+    pub async fn push_chunk<B: Stream<Item = ByteChunk> + Send + Sync + 'static>(
         &self,
         mut upload: Upload,
-        chunk: UploadChunk,
-        body: Bytes,
+        start_range: usize,
+        size: Option<usize>,
+        body: B,
     ) -> Result<Upload> {
-        if chunk.start_range() != upload.latest_offset() {
+        if start_range != upload.latest_offset() {
             log::debug!(
                 "chunk range {} is not compatible with current offset {}",
-                chunk.start_range(),
+                start_range,
                 upload.latest_offset()
             );
             return Err(Error::from(ErrorCode::RequestedRangeNotSatisfiable(
@@ -50,30 +64,41 @@ impl UploadService {
             )));
         }
 
-        upload.add_chunk(chunk);
-        let chunks = upload.chunks();
-        let chunk = chunks.last().unwrap();
-        let key = chunk.storage_key().unwrap();
-
-        let blob = StorageBlob::new(
-            key.to_string(),
-            chunk.size(),
-            stream::once(async move { Ok(body) }),
-        );
-        log::debug!("storing blob {}", key);
-        self.store.store_blob(blob).await?;
-        log::debug!("blob stored");
+        match size {
+            Some(size) => {
+                self.store_chunk(&mut upload, start_range, size, body)
+                    .await?;
+            }
+            None => {
+                let mut body = Box::pin(body);
+                let mut offset = start_range;
+                let mut part = BytesMut::new();
+                while let Some(bytes) = body.next().await {
+                    let bytes =
+                        bytes.map_err(|err| Error::new(ErrorCode::BlobUploadInvalid, err))?;
+                    part.extend_from_slice(&bytes);
+                    if part.len() >= PART_SIZE {
+                        offset = self.store_part(&mut upload, offset, part.split()).await?;
+                        // parts are saved as soon as they are stored, so that they are
+                        // still tracked by the upload if the rest of the body fails
+                        upload = self.save(upload).await?;
+                    }
+                }
+                self.store_part(&mut upload, offset, part).await?;
+            }
+        }
 
         let upload = self.save(upload).await?;
         Ok(upload)
     }
 
-    // pub async fn complete_upload<B: Stream<Item = ByteChunk> + Send + Sync + 'static>(
+    /// Stores the content of an upload as a blob, once verified against its digest.
+    /// Uploads that would exceed the hard storage quota of the repository are rejected.
     pub async fn complete_upload(
         &self,
+        repo: &Repo,
         mut upload: Upload,
         digest: &Digest,
-        chunk: Option<(UploadChunk, Bytes)>,
     ) -> Result<Upload> {
         log::debug!(
             "completing upload {} with digest {}",
@@ -95,32 +120,178 @@ impl UploadService {
             blobs.push(blob);
         }
 
-        if let Some((chunk, body)) = chunk {
-            // we don't care about the storage key because this blob will never be stored
-            // see stream mapping a few line below
-            let blob = StorageBlob::new(
-                "we-dont-care",
-                chunk.size(),
-                stream::once(async move { Ok(body) }),
-            );
-            blobs.push(blob);
+        let size = blobs.iter().map(StorageBlob::size).sum();
+        if let Err(err) = self.check_quota(repo, digest, size).await {
+            self.delete(&upload).await?;
+            return Err(err);
         }
 
-        let size = blobs.iter().map(StorageBlob::size).sum();
+        let hasher = Arc::new(Mutex::new(digest.algo().hasher()));
+        let stream_hasher = hasher.clone();
         let buf = stream::iter(blobs.into_iter())
             .map(StorageBlob::into_byte_stream)
-            .flatten();
+            .flatten()
+            .inspect(move |chunk| {
+                if let Ok(bytes) = chunk {
+                    stream_hasher.lock().unwrap().update(bytes);
+                }
+            });
 
         let blob_key = storage::blob_key(digest);
-        log::debug!("storing blob {}", blob_key);
-        let blob = StorageBlob::new(blob_key, size, buf);
-        self.store.store_blob(blob).await?;
-        log::debug!("blob stored");
+        // blobs are content-addressed, so an existing blob must never be overwritten
+        // by unverified content. In that case the upload is only hashed, otherwise
+        // it's staged and only moved to the blob key once its digest is verified.
+        let staging_key = storage::staging_key(upload.id().id());
+        let staged = if self.store.is_blob_present(&blob_key).await? {
+            log::debug!("blob {} already present, verifying upload only", blob_key);
+            buf.try_for_each(|_| future::ok(()))
+                .await
+                .map_err(|err| Error::new(ErrorCode::BlobUploadInvalid, err))?;
+            false
+        } else {
+            log::debug!("staging blob {}", staging_key);
+            let blob = StorageBlob::new(staging_key.clone(), size, buf);
+            if let Err(err) = self.store.store_blob(blob).await {
+                self.discard_staged(&staging_key).await;
+                return Err(err.into());
+            }
+            true
+        };
+
+        let computed = hasher.lock().unwrap().clone().finish();
+        if &computed != digest {
+            log::warn!(
+                "upload {} has digest {} instead of {}, rolling back",
+                upload.id().id(),
+                computed,
+                digest
+            );
+            if staged {
+                self.discard_staged(&staging_key).await;
+            }
+            self.delete(&upload).await?;
+            return Err(
+                Error::from(ErrorCode::DigestInvalid).with_detail("digest", digest.to_string())
+            );
+        }
+
+        if staged {
+            log::debug!("moving blob {} to {}", staging_key, blob_key);
+            self.store.move_blob(&staging_key, &blob_key).await?;
+            log::debug!("blob stored");
+        }
+
         log::debug!("deleting upload");
         self.delete(&upload).await?;
         log::debug!("upload deleted");
         Ok(upload)
     }
+
+    /// Deletes staged content. Failures are only logged, as staged keys
+    /// never hold content that is served.
+    async fn discard_staged(&self, staging_key: &str) {
+        if let Err(err) = self.store.delete_blob(staging_key).await {
+            log::warn!("failed to delete staged blob {}: {}", staging_key, err);
+        }
+    }
+
+    async fn check_quota(&self, repo: &Repo, digest: &Digest, size: usize) -> Result<()> {
+        let quota = repo.quota();
+        if quota.is_unlimited() {
+            return Ok(());
+        }
+
+        let image = repo.full_name();
+        let linked = self.blobs.find_by_digest(&image, digest).await?.is_some();
+        let usage = self.blobs.usage(&image).await? + if linked { 0 } else { size as u64 };
+        match quota.status(usage) {
+            QuotaStatus::HardExceeded => {
+                log::warn!(
+                    "rejecting blob {} for {}, it exceeds the hard storage quota",
+                    digest,
+                    image
+                );
+                Err(
+                    Error::new(ErrorCode::Denied, "repository storage quota exceeded")
+                        .with_detail("quota", quota.hard().unwrap_or(0)),
+                )
+            }
+            QuotaStatus::SoftExceeded => {
+                log::warn!(
+                    "{} exceeds its soft storage quota, using {} bytes",
+                    image,
+                    usage
+                );
+                Ok(())
+            }
+            QuotaStatus::Within => Ok(()),
+        }
+    }
+
+    async fn store_part(
+        &self,
+        upload: &mut Upload,
+        start_range: usize,
+        part: BytesMut,
+    ) -> Result<usize> {
+        let part = part.freeze();
+        self.store_chunk(
+            upload,
+            start_range,
+            part.len(),
+            stream::once(future::ok(part)),
+        )
+        .await
+    }
+
+    /// Stores a chunk of known size, counting the bytes as they stream through.
+    /// Returns the offset the next chunk should start from.
+    async fn store_chunk<B: Stream<Item = ByteChunk> + Send + Sync + 'static>(
+        &self,
+        upload: &mut Upload,
+        start_range: usize,
+        size: usize,
+        body: B,
+    ) -> Result<usize> {
+        if size == 0 {
+            return Ok(start_range);
+        }
+
+        let received = Arc::new(AtomicUsize::new(0));
+        let counter = received.clone();
+        let body = body.inspect(move |chunk| {
+            if let Ok(bytes) = chunk {
+                counter.fetch_add(bytes.len(), Ordering::Relaxed);
+            }
+        });
+
+        upload.add_chunk(UploadChunk::new(start_range, start_range + size));
+        let chunks = upload.chunks();
+        let key = chunks.last().unwrap().storage_key().unwrap().to_string();
+        log::debug!("storing chunk {}", &key);
+        if let Err(err) = self
+            .store
+            .store_blob(StorageBlob::new(key.clone(), size, body))
+            .await
+        {
+            if let Err(err) = self.store.delete_blob(&key).await {
+                log::warn!("failed to delete chunk {}: {}", &key, err);
+            }
+            return Err(err.into());
+        }
+        log::debug!("chunk stored");
+
+        let received = received.load(Ordering::Relaxed);
+        if received != size {
+            log::debug!("chunk {} has {} bytes instead of {}", &key, received, size);
+            self.store.delete_blob(&key).await?;
+            return Err(Error::new(
+                ErrorCode::BlobUploadInvalid,
+                "chunk size does not match its declared length",
+            ));
+        }
+        Ok(start_range + size)
+    }
 }
 
 #[async_trait]
@@ -146,7 +317,7 @@ impl Repository<Upload> for UploadService {
 #[async_trait]
 impl EventHandler<RepoDeleted> for UploadService {
     async fn handle(&self, event: &RepoDeleted) {
-        let image = format!("{}/{}", &event.group, &event.name);
+        let image = name::join(&event.group, &event.name);
         if let Err(err) = self
             .delete_all(serde_json::json!({
               "image": image,
@@ -1,12 +1,18 @@
 use crate::digest::Digest;
 
+pub const UPLOADS_PREFIX: &str = "artifacts/oci/uploads/";
+pub const BLOBS_PREFIX: &str = "artifacts/oci/blobs/";
+
 pub fn chunk_key(upload_id: &str, chunk_offset: usize) -> String {
-    format!(
-        "artifacts/oci/uploads/{}/chunks/{}",
-        upload_id, chunk_offset
-    )
+    format!("{}{}/chunks/{}", UPLOADS_PREFIX, upload_id, chunk_offset)
+}
+
+/// Where the content of a blob is staged until its digest is verified,
+/// since content-addressed keys must only ever hold verified content.
+pub fn staging_key(id: &str) -> String {
+    format!("{}{}/blob", UPLOADS_PREFIX, id)
 }
 
 pub fn blob_key(digest: &Digest) -> String {
-    format!("artifacts/oci/blobs/{}", digest.to_string())
+    format!("{}{}", BLOBS_PREFIX, digest)
 }
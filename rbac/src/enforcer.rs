@@ -125,6 +125,34 @@ impl Enforcer {
         }
     }
 
+    /// Checks a permission on an object whose id is a `/`-separated path.
+    /// Permissions granted on any of its parent namespaces apply to the object as well,
+    /// so that `oci_repo:org/team` covers `oci_repo:org/team/service`.
+    #[tracing::instrument]
+    pub fn check_nested(&self, sub: &Guid, obj: &Guid, act: &str) -> Result<(), EvaluationError> {
+        if sub.to_string() == ROOT_USER {
+            return Ok(());
+        }
+
+        let sub = &sub.to_string();
+        for obj in namespaces(obj) {
+            let obj = &obj.to_string();
+            log::info!(
+                "Evaluating permission sub: {}, obj: {}, act: {}",
+                sub,
+                obj,
+                act
+            );
+            if self.model.check(sub, obj, act) == EvaluationResult::Granted {
+                log::info!("Access Granted");
+                return Ok(());
+            }
+        }
+
+        log::warn!("Access Denied");
+        Err(EvaluationError::Denied)
+    }
+
     #[tracing::instrument]
     pub async fn add_permission(&self, sub: Guid, obj: Guid, act: &str) -> Result<(), Error> {
         if sub.to_string() == ROOT_USER {
@@ -295,3 +323,43 @@ impl Display for EvaluationError {
 }
 
 impl std::error::Error for EvaluationError {}
+
+fn namespaces(obj: &Guid) -> Vec<Guid> {
+    let build = |id: &str| match obj.partition() {
+        Some(partition) => Guid::partitioned(partition, id),
+        None => Guid::simple(id),
+    };
+    let id = obj.id();
+    let mut namespaces = vec![build(id)];
+    namespaces.extend(
+        id.match_indices('/')
+            .rev()
+            .map(|(idx, _)| build(&id[..idx])),
+    );
+    namespaces
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_lists_object_namespaces() {
+        let obj = Guid::partitioned("oci_repo", "org/team/service");
+        let namespaces: Vec<String> = namespaces(&obj).iter().map(Guid::to_string).collect();
+        assert_eq!(
+            namespaces,
+            vec![
+                "oci_repo:org/team/service",
+                "oci_repo:org/team",
+                "oci_repo:org"
+            ]
+        );
+    }
+
+    #[test]
+    fn it_lists_a_single_namespace_for_flat_objects() {
+        let obj = Guid::partitioned("oci_repo", "alpine");
+        assert_eq!(namespaces(&obj), vec![obj]);
+    }
+}
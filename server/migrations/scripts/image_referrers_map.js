@@ -0,0 +1,5 @@
+function (doc) {
+  if (doc.manifest && doc.manifest.subject && doc.reference === doc.digest) {
+    emit(doc.image + "@" + doc.manifest.subject.digest, doc.reference);
+  }
+}
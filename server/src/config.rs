@@ -72,15 +72,37 @@ pub enum Storage {
         endpoint: Option<String>,
         access_key_id: Option<String>,
         secret_access_key: Option<String>,
+        presign: Presign,
     },
     #[serde(other)]
     Unknown,
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Presign {
+    enabled: bool,
+    ttl: u64,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct OCI {
     host: String,
     max_body_size: usize,
+    gc: GarbageCollection,
+    retention: Retention,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GarbageCollection {
+    enabled: bool,
+    interval: u64,
+    grace_period: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Retention {
+    enabled: bool,
+    interval: u64,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -111,11 +133,18 @@ impl Configuration {
         };
         c.set_default("public.url", format!("{}://localhost:{}", proto, port))?;
 
+        c.set_default("storage.presign.enabled", false)?;
+        c.set_default("storage.presign.ttl", 300)?; // 5 minutes
         c.set_default("log.level", "info")?;
         c.set_default("log.root_level", "warn")?;
         c.set_default("couchdb.url", "http://localhost:5984")?;
         c.set_default("oci.host", "containers.localhost")?;
         c.set_default("oci.max_body_size", 10_737_418_240)?; // 10 Gib
+        c.set_default("oci.gc.enabled", false)?;
+        c.set_default("oci.gc.interval", 86_400)?; // 1 day
+        c.set_default("oci.gc.grace_period", 3_600)?; // 1 hour
+        c.set_default("oci.retention.enabled", false)?;
+        c.set_default("oci.retention.interval", 86_400)?; // 1 day
         c.set_default("tracing.log", false)?;
         c.set_default("tracing.level", "info")?;
 
@@ -251,6 +280,52 @@ impl OCI {
     pub fn max_body_size(&self) -> usize {
         self.max_body_size
     }
+
+    pub fn gc(&self) -> &GarbageCollection {
+        &self.gc
+    }
+
+    pub fn retention(&self) -> &Retention {
+        &self.retention
+    }
+}
+
+impl GarbageCollection {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Interval between scheduled runs, in seconds.
+    pub fn interval(&self) -> u64 {
+        self.interval
+    }
+
+    /// Minimum age of blobs and uploads to be collected, in seconds.
+    pub fn grace_period(&self) -> u64 {
+        self.grace_period
+    }
+}
+
+impl Retention {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Interval between scheduled runs, in seconds.
+    pub fn interval(&self) -> u64 {
+        self.interval
+    }
+}
+
+impl Presign {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Validity of pre-signed URLs, in seconds.
+    pub fn ttl(&self) -> u64 {
+        self.ttl
+    }
 }
 
 impl Tracing {
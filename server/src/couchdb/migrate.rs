@@ -3,14 +3,19 @@ use std::io::{Error, ErrorKind};
 use std::iter::FromIterator;
 
 use include_dir::Dir;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
 
 use couchdb::db::Database;
 use couchdb::migrator::Migrator;
 use couchdb::{Couch, Result};
 use enseada::couchdb::repository::Entity;
+use enseada::guid::Guid;
 use oauth::client::Client;
 use oauth::persistence::client::ClientEntity;
 use oauth::scope::Scope;
+use oci::entity::{Blob, Manifest, Repo};
+use rbac::Rule;
 use users::User;
 
 use crate::config::Configuration;
@@ -88,6 +93,10 @@ async fn run(couch: &Couch, cfg: &Configuration) -> Result<()> {
     let root_pwd = cfg.root_password();
     create_root_user(&users_db, root_pwd).await?;
 
+    let oci_db = couch.database(crate::couchdb::name::OCI, true);
+    let rbac_db = couch.database(crate::couchdb::name::RBAC, true);
+    migrate_oci_ids(&oci_db, &rbac_db).await?;
+
     log::info!("Migrations completed");
     Ok(())
 }
@@ -115,6 +124,119 @@ async fn create_root_user(db: &Database, password: String) -> Result<()> {
     db.put(&user.id().to_string(), user).await.map(|_| ())
 }
 
+/// Repositories used to be identified by `group-name` and manifests by `group-name:reference`,
+/// before repository names could have any number of components, and blobs by their digest,
+/// before they could be linked to several repositories. Documents with the old ids,
+/// and the RBAC rules on the old repository ids, are moved to the new ones.
+async fn migrate_oci_ids(oci_db: &Database, rbac_db: &Database) -> Result<()> {
+    log::debug!("Migrating OCI document ids");
+    let repos = find_misplaced(oci_db, "oci_repo", |repo: &Repo| {
+        Repo::build_guid(&Repo::build_id(&repo.full_name()))
+    })
+    .await?;
+
+    // Rules are moved first, so that an interrupted migration still finds
+    // the old repository ids when it runs again.
+    let moved: HashMap<String, String> = repos
+        .iter()
+        .map(|(doc, id)| (doc_id(doc), id.to_string()))
+        .collect();
+    migrate_rules(rbac_db, &moved).await?;
+    for (doc, id) in repos {
+        move_doc(oci_db, doc, &id).await?;
+    }
+
+    let manifests = find_misplaced(oci_db, "oci_manifest", |manifest: &Manifest| {
+        Manifest::build_guid(&Manifest::build_id(manifest.image(), manifest.reference()))
+    })
+    .await?;
+    for (doc, id) in manifests {
+        move_doc(oci_db, doc, &id).await?;
+    }
+
+    let blobs = find_misplaced(oci_db, "oci_blob", |blob: &Blob| {
+        Blob::build_guid(&Blob::build_id(blob.image(), blob.digest()))
+    })
+    .await?;
+    for (doc, id) in blobs {
+        move_doc(oci_db, doc, &id).await?;
+    }
+    Ok(())
+}
+
+async fn migrate_rules(db: &Database, moved: &HashMap<String, String>) -> Result<()> {
+    if moved.is_empty() {
+        return Ok(());
+    }
+
+    let partition = Rule::build_guid("");
+    let rules = db
+        .list_all_partitioned::<Value>(partition.partition().unwrap())
+        .await?;
+    for doc in rules.rows.into_iter().filter_map(|row| row.doc) {
+        let obj = match doc["obj"].as_str().and_then(|obj| moved.get(obj)) {
+            Some(obj) => obj.clone(),
+            None => continue,
+        };
+        let sub = doc["sub"].as_str().unwrap_or_default().to_string();
+        let act = doc["act"].as_str().unwrap_or_default().to_string();
+        let id = Rule::build_id(&sub, &obj, &act);
+
+        let mut rule = doc;
+        rule["obj"] = Value::from(obj);
+        move_doc(db, rule, &id).await?;
+    }
+    Ok(())
+}
+
+/// Finds the documents of a partition whose id is not the one built from their content,
+/// paired with the id they should have.
+async fn find_misplaced<T, F>(
+    db: &Database,
+    partition: &str,
+    build_guid: F,
+) -> Result<Vec<(Value, Guid)>>
+where
+    T: DeserializeOwned,
+    F: Fn(&T) -> Guid,
+{
+    let docs = db.list_all_partitioned::<Value>(partition).await?;
+    let mut misplaced = Vec::new();
+    for doc in docs.rows.into_iter().filter_map(|row| row.doc) {
+        let entity = match serde_json::from_value::<T>(doc.clone()) {
+            Ok(entity) => entity,
+            Err(err) => {
+                log::warn!("Skipping document {}: {}", doc_id(&doc), err);
+                continue;
+            }
+        };
+        let id = build_guid(&entity);
+        if doc_id(&doc) != id.to_string() {
+            misplaced.push((doc, id));
+        }
+    }
+    Ok(misplaced)
+}
+
+/// Moves a document to a new id. If a previous run already created it, only the old one is deleted.
+async fn move_doc(db: &Database, mut doc: Value, id: &Guid) -> Result<()> {
+    let old_id = doc_id(&doc);
+    let rev = doc["_rev"].as_str().unwrap_or_default().to_string();
+    log::debug!("Moving document {} to {}", &old_id, id);
+    if !db.exists(&id.to_string()).await? {
+        doc["_id"] = Value::from(id.to_string());
+        if let Some(doc) = doc.as_object_mut() {
+            doc.remove("_rev");
+        }
+        db.put(&id.to_string(), &doc).await?;
+    }
+    db.delete(&old_id, &rev).await
+}
+
+fn doc_id(doc: &Value) -> String {
+    doc["_id"].as_str().unwrap_or_default().to_string()
+}
+
 fn filter_file_with_ext(ext: &'static str) -> Box<dyn FnMut(&&include_dir::File) -> bool> {
     Box::new(move |file: &&include_dir::File| {
         if let Some(file_ext) = file.path().extension() {
@@ -23,6 +23,7 @@ pub enum ApiError {
     BlockingError(String),
     Conflict(String),
     Forbidden(String),
+    InsufficientStorage(String),
     InternalServerError(String),
     NotFound(String),
     #[display(fmt = "")]
@@ -45,6 +46,7 @@ impl ApiError {
             StatusCode::BAD_REQUEST => ApiError::BadRequest(reason),
             StatusCode::CONFLICT => ApiError::Conflict(reason),
             StatusCode::FORBIDDEN => ApiError::Forbidden(reason),
+            StatusCode::INSUFFICIENT_STORAGE => ApiError::InsufficientStorage(reason),
             StatusCode::NOT_FOUND => ApiError::NotFound(reason),
             StatusCode::UNAUTHORIZED => ApiError::Unauthorized(reason),
             StatusCode::SERVICE_UNAVAILABLE => ApiError::ServiceUnavailable(reason),
@@ -70,6 +72,7 @@ impl ResponseError for ApiError {
             ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
             ApiError::Conflict(_) => StatusCode::CONFLICT,
             ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::InsufficientStorage(_) => StatusCode::INSUFFICIENT_STORAGE,
             ApiError::NotFound(_) => StatusCode::NOT_FOUND,
             ApiError::ValidationError(_) => StatusCode::BAD_REQUEST,
             ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
@@ -85,6 +88,11 @@ impl ResponseError for ApiError {
                 .json::<ErrorResponse>(ErrorResponse::new(self.status_code(), vec![error.clone()])),
             ApiError::Forbidden(error) => HttpResponse::Forbidden()
                 .json::<ErrorResponse>(ErrorResponse::new(self.status_code(), vec![error.clone()])),
+            ApiError::InsufficientStorage(error) => HttpResponse::InsufficientStorage()
+                .json::<ErrorResponse>(ErrorResponse::new(
+                self.status_code(),
+                vec![error.clone()],
+            )),
             ApiError::NotFound(error) => HttpResponse::NotFound()
                 .json::<ErrorResponse>(ErrorResponse::new(self.status_code(), vec![error.clone()])),
             ApiError::ValidationError(errors) => HttpResponse::UnprocessableEntity()
@@ -126,6 +134,7 @@ impl From<Error> for ApiError {
         match err.status() {
             StatusCode::CONFLICT => ApiError::Conflict(message),
             StatusCode::NOT_FOUND => ApiError::NotFound(message),
+            StatusCode::INSUFFICIENT_STORAGE => ApiError::InsufficientStorage(message),
             _ => ApiError::InternalServerError(message),
         }
     }
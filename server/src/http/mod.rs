@@ -1,4 +1,9 @@
-use serde::Deserialize;
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use enseada::quota::Quota;
+use enseada::replication::Remote;
 
 use crate::http::error::ApiError;
 
@@ -34,3 +39,61 @@ impl PaginationQuery {
         self.offset
     }
 }
+
+/// Checks that the soft limit of a storage quota is not above the hard one.
+pub fn validate_quota(quota: &Quota) -> ApiResult<()> {
+    match (quota.soft(), quota.hard()) {
+        (Some(soft), Some(hard)) if soft > hard => Err(ApiError::BadRequest(format!(
+            "soft quota ({} bytes) is above the hard quota ({} bytes)",
+            soft, hard
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that the repository patterns of a replication rule or notification endpoint are valid globs.
+pub fn validate_patterns(patterns: &[String]) -> ApiResult<()> {
+    for pattern in patterns {
+        Pattern::new(pattern).map_err(|err| {
+            ApiError::BadRequest(format!("invalid repository pattern '{}': {}", pattern, err))
+        })?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RemotePayload {
+    url: String,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+impl RemotePayload {
+    pub fn to_remote(&self) -> ApiResult<Remote> {
+        Url::parse(&self.url)
+            .map_err(|err| ApiError::BadRequest(format!("invalid remote url: {}", err)))?;
+        let remote = Remote::new(&self.url);
+        Ok(match (&self.username, &self.password) {
+            (Some(username), Some(password)) => {
+                remote.with_credentials(username.clone(), password.clone())
+            }
+            _ => remote,
+        })
+    }
+}
+
+/// A replication target, without its password.
+#[derive(Debug, Serialize)]
+pub struct RemoteResponse {
+    url: String,
+    username: Option<String>,
+}
+
+impl From<&Remote> for RemoteResponse {
+    fn from(remote: &Remote) -> Self {
+        Self {
+            url: remote.url().to_string(),
+            username: remote.username().map(str::to_string),
+        }
+    }
+}
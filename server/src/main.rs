@@ -37,6 +37,18 @@ mod user;
 struct Cli {
     #[structopt(short, long, default_value = "enseada")]
     config: PathBuf,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Deletes unreferenced OCI blobs and stale uploads, then exits
+    Gc {
+        /// Only report what would be deleted
+        #[structopt(long)]
+        dry_run: bool,
+    },
 }
 
 #[actix_rt::main]
@@ -50,6 +62,10 @@ async fn main() -> std::io::Result<()> {
 
     couchdb::migrate(&cfg).await?;
 
+    if let Some(Command::Gc { dry_run }) = args.command {
+        return oci::gc::run(&cfg, dry_run).await;
+    }
+
     log::info!("Starting Enseada...");
 
     server::run(cfg).await?;
@@ -1,14 +1,17 @@
 use std::sync::Arc;
 
 use actix_web::web::{Data, Json, Path, Query};
-use actix_web::{delete, get, post};
+use actix_web::{delete, get, post, put};
 use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
+use url::Url;
 
 use enseada::couchdb::repository::{Entity, Repository};
 use enseada::guid::Guid;
 use enseada::pagination::Page;
-use maven::entity::Repo;
+use enseada::quota::Quota;
+use maven::entity::{Repo, RepoKind, Upstream};
+use maven::group::{Group, GroupService};
 use maven::service::RepoService;
 use oauth::scope::Scope;
 use rbac::Enforcer;
@@ -16,7 +19,10 @@ use rbac::Enforcer;
 use crate::http::error::ApiError;
 use crate::http::extractor::scope::OAuthScope;
 use crate::http::extractor::user::CurrentUser;
-use crate::http::{ApiResult, PaginationQuery};
+use crate::http::{validate_quota, ApiResult, PaginationQuery};
+
+const DEFAULT_METADATA_TTL: u64 = 3600;
+const DEFAULT_NOT_FOUND_TTL: u64 = 600;
 
 #[derive(Debug, Deserialize)]
 pub struct RepoPath {
@@ -29,6 +35,19 @@ pub struct RepoResponse {
     group_id: String,
     artifact_id: String,
     public: bool,
+    quota: Quota,
+    usage: u64,
+    snapshot_retention: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upstream: Option<UpstreamResponse>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UpstreamResponse {
+    url: String,
+    username: Option<String>,
+    metadata_ttl: u64,
+    not_found_ttl: u64,
 }
 
 impl From<&Repo> for RepoResponse {
@@ -37,6 +56,21 @@ impl From<&Repo> for RepoResponse {
             group_id: repo.group_id().to_string(),
             artifact_id: repo.artifact_id().to_string(),
             public: repo.is_public(),
+            quota: repo.quota().clone(),
+            usage: repo.usage(),
+            snapshot_retention: repo.snapshot_retention(),
+            upstream: repo.upstream().map(UpstreamResponse::from),
+        }
+    }
+}
+
+impl From<&Upstream> for UpstreamResponse {
+    fn from(upstream: &Upstream) -> Self {
+        Self {
+            url: upstream.url().to_string(),
+            username: upstream.username().map(str::to_string),
+            metadata_ttl: upstream.metadata_ttl(),
+            not_found_ttl: upstream.not_found_ttl(),
         }
     }
 }
@@ -71,11 +105,59 @@ pub struct CreateRepoPayload {
     artifact_id: String,
     #[serde(default)]
     public: bool,
+    #[serde(default)]
+    quota: Quota,
+    snapshot_retention: Option<usize>,
+    upstream: Option<UpstreamPayload>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpstreamPayload {
+    url: String,
+    username: Option<String>,
+    password: Option<String>,
+    #[serde(default = "default_metadata_ttl")]
+    metadata_ttl: u64,
+    #[serde(default = "default_not_found_ttl")]
+    not_found_ttl: u64,
+}
+
+impl UpstreamPayload {
+    fn to_upstream(&self) -> ApiResult<Upstream> {
+        Url::parse(&self.url)
+            .map_err(|err| ApiError::BadRequest(format!("invalid upstream url: {}", err)))?;
+
+        let upstream = Upstream::new(&self.url, self.metadata_ttl, self.not_found_ttl);
+        Ok(match (&self.username, &self.password) {
+            (Some(username), Some(password)) => {
+                upstream.with_credentials(username.clone(), password.clone())
+            }
+            _ => upstream,
+        })
+    }
+}
+
+fn default_metadata_ttl() -> u64 {
+    DEFAULT_METADATA_TTL
+}
+
+fn default_not_found_ttl() -> u64 {
+    DEFAULT_NOT_FOUND_TTL
+}
+
+fn validate_snapshot_retention(retention: Option<usize>) -> ApiResult<()> {
+    if retention == Some(0) {
+        return Err(ApiError::BadRequest(
+            "snapshot retention must keep at least 1 build".to_string(),
+        ));
+    }
+    Ok(())
 }
 
 #[post("/api/maven/v1beta1/repositories")]
 pub async fn create_repo(
     service: Data<RepoService>,
+    groups: Data<GroupService>,
     enforcer: Data<Arc<RwLock<Enforcer>>>,
     scope: OAuthScope,
     current_user: CurrentUser,
@@ -85,7 +167,23 @@ pub async fn create_repo(
     let enforcer = enforcer.read().await;
     enforcer.check(current_user.id(), &Guid::simple("maven_repos"), "create")?;
 
-    let repo = Repo::new(&body.group_id, &body.artifact_id, body.public);
+    validate_quota(&body.quota)?;
+    validate_snapshot_retention(body.snapshot_retention)?;
+    let mut repo = Repo::new(&body.group_id, &body.artifact_id, body.public);
+    if let Some((name, _)) = Group::split_path(repo.location()) {
+        if groups.find_group(name).await?.is_some() {
+            return Err(ApiError::Conflict(format!(
+                "repository '{}' would be hidden by the Maven group '{}'",
+                repo.location(),
+                name
+            )));
+        }
+    }
+    repo.set_quota(body.quota.clone())
+        .set_snapshot_retention(body.snapshot_retention);
+    if let Some(upstream) = &body.upstream {
+        repo.set_kind(RepoKind::Proxy(upstream.to_upstream()?));
+    }
     let repo = service.save(repo).await?;
 
     Ok(Json(RepoResponse::from(repo)))
@@ -115,6 +213,57 @@ pub async fn get_repo(
     Ok(Json(RepoResponse::from(repo)))
 }
 
+#[derive(Debug, Deserialize)]
+pub struct UpdateRepoPayload {
+    quota: Option<Quota>,
+    snapshot_retention: Option<usize>,
+    upstream: Option<UpstreamPayload>,
+}
+
+#[put("/api/maven/v1beta1/repositories/{group_id}/{artifact_id}")]
+pub async fn update_repo(
+    service: Data<RepoService>,
+    enforcer: Data<Arc<RwLock<Enforcer>>>,
+    scope: OAuthScope,
+    current_user: CurrentUser,
+    path: Path<RepoPath>,
+    body: Json<UpdateRepoPayload>,
+) -> ApiResult<Json<RepoResponse>> {
+    Scope::from("maven:repos:manage").matches(&scope)?;
+    let enforcer = enforcer.read().await;
+    let id = &Repo::build_id(&path.group_id, &path.artifact_id);
+    enforcer.check(current_user.id(), &Repo::build_guid(id), "update")?;
+
+    let mut repo = service
+        .find(id)
+        .await?
+        .ok_or_else(|| ApiError::not_found(&format!("Maven repository '{}' not found", id)))?;
+
+    log::debug!("updating Maven repository {}", id);
+    if let Some(quota) = &body.quota {
+        validate_quota(quota)?;
+        repo.set_quota(quota.clone());
+    }
+
+    if body.snapshot_retention.is_some() {
+        validate_snapshot_retention(body.snapshot_retention)?;
+        repo.set_snapshot_retention(body.snapshot_retention);
+    }
+
+    if let Some(upstream) = &body.upstream {
+        if !repo.is_proxy() {
+            return Err(ApiError::BadRequest(format!(
+                "Maven repository '{}' is not a proxy repository",
+                id
+            )));
+        }
+        repo.set_kind(RepoKind::Proxy(upstream.to_upstream()?));
+    }
+
+    let repo = service.save(repo).await?;
+    Ok(Json(RepoResponse::from(repo)))
+}
+
 #[get("/api/maven/v1beta1/repositories/{group_id}/{artifact_id}/files")]
 pub async fn get_repo_files(
     service: Data<RepoService>,
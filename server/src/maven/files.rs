@@ -8,9 +8,13 @@ use tokio::sync::RwLock;
 use enseada::backports;
 use enseada::couchdb::repository::Entity;
 use enseada::error::Error;
+use maven::checksum::{checksum_target, parse_checksum, Checksums};
 use maven::entity::Repo;
 use maven::file::{parse_file_path, File};
+use maven::group::{Group, GroupService};
+use maven::metadata::{self, Metadata};
 use maven::service::RepoService;
+use maven::Version;
 use oauth::scope::Scope;
 use rbac::Enforcer;
 
@@ -18,15 +22,34 @@ use crate::http::error::ApiError;
 use crate::http::extractor::scope::OAuthScope;
 use crate::http::extractor::user::CurrentUser;
 use crate::http::ApiResult;
+use crate::storage::PresignedUrls;
 
 #[get("/maven2/{tail:.*}")]
 pub async fn get(
     repos: Data<RepoService>,
+    groups: Data<GroupService>,
+    presigned: Data<Arc<PresignedUrls>>,
     Path(location): Path<String>,
     current_user: Option<CurrentUser>,
     scope: Option<OAuthScope>,
     enforcer: Data<Arc<RwLock<Enforcer>>>,
 ) -> ApiResult<HttpResponse> {
+    // groups take precedence over the repositories sharing the first segment of their location
+    if let Some((name, path)) = Group::split_path(&location) {
+        if let Some(group) = groups.find_group(name).await? {
+            return get_from_group(
+                &repos,
+                &presigned,
+                &group,
+                path,
+                current_user.as_ref(),
+                scope.as_ref(),
+                &enforcer,
+            )
+            .await;
+        }
+    }
+
     let file_pointer = parse_file_path(&location)
         .ok_or_else(|| ApiError::invalid(format!("{} is not a valid Maven path", location)))?;
 
@@ -48,17 +71,149 @@ pub async fn get(
         }
     }
 
-    let file = repos
-        .get_file(&repo, file_pointer.version(), file_pointer.filename())
-        .await?;
+    serve_file(
+        &repos,
+        &presigned,
+        &repo,
+        file_pointer.version(),
+        file_pointer.filename(),
+    )
+    .await
+}
+
+/// Serves a file from the first member of a group serving its location and having it,
+/// while the artifact metadata is merged across all of them. Private members are
+/// skipped unless the current user can pull from them.
+async fn get_from_group(
+    repos: &RepoService,
+    presigned: &PresignedUrls,
+    group: &Group,
+    path: &str,
+    current_user: Option<&CurrentUser>,
+    scope: Option<&OAuthScope>,
+    enforcer: &RwLock<Enforcer>,
+) -> ApiResult<HttpResponse> {
+    let file_pointer = parse_file_path(path)
+        .ok_or_else(|| ApiError::invalid(format!("{} is not a valid Maven path", path)))?;
+    let location = file_pointer.prefix().trim_matches('/');
+
+    let mut members = Vec::new();
+    for member in group.members_at(location) {
+        let repo = match repos.find_by_location(member.repository()).await? {
+            Some(repo) => repo,
+            None => continue,
+        };
+        if repo.is_private() && !can_pull(enforcer, &repo, current_user, scope).await {
+            log::debug!(
+                "skipping member {} of group {}, pull not allowed",
+                member.repository(),
+                group.name()
+            );
+            continue;
+        }
+        if repo.location() == location {
+            members.push(repo);
+        } else if let Some(view) = repo.proxy_location(location) {
+            members.push(view);
+        }
+    }
+
+    let version = file_pointer.version();
+    let filename = file_pointer.filename();
+    if version.is_none() && metadata::is_metadata(filename) {
+        let mut merged = Vec::new();
+        for repo in &members {
+            if let Some(metadata) = repos.get_metadata(repo).await? {
+                merged.push(metadata);
+            }
+        }
+        let xml = Metadata::merge(merged)
+            .ok_or_else(|| Error::not_found("Maven file", filename))?
+            .to_xml();
+        return Ok(match checksum_target(filename) {
+            Some((_, algo)) => HttpResponse::Ok().content_type("text/plain").body(
+                Checksums::compute(&xml)
+                    .get(algo)
+                    .unwrap_or_default()
+                    .to_string(),
+            ),
+            None => HttpResponse::Ok().content_type("application/xml").body(xml),
+        });
+    }
+
+    for repo in &members {
+        match serve_file(repos, presigned, repo, version, filename).await {
+            Err(ApiError::NotFound(_)) => continue,
+            res => return res,
+        }
+    }
+    Err(Error::not_found("Maven file", filename).into())
+}
+
+async fn can_pull(
+    enforcer: &RwLock<Enforcer>,
+    repo: &Repo,
+    current_user: Option<&CurrentUser>,
+    scope: Option<&OAuthScope>,
+) -> bool {
+    let (current_user, scope) = match Option::zip(current_user, scope) {
+        Some(auth) => auth,
+        None => return false,
+    };
+    if Scope::from("maven:repos:pull").matches(scope).is_err() {
+        return false;
+    }
+    let enforcer = enforcer.read().await;
+    enforcer.check(current_user.id(), repo.id(), "pull").is_ok()
+}
+
+async fn serve_file(
+    repos: &RepoService,
+    presigned: &PresignedUrls,
+    repo: &Repo,
+    version: Option<&Version>,
+    filename: &str,
+) -> ApiResult<HttpResponse> {
+    if let Some((target, algo)) = checksum_target(filename) {
+        let target = repos.resolve_filename(repo, version, target);
+        let checksum = repos.get_checksum(repo, version, &target, algo).await?;
+        return Ok(HttpResponse::Ok().content_type("text/plain").body(checksum));
+    }
+
+    let filename = &repos.resolve_filename(repo, version, filename);
+
+    // metadata of proxy repositories may have to be revalidated first
+    let revalidate = repo.is_proxy() && metadata::is_metadata(filename);
+    if presigned.is_enabled()
+        && !revalidate
+        && repos.is_file_present(repo, version, filename).await?
+    {
+        if let Some(url) = presigned
+            .url(&repos.file_key(repo, version, filename))
+            .await
+        {
+            log::debug!("redirecting to pre-signed URL of {}", filename);
+            return Ok(HttpResponse::TemporaryRedirect()
+                .header(http::header::LOCATION, url)
+                .finish());
+        }
+    }
+
+    let file = repos.get_file(repo, version, filename).await?;
+    let content_type = if filename == metadata::METADATA_FILE {
+        "application/xml"
+    } else {
+        "application/octet-stream"
+    };
     Ok(HttpResponse::Ok()
-        .content_type("application/octet-stream")
+        .content_type(content_type)
         .streaming(file.into_byte_stream()))
 }
 
 #[put("/maven2/{tail:.*}")]
 pub async fn put(
     repos: Data<RepoService>,
+    groups: Data<GroupService>,
     Path(location): Path<String>,
     body: Bytes,
     current_user: Option<CurrentUser>,
@@ -68,6 +223,15 @@ pub async fn put(
     let file_pointer = parse_file_path(&location)
         .ok_or_else(|| ApiError::invalid(format!("{} is not a valid Maven path", location)))?;
 
+    if let Some((name, _)) = Group::split_path(&location) {
+        if groups.find_group(name).await?.is_some() {
+            return Err(ApiError::BadRequest(format!(
+                "could not store {}, {} is a repository group",
+                &location, name
+            )));
+        }
+    }
+
     let repo = repos.find_by_location(file_pointer.prefix()).await?;
     let repo = match repo {
         Some(repo) => repo,
@@ -86,9 +250,49 @@ pub async fn put(
         }
     }
 
+    if repo.is_proxy() {
+        return Err(ApiError::BadRequest(format!(
+            "could not store {}, {} is a proxy repository",
+            &location,
+            repo.location()
+        )));
+    }
+
     let filename = file_pointer.filename();
+    if metadata::is_metadata(filename) {
+        log::debug!(
+            "ignoring upload of {}, metadata is generated by the server",
+            &location
+        );
+        return Ok(HttpResponse::Accepted().finish());
+    }
+
+    // checksums are computed on upload, the uploaded ones are only verified
+    if let Some((target, algo)) = checksum_target(filename) {
+        let checksum = parse_checksum(&body)
+            .ok_or_else(|| ApiError::invalid(format!("{} is not a valid checksum", filename)))?;
+        let version = file_pointer.version();
+        let target = repos.resolve_filename(&repo, version, target);
+        let valid = repos
+            .verify_checksum(&repo, version, &target, algo, &checksum)
+            .await?;
+        if !valid {
+            log::warn!(
+                "rejecting {}, it does not match the stored content",
+                &location
+            );
+            return Err(ApiError::invalid(format!(
+                "checksum {} does not match the content of {}",
+                filename, target
+            )));
+        }
+        return Ok(HttpResponse::Accepted().finish());
+    }
+
     if let Some(version) = file_pointer.version() {
-        let file_exists = repos.is_file_present(&repo, version, filename).await?;
+        let file_exists = repos
+            .is_file_present(&repo, Some(version), filename)
+            .await?;
         if !version.is_snapshot() && file_exists {
             return Err(ApiError::new(
                 StatusCode::CONFLICT,
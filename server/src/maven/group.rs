@@ -0,0 +1,264 @@
+use std::sync::Arc;
+
+use actix_web::web::{Data, Json, Path, Query};
+use actix_web::{delete, get, post, put};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use enseada::couchdb::repository::{Entity, Repository};
+use enseada::guid::Guid;
+use enseada::pagination::Page;
+use maven::group::{Group, GroupService, Member};
+use maven::service::RepoService;
+use oauth::scope::Scope;
+use rbac::Enforcer;
+
+use crate::http::error::ApiError;
+use crate::http::extractor::scope::OAuthScope;
+use crate::http::extractor::user::CurrentUser;
+use crate::http::{ApiResult, PaginationQuery};
+
+#[derive(Debug, Serialize)]
+pub struct GroupResponse {
+    name: String,
+    members: Vec<MemberResponse>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MemberResponse {
+    repository: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prefix: Option<String>,
+}
+
+impl From<&Group> for GroupResponse {
+    fn from(group: &Group) -> Self {
+        Self {
+            name: group.name().to_string(),
+            members: group.members().iter().map(MemberResponse::from).collect(),
+        }
+    }
+}
+
+impl From<&Member> for MemberResponse {
+    fn from(member: &Member) -> Self {
+        Self {
+            repository: member.repository().to_string(),
+            prefix: member.prefix().map(str::to_string),
+        }
+    }
+}
+
+impl From<Group> for GroupResponse {
+    fn from(group: Group) -> Self {
+        Self::from(&group)
+    }
+}
+
+#[get("/api/maven/v1beta1/groups")]
+pub async fn list_groups(
+    service: Data<GroupService>,
+    enforcer: Data<Arc<RwLock<Enforcer>>>,
+    scope: OAuthScope,
+    current_user: CurrentUser,
+    list: Query<PaginationQuery>,
+) -> ApiResult<Json<Page<GroupResponse>>> {
+    Scope::from("maven:groups:read").matches(&scope)?;
+    let enforcer = enforcer.read().await;
+    enforcer.check(current_user.id(), &Guid::simple("maven_groups"), "read")?;
+    let limit = list.limit();
+    let offset = list.offset();
+
+    let page = service.list(limit, offset).await?.map(GroupResponse::from);
+    Ok(Json(page))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateGroupPayload {
+    name: String,
+    members: Vec<MemberPayload>,
+}
+
+/// A member is either the location of a repository, or a proxy repository
+/// along with the prefix of the locations it covers.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum MemberPayload {
+    Repository(String),
+    Covering { repository: String, prefix: String },
+}
+
+impl MemberPayload {
+    fn to_member(&self) -> Member {
+        match self {
+            MemberPayload::Repository(repository) => Member::new(repository),
+            MemberPayload::Covering { repository, prefix } => Member::covering(repository, prefix),
+        }
+    }
+}
+
+/// Groups are served at `/maven2/{name}`, so names are a single path segment
+/// that must not be the first segment of the location of any repository.
+async fn validate_name(repos: &RepoService, name: &str) -> ApiResult<()> {
+    let valid = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if name.is_empty() || !valid {
+        return Err(ApiError::BadRequest(format!(
+            "invalid group name '{}', only letters, digits, '-' and '_' are allowed",
+            name
+        )));
+    }
+
+    let shadowed = repos
+        .find_all(
+            1,
+            0,
+            serde_json::json!({
+                "decoded_location": { "$regex": format!("^{}/", name) },
+            }),
+        )
+        .await?;
+    if shadowed.count() > 0 {
+        return Err(ApiError::Conflict(format!(
+            "group '{}' would hide the repositories under /maven2/{}",
+            name, name
+        )));
+    }
+    Ok(())
+}
+
+/// Members must be existing repositories, listed once.
+/// Only proxy repositories can cover a prefix.
+async fn validate_members(
+    repos: &RepoService,
+    members: &[MemberPayload],
+) -> ApiResult<Vec<Member>> {
+    let mut validated: Vec<Member> = Vec::with_capacity(members.len());
+    for member in members {
+        let member = member.to_member();
+        let location = member.repository();
+        if validated.iter().any(|other| other.repository() == location) {
+            return Err(ApiError::BadRequest(format!(
+                "repository '{}' is listed more than once",
+                location
+            )));
+        }
+        let repo = repos.find_by_location(location).await?.ok_or_else(|| {
+            ApiError::BadRequest(format!("Maven repository '{}' not found", location))
+        })?;
+        if member.prefix().is_some() && !repo.is_proxy() {
+            return Err(ApiError::BadRequest(format!(
+                "repository '{}' is not a proxy, it can only serve its own location",
+                location
+            )));
+        }
+        validated.push(member);
+    }
+    Ok(validated)
+}
+
+#[post("/api/maven/v1beta1/groups")]
+pub async fn create_group(
+    service: Data<GroupService>,
+    repos: Data<RepoService>,
+    enforcer: Data<Arc<RwLock<Enforcer>>>,
+    scope: OAuthScope,
+    current_user: CurrentUser,
+    body: Json<CreateGroupPayload>,
+) -> ApiResult<Json<GroupResponse>> {
+    Scope::from("maven:groups:manage").matches(&scope)?;
+    let enforcer = enforcer.read().await;
+    enforcer.check(current_user.id(), &Guid::simple("maven_groups"), "create")?;
+
+    validate_name(&repos, &body.name).await?;
+    if service.find_group(&body.name).await?.is_some() {
+        return Err(ApiError::Conflict(format!(
+            "Maven group '{}' already exists",
+            &body.name
+        )));
+    }
+
+    let members = validate_members(&repos, &body.members).await?;
+    let group = service.save(Group::new(&body.name, members)).await?;
+
+    Ok(Json(GroupResponse::from(group)))
+}
+
+#[get("/api/maven/v1beta1/groups/{name}")]
+pub async fn get_group(
+    service: Data<GroupService>,
+    enforcer: Data<Arc<RwLock<Enforcer>>>,
+    scope: OAuthScope,
+    current_user: CurrentUser,
+    path: Path<String>,
+) -> ApiResult<Json<GroupResponse>> {
+    Scope::from("maven:groups:read").matches(&scope)?;
+    let enforcer = enforcer.read().await;
+    enforcer.check(current_user.id(), &Guid::simple("maven_groups"), "read")?;
+
+    let name = path.as_str();
+    let group = service
+        .find_group(name)
+        .await?
+        .ok_or_else(|| ApiError::not_found(&format!("Maven group '{}' not found", name)))?;
+
+    Ok(Json(GroupResponse::from(group)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateGroupPayload {
+    members: Option<Vec<MemberPayload>>,
+}
+
+#[put("/api/maven/v1beta1/groups/{name}")]
+pub async fn update_group(
+    service: Data<GroupService>,
+    repos: Data<RepoService>,
+    enforcer: Data<Arc<RwLock<Enforcer>>>,
+    scope: OAuthScope,
+    current_user: CurrentUser,
+    path: Path<String>,
+    body: Json<UpdateGroupPayload>,
+) -> ApiResult<Json<GroupResponse>> {
+    Scope::from("maven:groups:manage").matches(&scope)?;
+    let enforcer = enforcer.read().await;
+    enforcer.check(current_user.id(), &Guid::simple("maven_groups"), "update")?;
+
+    let name = path.as_str();
+    let mut group = service
+        .find_group(name)
+        .await?
+        .ok_or_else(|| ApiError::not_found(&format!("Maven group '{}' not found", name)))?;
+
+    log::debug!("updating Maven group {}", name);
+    if let Some(members) = &body.members {
+        let members = validate_members(&repos, members).await?;
+        group.set_members(members);
+    }
+
+    let group = service.save(group).await?;
+    Ok(Json(GroupResponse::from(group)))
+}
+
+#[delete("/api/maven/v1beta1/groups/{name}")]
+pub async fn delete_group(
+    service: Data<GroupService>,
+    enforcer: Data<Arc<RwLock<Enforcer>>>,
+    scope: OAuthScope,
+    current_user: CurrentUser,
+    path: Path<String>,
+) -> ApiResult<Json<GroupResponse>> {
+    Scope::from("maven:groups:manage").matches(&scope)?;
+    let enforcer = enforcer.read().await;
+    enforcer.check(current_user.id(), &Guid::simple("maven_groups"), "delete")?;
+
+    let name = path.as_str();
+    let group = service
+        .find_group(name)
+        .await?
+        .ok_or_else(|| ApiError::not_found(&format!("Maven group '{}' not found", name)))?;
+
+    service.delete(&group).await?;
+    Ok(Json(GroupResponse::from(group)))
+}
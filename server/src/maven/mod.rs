@@ -6,10 +6,23 @@ use serde::Deserialize;
 use couchdb::db::Database;
 use enseada::storage::Provider;
 use events::EventBus;
+use maven::group::GroupService;
+use maven::replication::ReplicationService;
 use maven::service::RepoService;
 
 mod api;
 mod files;
+mod group;
+mod replication;
+
+/// Subscribes the Maven event handlers that must run once per process,
+/// not once per worker.
+pub fn subscribe(db: Database, bus: Arc<RwLock<EventBus>>, store: Arc<Provider>) {
+    let replication = ReplicationService::new(db, store);
+    bus.write()
+        .expect("maven::subscribe EventBus unlock")
+        .subscribe_wrap(replication);
+}
 
 pub fn mount(
     db: Database,
@@ -17,15 +30,34 @@ pub fn mount(
     store: Arc<Provider>,
 ) -> Box<impl FnOnce(&mut ServiceConfig)> {
     Box::new(move |cfg: &mut ServiceConfig| {
+        let replication = ReplicationService::new(db.clone(), store.clone());
+        cfg.data(replication);
+
+        let groups = GroupService::new(db.clone());
+        cfg.data(groups);
+
         let repo = RepoService::new(db, bus, store);
         cfg.data(repo);
 
         cfg.service(api::list_repos);
         cfg.service(api::create_repo);
         cfg.service(api::get_repo);
+        cfg.service(api::update_repo);
         cfg.service(api::get_repo_files);
         cfg.service(api::delete_repo);
 
+        cfg.service(replication::list_rules);
+        cfg.service(replication::create_rule);
+        cfg.service(replication::get_rule);
+        cfg.service(replication::update_rule);
+        cfg.service(replication::delete_rule);
+
+        cfg.service(group::list_groups);
+        cfg.service(group::create_group);
+        cfg.service(group::get_group);
+        cfg.service(group::update_group);
+        cfg.service(group::delete_group);
+
         cfg.service(files::get);
         cfg.service(files::put);
     })
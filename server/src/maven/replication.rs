@@ -0,0 +1,215 @@
+use std::sync::Arc;
+
+use actix_web::web::{Data, Json, Path, Query};
+use actix_web::{delete, get, post, put};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use enseada::couchdb::repository::{Entity, Repository};
+use enseada::guid::Guid;
+use enseada::pagination::Page;
+use enseada::replication::ReplicationStatus;
+use maven::replication::{ReplicationRule, ReplicationService};
+use oauth::scope::Scope;
+use rbac::Enforcer;
+
+use crate::http::error::ApiError;
+use crate::http::extractor::scope::OAuthScope;
+use crate::http::extractor::user::CurrentUser;
+use crate::http::{validate_patterns, ApiResult, PaginationQuery, RemotePayload, RemoteResponse};
+
+#[derive(Debug, Serialize)]
+pub struct RuleResponse {
+    name: String,
+    repositories: Vec<String>,
+    target: RemoteResponse,
+    enabled: bool,
+    status: ReplicationStatus,
+}
+
+impl From<&ReplicationRule> for RuleResponse {
+    fn from(rule: &ReplicationRule) -> Self {
+        Self {
+            name: rule.name().to_string(),
+            repositories: rule.repositories().to_vec(),
+            target: RemoteResponse::from(rule.target()),
+            enabled: rule.is_enabled(),
+            status: rule.status().clone(),
+        }
+    }
+}
+
+impl From<ReplicationRule> for RuleResponse {
+    fn from(rule: ReplicationRule) -> Self {
+        Self::from(&rule)
+    }
+}
+
+#[get("/api/maven/v1beta1/replication/rules")]
+pub async fn list_rules(
+    service: Data<ReplicationService>,
+    enforcer: Data<Arc<RwLock<Enforcer>>>,
+    scope: OAuthScope,
+    current_user: CurrentUser,
+    list: Query<PaginationQuery>,
+) -> ApiResult<Json<Page<RuleResponse>>> {
+    Scope::from("maven:replication:read").matches(&scope)?;
+    let enforcer = enforcer.read().await;
+    enforcer.check(
+        current_user.id(),
+        &Guid::simple("maven_replication"),
+        "read",
+    )?;
+    let limit = list.limit();
+    let offset = list.offset();
+
+    let page = service.list(limit, offset).await?.map(RuleResponse::from);
+    Ok(Json(page))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateRulePayload {
+    name: String,
+    repositories: Vec<String>,
+    target: RemotePayload,
+    #[serde(default = "default_enabled")]
+    enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[post("/api/maven/v1beta1/replication/rules")]
+pub async fn create_rule(
+    service: Data<ReplicationService>,
+    enforcer: Data<Arc<RwLock<Enforcer>>>,
+    scope: OAuthScope,
+    current_user: CurrentUser,
+    body: Json<CreateRulePayload>,
+) -> ApiResult<Json<RuleResponse>> {
+    Scope::from("maven:replication:manage").matches(&scope)?;
+    let enforcer = enforcer.read().await;
+    enforcer.check(
+        current_user.id(),
+        &Guid::simple("maven_replication"),
+        "create",
+    )?;
+
+    if service.find_rule(&body.name).await?.is_some() {
+        return Err(ApiError::Conflict(format!(
+            "replication rule '{}' already exists",
+            &body.name
+        )));
+    }
+
+    validate_patterns(&body.repositories)?;
+    let mut rule = ReplicationRule::new(
+        &body.name,
+        body.repositories.clone(),
+        body.target.to_remote()?,
+    );
+    rule.set_enabled(body.enabled);
+    let rule = service.save(rule).await?;
+
+    Ok(Json(RuleResponse::from(rule)))
+}
+
+#[get("/api/maven/v1beta1/replication/rules/{name}")]
+pub async fn get_rule(
+    service: Data<ReplicationService>,
+    enforcer: Data<Arc<RwLock<Enforcer>>>,
+    scope: OAuthScope,
+    current_user: CurrentUser,
+    path: Path<String>,
+) -> ApiResult<Json<RuleResponse>> {
+    Scope::from("maven:replication:read").matches(&scope)?;
+    let enforcer = enforcer.read().await;
+    enforcer.check(
+        current_user.id(),
+        &Guid::simple("maven_replication"),
+        "read",
+    )?;
+
+    let name = path.as_str();
+    let rule = service
+        .find_rule(name)
+        .await?
+        .ok_or_else(|| ApiError::not_found(&format!("replication rule '{}' not found", name)))?;
+
+    Ok(Json(RuleResponse::from(rule)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateRulePayload {
+    repositories: Option<Vec<String>>,
+    target: Option<RemotePayload>,
+    enabled: Option<bool>,
+}
+
+#[put("/api/maven/v1beta1/replication/rules/{name}")]
+pub async fn update_rule(
+    service: Data<ReplicationService>,
+    enforcer: Data<Arc<RwLock<Enforcer>>>,
+    scope: OAuthScope,
+    current_user: CurrentUser,
+    path: Path<String>,
+    body: Json<UpdateRulePayload>,
+) -> ApiResult<Json<RuleResponse>> {
+    Scope::from("maven:replication:manage").matches(&scope)?;
+    let enforcer = enforcer.read().await;
+    enforcer.check(
+        current_user.id(),
+        &Guid::simple("maven_replication"),
+        "update",
+    )?;
+
+    let name = path.as_str();
+    let mut rule = service
+        .find_rule(name)
+        .await?
+        .ok_or_else(|| ApiError::not_found(&format!("replication rule '{}' not found", name)))?;
+
+    log::debug!("updating Maven replication rule {}", name);
+    if let Some(repositories) = &body.repositories {
+        validate_patterns(repositories)?;
+        rule.set_repositories(repositories.clone());
+    }
+
+    if let Some(target) = &body.target {
+        rule.set_target(target.to_remote()?);
+    }
+
+    if let Some(enabled) = body.enabled {
+        rule.set_enabled(enabled);
+    }
+
+    let rule = service.save(rule).await?;
+    Ok(Json(RuleResponse::from(rule)))
+}
+
+#[delete("/api/maven/v1beta1/replication/rules/{name}")]
+pub async fn delete_rule(
+    service: Data<ReplicationService>,
+    enforcer: Data<Arc<RwLock<Enforcer>>>,
+    scope: OAuthScope,
+    current_user: CurrentUser,
+    path: Path<String>,
+) -> ApiResult<Json<RuleResponse>> {
+    Scope::from("maven:replication:manage").matches(&scope)?;
+    let enforcer = enforcer.read().await;
+    enforcer.check(
+        current_user.id(),
+        &Guid::simple("maven_replication"),
+        "delete",
+    )?;
+
+    let name = path.as_str();
+    let rule = service
+        .find_rule(name)
+        .await?
+        .ok_or_else(|| ApiError::not_found(&format!("replication rule '{}' not found", name)))?;
+
+    service.delete(&rule).await?;
+    Ok(Json(RuleResponse::from(rule)))
+}
@@ -1,50 +1,85 @@
 use std::sync::Arc;
 
 use actix_web::web::{Data, Json, Path, Query};
-use actix_web::{delete, get, post};
+use actix_web::{delete, get, post, put};
+use futures::future;
+use glob::Pattern;
 use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
+use url::Url;
 
 use enseada::couchdb::repository::{Entity, Repository};
 use enseada::guid::Guid;
 use enseada::pagination::Page;
+use enseada::quota::Quota;
 use oauth::scope::Scope;
-use oci::entity::Repo;
-use oci::service::RepoService;
+use oci::entity::{ImageMetadata, Manifest, Repo, RetentionRule, TagPolicy, Upstream};
+use oci::image::ImageConfig;
+use oci::manifest::{Descriptor, ManifestBody};
+use oci::retention::{RetentionPlan, RetentionService};
+use oci::service::{BlobService, ImageService, ManifestService, RepoService};
 use rbac::Enforcer;
 
 use crate::http::error::ApiError;
 use crate::http::extractor::scope::OAuthScope;
 use crate::http::extractor::user::CurrentUser;
-use crate::http::{ApiResult, PaginationQuery};
-use crate::oci::RepoPath;
+use crate::http::{validate_quota, ApiResult, PaginationQuery};
+
+const DEFAULT_UPSTREAM_TTL: u64 = 3600;
 
 #[derive(Debug, Serialize)]
 pub struct RepoResponse {
     group: String,
     name: String,
     description: Option<String>,
+    public: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upstream: Option<UpstreamResponse>,
+    tag_policy: TagPolicy,
+    retention: Vec<RetentionRule>,
+    quota: Quota,
+    usage: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UpstreamResponse {
+    url: String,
+    name: Option<String>,
+    username: Option<String>,
+    ttl: u64,
 }
 
-impl From<&Repo> for RepoResponse {
-    fn from(repo: &Repo) -> Self {
+impl RepoResponse {
+    fn new(repo: &Repo, usage: u64) -> Self {
         Self {
             group: repo.group().to_string(),
             name: repo.name().to_string(),
             description: repo.description().map(str::to_string),
+            public: repo.is_public(),
+            upstream: repo.upstream().map(UpstreamResponse::from),
+            tag_policy: repo.tag_policy().clone(),
+            retention: repo.retention().to_vec(),
+            quota: repo.quota().clone(),
+            usage,
         }
     }
 }
 
-impl From<Repo> for RepoResponse {
-    fn from(repo: Repo) -> Self {
-        Self::from(&repo)
+impl From<&Upstream> for UpstreamResponse {
+    fn from(upstream: &Upstream) -> Self {
+        Self {
+            url: upstream.url().to_string(),
+            name: upstream.name().map(str::to_string),
+            username: upstream.username().map(str::to_string),
+            ttl: upstream.ttl(),
+        }
     }
 }
 
 #[get("/api/oci/v1beta1/repositories")]
 pub async fn list_repos(
     service: Data<RepoService>,
+    blobs: Data<BlobService>,
     enforcer: Data<Arc<RwLock<Enforcer>>>,
     scope: OAuthScope,
     current_user: CurrentUser,
@@ -56,20 +91,93 @@ pub async fn list_repos(
     let limit = list.limit();
     let offset = list.offset();
 
-    let page = service.list(limit, offset).await?.map(RepoResponse::from);
+    let page = service.list(limit, offset).await?;
+    let blobs = &blobs;
+    let usages = future::try_join_all(
+        page.iter()
+            .map(|repo| async move { blobs.usage(&repo.full_name()).await }),
+    )
+    .await?;
+    let mut usages = usages.into_iter();
+    let page = page.map(|repo| RepoResponse::new(&repo, usages.next().unwrap_or(0)));
     Ok(Json(page))
 }
 
 #[derive(Debug, Deserialize)]
 pub struct CreateRepoPayload {
+    #[serde(default)]
     group: String,
     name: String,
     description: Option<String>,
+    #[serde(default)]
+    public: bool,
+    upstream: Option<UpstreamPayload>,
+    #[serde(default)]
+    tag_policy: TagPolicy,
+    #[serde(default)]
+    retention: Vec<RetentionRule>,
+    #[serde(default)]
+    quota: Quota,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpstreamPayload {
+    url: String,
+    name: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    #[serde(default = "default_upstream_ttl")]
+    ttl: u64,
+}
+
+impl UpstreamPayload {
+    fn to_upstream(&self) -> ApiResult<Upstream> {
+        Url::parse(&self.url)
+            .map_err(|err| ApiError::BadRequest(format!("invalid upstream url: {}", err)))?;
+        if let Some(name) = &self.name {
+            oci::name::validate(name)?;
+        }
+
+        let upstream = Upstream::new(&self.url, self.name.clone(), self.ttl);
+        Ok(match (&self.username, &self.password) {
+            (Some(username), Some(password)) => {
+                upstream.with_credentials(username.clone(), password.clone())
+            }
+            _ => upstream,
+        })
+    }
+}
+
+fn default_upstream_ttl() -> u64 {
+    DEFAULT_UPSTREAM_TTL
+}
+
+fn validate_tag_policy(policy: &TagPolicy) -> ApiResult<()> {
+    if let TagPolicy::Protected { patterns } = policy {
+        for pattern in patterns {
+            Pattern::new(pattern).map_err(|err| {
+                ApiError::BadRequest(format!("invalid tag pattern '{}': {}", pattern, err))
+            })?;
+        }
+    }
+    Ok(())
+}
+
+fn validate_retention(rules: &[RetentionRule]) -> ApiResult<()> {
+    for rule in rules {
+        if let RetentionRule::KeepLastTags { pattern, .. } = rule {
+            Pattern::new(pattern).map_err(|err| {
+                ApiError::BadRequest(format!("invalid retention pattern '{}': {}", pattern, err))
+            })?;
+        }
+    }
+    Ok(())
 }
 
 #[post("/api/oci/v1beta1/repositories")]
 pub async fn create_repo(
     service: Data<RepoService>,
+    blobs: Data<BlobService>,
     enforcer: Data<Arc<RwLock<Enforcer>>>,
     scope: OAuthScope,
     current_user: CurrentUser,
@@ -79,54 +187,244 @@ pub async fn create_repo(
     let enforcer = enforcer.read().await;
     enforcer.check(current_user.id(), &Guid::simple("oci_repos"), "create")?;
 
-    let repo = Repo::new(&body.group, &body.name, body.description.clone());
+    oci::name::validate(&oci::name::join(&body.group, &body.name))?;
+    let upstream = body
+        .upstream
+        .as_ref()
+        .map(UpstreamPayload::to_upstream)
+        .transpose()?;
+    validate_tag_policy(&body.tag_policy)?;
+    let mut repo =
+        Repo::new(&body.group, &body.name, body.description.clone()).with_upstream(upstream);
+    repo.set_public(body.public)
+        .set_tag_policy(body.tag_policy.clone());
+    validate_retention(&body.retention)?;
+    repo.set_retention(body.retention.clone());
+    validate_quota(&body.quota)?;
+    repo.set_quota(body.quota.clone());
     let repo = service.save(repo).await?;
 
-    Ok(Json(RepoResponse::from(repo)))
+    let usage = blobs.usage(&repo.full_name()).await?;
+    Ok(Json(RepoResponse::new(&repo, usage)))
 }
 
-#[get("/api/oci/v1beta1/repositories/{group}/{name}")]
+/// Lists the tags and manifests that the retention rules of a repository would delete.
+/// Sub-resources of repositories follow a `-` segment, which no repository name
+/// can contain, so that they are never mistaken for a part of the name.
+#[get("/api/oci/v1beta1/repositories/{name:.+}/-/retention")]
+pub async fn preview_retention(
+    service: Data<RepoService>,
+    retention: Data<RetentionService>,
+    enforcer: Data<Arc<RwLock<Enforcer>>>,
+    scope: OAuthScope,
+    current_user: CurrentUser,
+    path: Path<String>,
+) -> ApiResult<Json<RetentionPlan>> {
+    Scope::from("oci:repos:read").matches(&scope)?;
+    let enforcer = enforcer.read().await;
+    let name = path.as_str();
+    let id = &Repo::build_id(name);
+    enforcer.check_nested(current_user.id(), &Repo::build_guid(id), "read")?;
+
+    let repo = service
+        .find(id)
+        .await?
+        .ok_or_else(|| ApiError::not_found(&format!("OCI repository '{}' not found", name)))?;
+
+    let plan = retention.plan(&repo).await?;
+    Ok(Json(plan))
+}
+
+#[derive(Debug, Serialize)]
+pub struct ManifestResponse {
+    name: String,
+    reference: String,
+    digest: String,
+    media_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<ImageResponse>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    manifests: Vec<Descriptor>,
+    tags: Page<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImageResponse {
+    config: Option<ImageConfig>,
+    layers: Vec<Descriptor>,
+    size: usize,
+}
+
+impl ManifestResponse {
+    fn new(manifest: &Manifest, metadata: Option<ImageMetadata>, tags: Page<String>) -> Self {
+        let manifests = match manifest.manifest() {
+            ManifestBody::Index(index) => index.manifests().to_vec(),
+            ManifestBody::Image(_) => Vec::new(),
+        };
+        Self {
+            name: manifest.image().to_string(),
+            reference: manifest.reference().to_string(),
+            digest: manifest.digest().to_string(),
+            media_type: manifest.media_type().to_string(),
+            image: metadata.map(|metadata| ImageResponse {
+                config: metadata.config().cloned(),
+                layers: metadata.layers().to_vec(),
+                size: metadata.size(),
+            }),
+            manifests,
+            tags,
+        }
+    }
+}
+
+/// Describes what an image contains, without pulling it: its configuration,
+/// its layers or, for image indexes, the platform manifests. The tags of the
+/// repository are listed as well, paginated.
+#[allow(clippy::too_many_arguments)]
+#[get("/api/oci/v1beta1/repositories/{name:.+}/-/manifests/{reference}")]
+pub async fn get_manifest(
+    service: Data<RepoService>,
+    manifests: Data<ManifestService>,
+    images: Data<ImageService>,
+    enforcer: Data<Arc<RwLock<Enforcer>>>,
+    scope: OAuthScope,
+    current_user: CurrentUser,
+    Path((name, reference)): Path<(String, String)>,
+    list: Query<PaginationQuery>,
+) -> ApiResult<Json<ManifestResponse>> {
+    Scope::from("oci:repos:read").matches(&scope)?;
+    let enforcer = enforcer.read().await;
+    let id = &Repo::build_id(&name);
+    enforcer.check_nested(current_user.id(), &Repo::build_guid(id), "read")?;
+
+    let repo = service
+        .find(id)
+        .await?
+        .ok_or_else(|| ApiError::not_found(&format!("OCI repository '{}' not found", name)))?;
+    let manifest = manifests
+        .find_by_ref(&repo.full_name(), &reference)
+        .await?
+        .ok_or_else(|| {
+            ApiError::not_found(&format!("manifest '{}' not found in '{}'", reference, name))
+        })?;
+
+    let metadata = images.metadata(&manifest).await?;
+    let tags = service
+        .list_repo_tags(&repo, list.limit(), list.offset())
+        .await?;
+    Ok(Json(ManifestResponse::new(&manifest, metadata, tags)))
+}
+
+#[get("/api/oci/v1beta1/repositories/{name:.+}")]
 pub async fn get_repo(
     service: Data<RepoService>,
+    blobs: Data<BlobService>,
     enforcer: Data<Arc<RwLock<Enforcer>>>,
     scope: OAuthScope,
     current_user: CurrentUser,
-    path: Path<RepoPath>,
+    path: Path<String>,
 ) -> ApiResult<Json<RepoResponse>> {
     Scope::from("oci:repos:read").matches(&scope)?;
     let enforcer = enforcer.read().await;
-    let group = &path.group;
-    let name = &path.name;
-    let id = &Repo::build_id(group, name);
-    enforcer.check(current_user.id(), &Repo::build_guid(id), "read")?;
+    let name = path.as_str();
+    let id = &Repo::build_id(name);
+    enforcer.check_nested(current_user.id(), &Repo::build_guid(id), "read")?;
+
+    let repo = service
+        .find(id)
+        .await?
+        .ok_or_else(|| ApiError::not_found(&format!("OCI repository '{}' not found", name)))?;
+
+    let usage = blobs.usage(&repo.full_name()).await?;
+    Ok(Json(RepoResponse::new(&repo, usage)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateRepoPayload {
+    description: Option<String>,
+    public: Option<bool>,
+    upstream: Option<UpstreamPayload>,
+    tag_policy: Option<TagPolicy>,
+    retention: Option<Vec<RetentionRule>>,
+    quota: Option<Quota>,
+}
+
+#[put("/api/oci/v1beta1/repositories/{name:.+}")]
+pub async fn update_repo(
+    service: Data<RepoService>,
+    blobs: Data<BlobService>,
+    enforcer: Data<Arc<RwLock<Enforcer>>>,
+    scope: OAuthScope,
+    current_user: CurrentUser,
+    path: Path<String>,
+    body: Json<UpdateRepoPayload>,
+) -> ApiResult<Json<RepoResponse>> {
+    Scope::from("oci:repos:manage").matches(&scope)?;
+    let enforcer = enforcer.read().await;
+    let name = path.as_str();
+    let id = &Repo::build_id(name);
+    enforcer.check_nested(current_user.id(), &Repo::build_guid(id), "update")?;
 
-    let repo = service.find(id).await?.ok_or_else(|| {
-        ApiError::not_found(&format!("OCI repository '{}/{}' not found", group, name))
-    })?;
+    let mut repo = service
+        .find(id)
+        .await?
+        .ok_or_else(|| ApiError::not_found(&format!("OCI repository '{}' not found", name)))?;
 
-    Ok(Json(RepoResponse::from(repo)))
+    log::debug!("updating OCI repository {}", name);
+    if let Some(description) = &body.description {
+        repo.set_description(Some(description.clone()));
+    }
+
+    if let Some(public) = body.public {
+        repo.set_public(public);
+    }
+
+    if let Some(upstream) = &body.upstream {
+        repo.set_upstream(Some(upstream.to_upstream()?));
+    }
+
+    if let Some(tag_policy) = &body.tag_policy {
+        validate_tag_policy(tag_policy)?;
+        repo.set_tag_policy(tag_policy.clone());
+    }
+
+    if let Some(retention) = &body.retention {
+        validate_retention(retention)?;
+        repo.set_retention(retention.clone());
+    }
+
+    if let Some(quota) = &body.quota {
+        validate_quota(quota)?;
+        repo.set_quota(quota.clone());
+    }
+
+    let repo = service.save(repo).await?;
+    let usage = blobs.usage(&repo.full_name()).await?;
+    Ok(Json(RepoResponse::new(&repo, usage)))
 }
 
-#[delete("/api/oci/v1beta1/repositories/{group}/{name}")]
+#[delete("/api/oci/v1beta1/repositories/{name:.+}")]
 pub async fn delete_repo(
     service: Data<RepoService>,
+    blobs: Data<BlobService>,
     enforcer: Data<Arc<RwLock<Enforcer>>>,
     scope: OAuthScope,
     current_user: CurrentUser,
-    path: Path<RepoPath>,
+    path: Path<String>,
 ) -> ApiResult<Json<RepoResponse>> {
     Scope::from("oci:repos:delete").matches(&scope)?;
     let enforcer = enforcer.read().await;
-    let group = &path.group;
-    let name = &path.name;
-    let id = &Repo::build_id(group, name);
-    enforcer.check(current_user.id(), &Repo::build_guid(id), "delete")?;
+    let name = path.as_str();
+    let id = &Repo::build_id(name);
+    enforcer.check_nested(current_user.id(), &Repo::build_guid(id), "delete")?;
 
-    let repo = service.find(id).await?.ok_or_else(|| {
-        ApiError::not_found(&format!("OCI repository '{}/{}' not found", group, name))
-    })?;
+    let repo = service
+        .find(id)
+        .await?
+        .ok_or_else(|| ApiError::not_found(&format!("OCI repository '{}' not found", name)))?;
 
+    let usage = blobs.usage(&repo.full_name()).await?;
     service.delete(&repo).await?;
 
-    Ok(Json(RepoResponse::from(repo)))
+    Ok(Json(RepoResponse::new(&repo, usage)))
 }
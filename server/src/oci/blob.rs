@@ -1,131 +1,202 @@
 use std::sync::Arc;
 
+use actix_web::dev::{Body, BodyEncoding, HttpResponseBuilder, SizedStream};
+use actix_web::http::ContentEncoding;
 use actix_web::web::{Data, Path};
-use actix_web::HttpResponse;
 use actix_web::{delete, get, head};
+use actix_web::{HttpRequest, HttpResponse};
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use futures::{stream, Stream, TryStreamExt};
 use tokio::sync::RwLock;
 
 use enseada::couchdb::repository::{Entity, Repository};
-use oauth::scope::Scope;
-use oci::entity::Repo;
+use enseada::storage::{ByteChunk, ByteRange};
+use oci::entity::{Blob, Repo};
 use oci::error::{Error, ErrorCode};
 use oci::header;
-use oci::service::{BlobService, RepoService};
+use oci::service::{BlobService, ProxiedBlob, ProxyService, RepoService};
 use rbac::Enforcer;
 
 use crate::http::extractor::scope::OAuthScope;
 use crate::http::extractor::user::CurrentUser;
+use crate::oci::token::image_scope;
 use crate::oci::upload::DigestParam;
-use crate::oci::{RepoPath, Result};
+use crate::oci::{authorize_pull, RepoPath, Result};
+use crate::storage::PresignedUrls;
 
-#[get("/{group}/{name}/blobs/{digest}")]
+#[allow(clippy::too_many_arguments)]
+#[get("/{name:.+}/blobs/{digest}")]
 pub async fn get(
+    req: HttpRequest,
     blobs: Data<BlobService>,
-    repos: Data<RepoService>,
-    repo: Path<RepoPath>,
+    proxies: Data<ProxyService>,
+    presigned: Data<Arc<PresignedUrls>>,
+    repo: RepoPath,
     digest: Path<DigestParam>,
     enforcer: Data<Arc<RwLock<Enforcer>>>,
-    scope: OAuthScope,
-    current_user: CurrentUser,
+    scope: Option<OAuthScope>,
+    current_user: Option<CurrentUser>,
 ) -> Result<HttpResponse> {
-    Scope::from("oci:image:pull").matches(&scope)?;
-    let group = &repo.group;
-    let name = &repo.name;
+    let name = repo.name();
     let digest = &digest.digest;
-    let repo_id = Repo::build_id(group, name);
-    let enforcer = enforcer.read().await;
-    enforcer.check(current_user.id(), &Repo::build_guid(&repo_id), "image:pull")?;
+    authorize_pull(
+        &proxies,
+        &enforcer,
+        name,
+        current_user.as_ref(),
+        scope.as_ref(),
+    )
+    .await?;
 
-    log::debug!("looking for repo {}/{}", group, name);
-    repos
-        .find(&Repo::build_id(group, name))
+    log::debug!("looking for repo {}", name);
+    proxies
+        .find_repo(name)
         .await?
         .ok_or_else(|| Error::from(ErrorCode::NameUnknown))?;
 
     log::debug!("looking for blob {}", digest);
     let digest_s = digest.to_string();
-    blobs
-        .find(&digest_s)
-        .await?
-        .ok_or_else(|| Error::from(ErrorCode::BlobUnknown))?;
+    let blob = match blobs.find_by_digest(name, digest).await? {
+        Some(blob) => blob,
+        None => match proxies
+            .fetch_blob(name, digest)
+            .await?
+            .ok_or_else(|| Error::from(ErrorCode::BlobUnknown))?
+        {
+            ProxiedBlob::Cached(blob) => blob,
+            ProxiedBlob::Streamed {
+                size,
+                content,
+                cache,
+            } => {
+                blobs.mark_streamed(name, digest, size);
+                return Ok(streamed_response(&digest_s, size, content, cache));
+            }
+        },
+    };
+    blobs.mark_pulled(&blob);
+    if let Some(url) = presigned.url(&blobs.content_key(blob.digest())).await {
+        log::debug!("redirecting to pre-signed URL of blob {}", digest);
+        return Ok(blob_response(HttpResponse::TemporaryRedirect(), &digest_s)
+            .header(http::header::LOCATION, url)
+            .finish());
+    }
+    let size = blobs.content_size(&blob).await?;
 
-    let content = blobs.fetch_content(&digest).await?;
+    let range = match requested_range(&req, &digest_s, size) {
+        Some(Ok(range)) => range,
+        Some(Err(())) => {
+            log::debug!(
+                "range not satisfiable for blob {} of {} bytes",
+                digest,
+                size
+            );
+            return Ok(HttpResponse::RangeNotSatisfiable()
+                .header(http::header::CONTENT_RANGE, format!("bytes */{}", size))
+                .finish());
+        }
+        None => {
+            let content = blobs.fetch_content(&digest).await?;
+            return Ok(blob_response(HttpResponse::Ok(), &digest_s).body(sized_body(size, content)));
+        }
+    };
 
-    Ok(HttpResponse::Ok()
-        .header(http::header::CONTENT_TYPE, "application/octet-stream")
-        .header(header::CONTENT_DIGEST, digest_s)
-        .streaming(content))
+    log::debug!(
+        "serving bytes {}-{} of blob {}",
+        range.start(),
+        range.end(),
+        digest
+    );
+    let content = blobs.fetch_content_range(&digest, range).await?;
+    Ok(blob_response(HttpResponse::PartialContent(), &digest_s)
+        .header(
+            http::header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", range.start(), range.end(), size),
+        )
+        .body(sized_body(range.len(), content)))
 }
 
-#[head("/{group}/{name}/blobs/{digest}")]
+#[head("/{name:.+}/blobs/{digest}")]
 pub async fn head(
     blobs: Data<BlobService>,
-    repos: Data<RepoService>,
-    repo: Path<RepoPath>,
+    proxies: Data<ProxyService>,
+    repo: RepoPath,
     digest: Path<DigestParam>,
     enforcer: Data<Arc<RwLock<Enforcer>>>,
-    scope: OAuthScope,
-    current_user: CurrentUser,
+    scope: Option<OAuthScope>,
+    current_user: Option<CurrentUser>,
 ) -> Result<HttpResponse> {
-    Scope::from("oci:image:pull").matches(&scope)?;
-    let group = &repo.group;
-    let name = &repo.name;
+    let name = repo.name();
     let digest = &digest.digest;
-    let repo_id = Repo::build_id(group, name);
-    let enforcer = enforcer.read().await;
-    enforcer.check(current_user.id(), &Repo::build_guid(&repo_id), "image:pull")?;
+    authorize_pull(
+        &proxies,
+        &enforcer,
+        name,
+        current_user.as_ref(),
+        scope.as_ref(),
+    )
+    .await?;
 
-    log::debug!("looking for repo {}/{}", group, name);
-    repos
-        .find(&Repo::build_id(group, name))
+    log::debug!("looking for repo {}", name);
+    proxies
+        .find_repo(name)
         .await?
         .ok_or_else(|| Error::from(ErrorCode::NameUnknown))?;
 
     log::debug!("looking for blob {}", digest);
     let digest_s = digest.to_string();
-    blobs
-        .find(&digest_s)
-        .await?
-        .ok_or_else(|| Error::from(ErrorCode::BlobUnknown))?;
+    let blob = match blobs.find_by_digest(name, digest).await? {
+        Some(blob) => blob,
+        None => match proxies
+            .head_blob(name, digest)
+            .await?
+            .ok_or_else(|| Error::from(ErrorCode::BlobUnknown))?
+        {
+            ProxiedBlob::Cached(blob) => blob,
+            ProxiedBlob::Streamed {
+                size,
+                content,
+                cache,
+            } => return Ok(streamed_response(&digest_s, size, content, cache)),
+        },
+    };
+    let size = blobs.content_size(&blob).await?;
 
-    Ok(HttpResponse::Ok()
-        .header(http::header::CONTENT_TYPE, "application/octet-stream")
-        .header(header::CONTENT_DIGEST, digest_s)
-        .finish())
+    Ok(blob_response(HttpResponse::Ok(), &digest_s).body(sized_body(size, stream::empty())))
 }
 
-#[delete("/{group}/{name}/blobs/{digest}")]
+#[delete("/{name:.+}/blobs/{digest}")]
 pub async fn delete(
     blobs: Data<BlobService>,
     repos: Data<RepoService>,
-    repo: Path<RepoPath>,
+    repo: RepoPath,
     digest: Path<DigestParam>,
     enforcer: Data<Arc<RwLock<Enforcer>>>,
     scope: OAuthScope,
     current_user: CurrentUser,
 ) -> Result<HttpResponse> {
-    Scope::from("oci:image:delete").matches(&scope)?;
-    let group = &repo.group;
-    let name = &repo.name;
+    let name = repo.name();
+    image_scope(name, "delete").matches(&scope)?;
     let digest = &digest.digest;
-    let repo_id = Repo::build_id(group, name);
+    let repo_id = Repo::build_id(name);
     let enforcer = enforcer.read().await;
-    enforcer.check(
+    enforcer.check_nested(
         current_user.id(),
         &Repo::build_guid(&repo_id),
         "image:delete",
     )?;
 
-    log::debug!("looking for repo {}/{}", group, name);
+    log::debug!("looking for repo {}", name);
     repos
-        .find(&Repo::build_id(group, name))
+        .find(&repo_id)
         .await?
         .ok_or_else(|| Error::from(ErrorCode::NameUnknown))?;
 
     log::debug!("looking for blob {}", digest);
     let digest_s = digest.to_string();
     let blob = blobs
-        .find(&digest_s)
+        .find_by_digest(name, digest)
         .await?
         .ok_or_else(|| Error::from(ErrorCode::BlobUnknown))?;
 
@@ -134,3 +205,60 @@ pub async fn delete(
         .header(header::CONTENT_DIGEST, digest_s)
         .finish())
 }
+
+/// Reads the byte range requested by the client, if any.
+/// The range is ignored when an `If-Range` validator does not match the blob,
+/// so that the whole content is served instead.
+fn requested_range(
+    req: &HttpRequest,
+    digest: &str,
+    size: usize,
+) -> Option<std::result::Result<ByteRange, ()>> {
+    let range = req.headers().get(http::header::RANGE)?.to_str().ok()?;
+    if let Some(validator) = req.headers().get(http::header::IF_RANGE) {
+        let validator = validator.to_str().ok()?.trim_matches('"');
+        if validator != digest {
+            log::debug!("If-Range {} does not match blob {}", validator, digest);
+            return None;
+        }
+    }
+    ByteRange::from_header(range, size)
+}
+
+/// Streams a blob from an upstream registry, caching it in the background.
+/// Byte ranges are not supported until the blob is cached, so the whole content is served.
+fn streamed_response(
+    digest: &str,
+    size: Option<usize>,
+    content: BoxStream<'static, ByteChunk>,
+    cache: Option<BoxFuture<'static, oci::Result<Blob>>>,
+) -> HttpResponse {
+    if let Some(cache) = cache {
+        actix_rt::spawn(async move {
+            if let Err(err) = cache.await {
+                log::error!("failed to cache upstream blob: {}", err);
+            }
+        });
+    }
+
+    let mut res = blob_response(HttpResponse::Ok(), digest);
+    match size {
+        Some(size) => res.body(sized_body(size, content)),
+        None => res.streaming(content),
+    }
+}
+
+fn blob_response(mut res: HttpResponseBuilder, digest: &str) -> HttpResponseBuilder {
+    // compression would drop the content length and break byte ranges
+    res.encoding(ContentEncoding::Identity)
+        .header(http::header::CONTENT_TYPE, "application/octet-stream")
+        .header(http::header::ACCEPT_RANGES, "bytes")
+        .header(http::header::ETAG, format!("\"{}\"", digest))
+        .header(header::CONTENT_DIGEST, digest);
+    res
+}
+
+fn sized_body<S: Stream<Item = ByteChunk> + 'static>(size: usize, content: S) -> Body {
+    let content = Box::pin(content.map_err(actix_web::Error::from));
+    Body::from_message(SizedStream::new(size as u64, content))
+}
@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+use actix_web::web::{Data, Query};
+use actix_web::{get, HttpResponse};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use enseada::couchdb::repository::Entity;
+use oauth::scope::Scope;
+use oci::service::RepoService;
+use rbac::Enforcer;
+
+use crate::http::extractor::scope::OAuthScope;
+use crate::http::extractor::user::CurrentUser;
+use crate::oci::token::CATALOG_SCOPE;
+use crate::oci::Result;
+
+#[derive(Debug, Serialize)]
+pub struct Catalog {
+    repositories: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CatalogPagination {
+    n: Option<usize>,
+    last: Option<String>,
+}
+
+/// Lists the names of the repositories the current user can pull from.
+/// Anonymous requests, or those without the pull or catalog scope, only list public repositories.
+/// Results are paginated only when `n` is provided.
+#[get("/_catalog")]
+pub async fn list(
+    repos: Data<RepoService>,
+    page: Query<CatalogPagination>,
+    enforcer: Data<Arc<RwLock<Enforcer>>>,
+    scope: Option<OAuthScope>,
+    current_user: Option<CurrentUser>,
+) -> Result<HttpResponse> {
+    let current_user = current_user.filter(|_| {
+        scope.map_or(false, |scope| {
+            Scope::from(vec!["oci:image:pull", CATALOG_SCOPE])
+                .matches(&scope)
+                .is_ok()
+        })
+    });
+    let enforcer = enforcer.read().await;
+
+    let mut repositories = Vec::new();
+    let mut has_next = false;
+    let mut stream = repos.list_after(page.last.as_deref());
+    while let Some(repo) = stream.next().await {
+        let repo = repo?;
+        let can_pull = repo.is_public()
+            || current_user.as_ref().map_or(false, |current_user| {
+                enforcer
+                    .check_nested(current_user.id(), repo.id(), "image:pull")
+                    .is_ok()
+            });
+        if !can_pull {
+            continue;
+        }
+
+        if page.n == Some(repositories.len()) {
+            has_next = true;
+            break;
+        }
+        repositories.push(repo.full_name());
+    }
+
+    let mut res = HttpResponse::Ok();
+    if let (true, Some(n), Some(last)) = (has_next, page.n, repositories.last()) {
+        let last: String = url::form_urlencoded::byte_serialize(last.as_bytes()).collect();
+        res.header(
+            http::header::LINK,
+            format!("</v2/_catalog?n={}&last={}>; rel=\"next\"", n, last),
+        );
+    }
+    Ok(res.json(Catalog { repositories }))
+}
@@ -4,10 +4,9 @@ use actix_web::body::{Body, MessageBody};
 use actix_web::dev::ServiceResponse;
 use actix_web::middleware::errhandlers::ErrorHandlerResponse;
 use actix_web::{HttpResponse, ResponseError};
-use http::{header, HeaderValue, StatusCode};
+use http::{header, Method, StatusCode};
 use serde::Serialize;
 
-use oauth::scope::Scope;
 use oci::error::{Error, ErrorCode};
 use oci::mime::MediaType;
 use rbac::EvaluationError;
@@ -80,19 +79,27 @@ impl From<EvaluationError> for ErrorResponse {
     }
 }
 
+/// A 401 response, challenging the client to authenticate.
 #[derive(Debug)]
-struct UnauthorizedError {
-    realm: String,
-    service: String,
-    scope: Scope,
+pub struct UnauthorizedError {
+    challenge: String,
 }
 
-impl Default for UnauthorizedError {
-    fn default() -> Self {
+impl UnauthorizedError {
+    /// Challenges the client to request a token from the token endpoint (`realm`),
+    /// for the given `scope`, as described by the Docker token authentication spec.
+    pub fn bearer(realm: &str, service: &str, scope: Option<&str>) -> Self {
+        let mut challenge = format!("Bearer realm=\"{}\",service=\"{}\"", realm, service);
+        if let Some(scope) = scope {
+            challenge.push_str(&format!(",scope=\"{}\"", scope));
+        }
+        UnauthorizedError { challenge }
+    }
+
+    /// Challenges the client to send its credentials, as required by the token endpoint.
+    pub fn basic() -> Self {
         UnauthorizedError {
-            realm: "Enseada OAuth Server".to_string(),
-            service: "Enseada OCI Registry".to_string(),
-            scope: Scope::from(vec!["oci:image:push:", "oci:image:pull"]),
+            challenge: "Basic realm=\"Enseada OCI Registry\"".to_string(),
         }
     }
 }
@@ -109,12 +116,8 @@ impl ResponseError for UnauthorizedError {
     }
 
     fn error_response(&self) -> HttpResponse {
-        let challenge = format!(
-            "Basic realm=\"{}\",service=\"{}\",scope=\"{}\"",
-            &self.realm, &self.service, &self.scope,
-        );
         HttpResponse::Unauthorized()
-            .header(http::header::WWW_AUTHENTICATE, challenge)
+            .header(http::header::WWW_AUTHENTICATE, self.challenge.as_str())
             .json(ErrorBody {
                 errors: vec![Error::new(
                     ErrorCode::Unauthorized,
@@ -127,6 +130,17 @@ impl ResponseError for UnauthorizedError {
 pub fn handle_unauthorized_request<B: MessageBody>(
     res: ServiceResponse<B>,
 ) -> actix_web::error::Result<ErrorHandlerResponse<B>> {
-    let err = UnauthorizedError::default();
+    let req = res.request();
+    let info = req.connection_info().clone();
+    let realm = format!("{}://{}/v2/token", info.scheme(), info.host());
+    let scope = req.match_info().get("name").map(|name| {
+        let actions = match *req.method() {
+            Method::GET | Method::HEAD => "pull",
+            Method::DELETE => "delete",
+            _ => "pull,push",
+        };
+        format!("repository:{}:{}", name, actions)
+    });
+    let err = UnauthorizedError::bearer(&realm, info.host(), scope.as_deref());
     Ok(ErrorHandlerResponse::Response(res.error_response(err)))
 }
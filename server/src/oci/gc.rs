@@ -0,0 +1,82 @@
+use std::io;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use actix_rt::time::{interval_at, Instant};
+use actix_rt::Arbiter;
+
+use enseada::couchdb::db::Database;
+use enseada::storage::Provider;
+use events::EventBus;
+use oci::gc::GarbageCollector;
+
+use crate::config::Configuration;
+use crate::couchdb::{self, name as dbname};
+use crate::storage;
+
+/// Runs the OCI garbage collector periodically in its own arbiter.
+pub struct GcJob {
+    arbiter: Arbiter,
+    collector: Arc<GarbageCollector>,
+    interval: Duration,
+}
+
+impl GcJob {
+    pub fn new(
+        cfg: &Configuration,
+        db: Database,
+        bus: Arc<RwLock<EventBus>>,
+        store: Arc<Provider>,
+    ) -> Self {
+        GcJob {
+            arbiter: Arbiter::new(),
+            collector: Arc::new(new_collector(cfg, db, bus, store)),
+            interval: Duration::from_secs(cfg.oci().gc().interval()),
+        }
+    }
+
+    pub fn start(&self) {
+        let collector = self.collector.clone();
+        let period = self.interval;
+        let fut = Box::pin(async move {
+            let mut interval = interval_at(Instant::now() + period, period);
+            loop {
+                interval.tick().await;
+                if let Err(err) = collector.run(false).await {
+                    log::error!("OCI garbage collection failed: {}", err);
+                }
+            }
+        });
+        self.arbiter.send(fut);
+    }
+
+    pub fn stop(&self) {
+        self.arbiter.stop();
+    }
+}
+
+/// Runs the OCI garbage collector once and prints its report.
+pub async fn run(cfg: &Configuration, dry_run: bool) -> io::Result<()> {
+    let db = couchdb::from_config(cfg).database(dbname::OCI, true);
+    let store = Arc::new(
+        storage::new_provider(cfg).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?,
+    );
+    let bus = Arc::new(RwLock::new(EventBus::new()));
+    let report = new_collector(cfg, db, bus, store)
+        .run(dry_run)
+        .await
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+    let report = serde_json::to_string_pretty(&report)?;
+    println!("{}", report);
+    Ok(())
+}
+
+fn new_collector(
+    cfg: &Configuration,
+    db: Database,
+    bus: Arc<RwLock<EventBus>>,
+    store: Arc<Provider>,
+) -> GarbageCollector {
+    let grace_period = chrono::Duration::seconds(cfg.oci().gc().grace_period() as i64);
+    GarbageCollector::new(Arc::new(db), bus, store, grace_period)
+}
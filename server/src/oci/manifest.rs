@@ -1,139 +1,253 @@
+use std::convert::TryFrom;
 use std::sync::Arc;
 
-use actix_web::web::{Data, Json, Path};
-use actix_web::HttpResponse;
+use actix_web::web::{Bytes, Data, Path};
+use actix_web::{HttpRequest, HttpResponse};
 use serde::Deserialize;
 use tokio::sync::RwLock;
 
 use enseada::couchdb::repository::{Entity, Repository};
-use oauth::scope::Scope;
-use oci::entity::{Manifest, Repo};
+use oci::entity::Repo;
 use oci::error::{Error, ErrorCode};
 use oci::header;
-use oci::manifest::ImageManifest;
 use oci::mime::MediaType;
-use oci::service::{ManifestService, RepoService};
+use oci::service::{ManifestService, ProxyService, RepoService};
 use rbac::Enforcer;
 
 use crate::http::extractor::scope::OAuthScope;
 use crate::http::extractor::user::CurrentUser;
-use crate::oci::{RepoPath, Result};
+use crate::oci::token::image_scope;
+use crate::oci::{authorize_pull, RepoPath, Result};
+
+const DEFAULT_OS: &str = "linux";
+const DEFAULT_ARCH: &str = "amd64";
 
 #[derive(Debug, Deserialize)]
 pub struct ManifestRefParam {
     reference: String,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn get(
     manifests: Data<ManifestService>,
-    repos: Data<RepoService>,
-    repo: Path<RepoPath>,
+    proxies: Data<ProxyService>,
+    repo: RepoPath,
     reference: Path<ManifestRefParam>,
+    req: HttpRequest,
     enforcer: Data<Arc<RwLock<Enforcer>>>,
-    scope: OAuthScope,
-    current_user: CurrentUser,
+    scope: Option<OAuthScope>,
+    current_user: Option<CurrentUser>,
 ) -> Result<HttpResponse> {
-    Scope::from("oci:image:pull").matches(&scope)?;
-    let group = &repo.group;
-    let name = &repo.name;
+    let name = repo.name();
     let reference = &reference.reference;
-    let repo_id = Repo::build_id(group, name);
-    let enforcer = enforcer.read().await;
-    enforcer.check(current_user.id(), &Repo::build_guid(&repo_id), "image:pull")?;
-
-    log::debug!("looking for repo {}/{}", group, name);
-    repos
-        .find(&repo_id)
+    authorize_pull(
+        &proxies,
+        &enforcer,
+        name,
+        current_user.as_ref(),
+        scope.as_ref(),
+    )
+    .await?;
+
+    log::debug!("looking for repo {}", name);
+    proxies
+        .find_repo(name)
         .await?
         .ok_or_else(|| Error::from(ErrorCode::NameUnknown))?;
 
-    let manifest = manifests
-        .find_by_ref(group, name, reference)
-        .await?
-        .ok_or_else(|| Error::from(ErrorCode::ManifestUnknown))?;
+    let manifest = match proxies.fetch_manifest(name, reference).await? {
+        Some(manifest) => manifest,
+        None => manifests
+            .find_by_ref(name, reference)
+            .await?
+            .ok_or_else(|| Error::from(ErrorCode::ManifestUnknown))?,
+    };
+    if req.method() == http::Method::GET {
+        manifests.mark_pulled(&manifest).await;
+    }
+
+    // Clients that don't understand image indexes get the default platform manifest,
+    // and any manifest of a type the client doesn't accept is unknown to it
+    let accepted = accepted_media_types(&req);
+    let manifest =
+        if manifest.manifest().is_index() && !is_accepted(&accepted, manifest.media_type()) {
+            log::debug!("client does not accept image indexes, resolving default platform");
+            manifests
+                .resolve_platform(name, manifest, DEFAULT_OS, DEFAULT_ARCH)
+                .await?
+        } else {
+            manifest
+        };
+    if !is_accepted(&accepted, manifest.media_type()) {
+        return Err(Error::new(
+            ErrorCode::ManifestUnknown,
+            format!(
+                "manifest of type {} is not accepted by the client",
+                manifest.media_type()
+            ),
+        )
+        .into());
+    }
 
-    let manifest = manifest.into_inner();
     Ok(HttpResponse::Ok()
-        .header(
-            http::header::CONTENT_TYPE,
-            MediaType::ImageManifest.to_string(),
-        )
+        .header(http::header::CONTENT_TYPE, manifest.media_type())
         .header(header::CONTENT_DIGEST, manifest.digest().to_string())
-        .json(manifest))
+        .body(manifest.content().to_string()))
 }
 
 #[allow(clippy::too_many_arguments)]
 pub async fn put(
     manifests: Data<ManifestService>,
     repos: Data<RepoService>,
-    repo: Path<RepoPath>,
+    repo: RepoPath,
     reference: Path<ManifestRefParam>,
-    body: Json<ImageManifest>,
+    body: Bytes,
+    req: HttpRequest,
     enforcer: Data<Arc<RwLock<Enforcer>>>,
     scope: OAuthScope,
     current_user: CurrentUser,
 ) -> Result<HttpResponse> {
-    Scope::from("oci:image:push").matches(&scope)?;
-    let group = &repo.group;
-    let name = &repo.name;
+    let name = repo.name();
+    image_scope(name, "push").matches(&scope)?;
     let reference = &reference.reference;
-    let repo_id = Repo::build_id(group, name);
+    let repo_id = Repo::build_id(name);
     let enforcer = enforcer.read().await;
-    enforcer.check(current_user.id(), &Repo::build_guid(&repo_id), "image:push")?;
+    enforcer.check_nested(current_user.id(), &Repo::build_guid(&repo_id), "image:push")?;
 
-    log::debug!("looking for repo {}/{}", group, name);
-    repos
-        .find(&Repo::build_id(group, name))
+    log::debug!("looking for repo {}", name);
+    let repo = repos
+        .find(&repo_id)
         .await?
         .ok_or_else(|| Error::from(ErrorCode::NameUnknown))?;
 
-    let manifest = Manifest::new(group, name, reference, body.into_inner());
-    let manifest = manifests.save(manifest).await?;
-
-    let manifest = manifest.into_inner();
-
-    Ok(HttpResponse::Created()
-        .header(
-            http::header::LOCATION,
-            format!("/{}/{}/manifests/{}", group, name, reference),
-        )
-        .header(header::CONTENT_DIGEST, manifest.digest().to_string())
-        .finish())
+    let media_type = req
+        .headers()
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok());
+    let manifest = manifests
+        .put_manifest(&repo, reference, media_type, body)
+        .await?;
+
+    let mut res = HttpResponse::Created();
+    res.header(
+        http::header::LOCATION,
+        format!("/v2/{}/manifests/{}", name, manifest.digest()),
+    )
+    .header(header::CONTENT_DIGEST, manifest.digest().to_string());
+    if let Some(subject) = manifest.manifest().subject() {
+        res.header(header::SUBJECT, subject.digest().to_string());
+    }
+    Ok(res.finish())
 }
 
 pub async fn delete(
     manifests: Data<ManifestService>,
     repos: Data<RepoService>,
-    repo: Path<RepoPath>,
+    repo: RepoPath,
     reference: Path<ManifestRefParam>,
     enforcer: Data<Arc<RwLock<Enforcer>>>,
     scope: OAuthScope,
     current_user: CurrentUser,
 ) -> Result<HttpResponse> {
-    Scope::from("oci:image:delete").matches(&scope)?;
-    let group = &repo.group;
-    let name = &repo.name;
+    let name = repo.name();
+    image_scope(name, "delete").matches(&scope)?;
     let reference = &reference.reference;
-    let repo_id = Repo::build_id(group, name);
+    let repo_id = Repo::build_id(name);
     let enforcer = enforcer.read().await;
-    enforcer.check(
+    enforcer.check_nested(
         current_user.id(),
         &Repo::build_guid(&repo_id),
         "image:delete",
     )?;
 
-    log::debug!("looking for repo {}/{}", group, name);
-    repos
-        .find(&Repo::build_id(group, name))
+    log::debug!("looking for repo {}", name);
+    let repo = repos
+        .find(&repo_id)
         .await?
         .ok_or_else(|| Error::from(ErrorCode::NameUnknown))?;
 
     let manifest = manifests
-        .find_by_ref(group, name, reference)
+        .find_by_ref(name, reference)
         .await?
         .ok_or_else(|| Error::from(ErrorCode::ManifestUnknown))?;
 
-    manifests.delete(&manifest).await?;
+    manifests.delete_manifest(&repo, &manifest).await?;
 
     Ok(HttpResponse::Accepted().finish())
 }
+
+fn accepted_media_types(req: &HttpRequest) -> Vec<String> {
+    req.headers()
+        .get_all(http::header::ACCEPT)
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .map(|mime| mime.split(';').next().unwrap_or("").trim().to_string())
+        .filter(|mime| !mime.is_empty())
+        .collect()
+}
+
+/// A manifest is accepted if the client sends no `Accept` header, accepts
+/// any type, or accepts its media type or an equivalent Docker or OCI one.
+fn is_accepted(accepted: &[String], media_type: &str) -> bool {
+    if accepted.is_empty() {
+        return true;
+    }
+
+    let compatible_types: Vec<String> = MediaType::try_from(media_type.to_string())
+        .map(|mime| {
+            mime.compatible_types()
+                .into_iter()
+                .map(|mime| format!("application/{}", mime))
+                .collect()
+        })
+        .unwrap_or_default();
+    accepted.iter().any(|mime| {
+        mime == "*/*"
+            || mime == "application/*"
+            || mime == media_type
+            || compatible_types.contains(mime)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const OCI_INDEX: &str = "application/vnd.oci.image.index.v1+json";
+    const OCI_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
+    const DOCKER_MANIFEST_LIST: &str = "application/vnd.docker.distribution.manifest.list.v2+json";
+    const DOCKER_MANIFEST: &str = "application/vnd.docker.distribution.manifest.v2+json";
+
+    fn accept(types: &[&str]) -> Vec<String> {
+        types.iter().map(|mime| mime.to_string()).collect()
+    }
+
+    #[test]
+    fn it_accepts_any_manifest_without_accept_header() {
+        assert!(is_accepted(&[], OCI_INDEX));
+        assert!(is_accepted(&[], OCI_MANIFEST));
+        assert!(is_accepted(&accept(&["*/*"]), OCI_INDEX));
+    }
+
+    #[test]
+    fn it_does_not_accept_an_index_with_an_image_manifest_only_accept() {
+        let accepted = accept(&[DOCKER_MANIFEST, OCI_MANIFEST]);
+        assert!(!is_accepted(&accepted, OCI_INDEX));
+        assert!(!is_accepted(&accepted, DOCKER_MANIFEST_LIST));
+        assert!(is_accepted(&accepted, OCI_MANIFEST));
+    }
+
+    #[test]
+    fn it_does_not_accept_an_image_manifest_with_an_index_only_accept() {
+        let accepted = accept(&[OCI_INDEX]);
+        assert!(is_accepted(&accepted, DOCKER_MANIFEST_LIST));
+        assert!(!is_accepted(&accepted, OCI_MANIFEST));
+        assert!(!is_accepted(&accepted, DOCKER_MANIFEST));
+    }
+
+    #[test]
+    fn it_accepts_equivalent_docker_and_oci_types() {
+        assert!(is_accepted(&accept(&[DOCKER_MANIFEST]), OCI_MANIFEST));
+        assert!(is_accepted(&accept(&[OCI_MANIFEST]), DOCKER_MANIFEST));
+    }
+}
@@ -1,37 +1,85 @@
 use std::sync::{Arc, RwLock};
 
+use actix_web::dev::Payload;
 use actix_web::middleware::errhandlers::ErrorHandlers;
 use actix_web::middleware::DefaultHeaders;
 use actix_web::web::{self, ServiceConfig};
 use actix_web::{get, guard, FromRequest, ResponseError};
 use actix_web::{HttpRequest, HttpResponse};
+use futures::future::{ready, Ready};
 use http::StatusCode;
-use serde::Deserialize;
 
 use enseada::couchdb::db::Database;
+use enseada::couchdb::repository::Entity;
 use enseada::storage::Provider;
 use events::EventBus;
+use oci::entity::Repo;
+use oci::error::{Error, ErrorCode};
+use oci::events::{
+    BlobDeleted, BlobPulled, BlobPushed, ManifestDeleted, ManifestPulled, ManifestPushed,
+};
 use oci::header;
-use oci::service::{BlobService, ManifestService, RepoService, UploadService};
+use oci::notification::NotificationService;
+use oci::replication::ReplicationService;
+use oci::retention::RetentionService;
+use oci::service::{
+    BlobService, ImageService, ManifestService, ProxyService, RepoService, UploadService,
+};
+use rbac::Enforcer;
 
 use crate::config::Configuration;
+use crate::http::extractor::scope::OAuthScope;
 use crate::http::extractor::session::TokenSession;
+use crate::http::extractor::user::CurrentUser;
 use crate::storage;
 
 mod api;
 mod blob;
+mod catalog;
 mod error;
+pub mod gc;
 mod manifest;
+mod notification;
+mod referrer;
+mod replication;
+pub mod retention;
 mod tag;
+mod token;
 mod upload;
 
 pub type Result<T> = std::result::Result<T, error::ErrorResponse>;
 
+/// Subscribes the registry event handlers that must run once per process,
+/// not once per worker. The notification service is returned to be shared
+/// with the workers, so that endpoint changes refresh its cache.
+pub fn subscribe(
+    cfg: &Configuration,
+    db: Database,
+    bus: Arc<RwLock<EventBus>>,
+    store: Arc<Provider>,
+) -> Arc<NotificationService> {
+    let db = Arc::new(db);
+    let replication = ReplicationService::new(db.clone(), bus.clone(), store);
+    let registry_url = format!("{}://{}", cfg.service_protocol(), cfg.oci().host());
+    let notification = Arc::new(NotificationService::new(db, &registry_url));
+
+    let mut bus = bus.write().expect("oci::subscribe EventBus unlock");
+    bus.subscribe_wrap(replication);
+    bus.subscribe::<ManifestPushed, _>(notification.clone());
+    bus.subscribe::<ManifestPulled, _>(notification.clone());
+    bus.subscribe::<ManifestDeleted, _>(notification.clone());
+    bus.subscribe::<BlobPushed, _>(notification.clone());
+    bus.subscribe::<BlobPulled, _>(notification.clone());
+    bus.subscribe::<BlobDeleted, _>(notification.clone());
+    notification
+}
+
 pub fn mount(
     cfg: &Configuration,
     db: Database,
     bus: Arc<RwLock<EventBus>>,
     store: Arc<Provider>,
+    notification: Arc<NotificationService>,
 ) -> Box<impl FnOnce(&mut ServiceConfig)> {
     let host = cfg.oci().host();
     let max_body_size = cfg.oci().max_body_size();
@@ -42,28 +90,63 @@ pub fn mount(
         let repo = RepoService::new(db.clone(), bus.clone());
         cfg.data(repo);
 
-        let mut bus = bus.write().expect("oci::mount EventBus unlock");
+        let proxy = ProxyService::new(db.clone(), bus.clone(), store.clone());
+        cfg.data(proxy);
 
-        let repo = UploadService::new(db.clone(), store.clone());
-        cfg.data(repo);
-        let repo_handler = UploadService::new(db.clone(), store.clone());
-        bus.subscribe_wrap(repo_handler);
+        let manifest = ManifestService::new(db.clone(), bus.clone());
+        cfg.data(manifest);
+        let manifest_handler = ManifestService::new(db.clone(), bus.clone());
+
+        let image = ImageService::new(db.clone(), bus.clone(), store.clone());
+        cfg.data(image);
+
+        let retention = RetentionService::new(db.clone(), bus.clone());
+        cfg.data(retention);
 
-        let blob = BlobService::new(db.clone(), store.clone());
+        let replication = ReplicationService::new(db.clone(), bus.clone(), store.clone());
+        cfg.data(replication);
+
+        let upload = UploadService::new(db.clone(), bus.clone(), store.clone());
+        cfg.data(upload);
+        let upload_handler = UploadService::new(db.clone(), bus.clone(), store.clone());
+
+        let blob = BlobService::new(db.clone(), bus.clone(), store.clone());
         cfg.data(blob);
-        let blob_handler = BlobService::new(db.clone(), store.clone());
-        bus.subscribe_wrap(blob_handler);
+        let blob_handler = BlobService::new(db.clone(), bus.clone(), store.clone());
 
-        let manifest = ManifestService::new(db.clone());
-        cfg.data(manifest);
-        let manifest_handler = ManifestService::new(db.clone());
+        cfg.data(notification);
+
+        let mut bus = bus.write().expect("oci::mount EventBus unlock");
+        bus.subscribe_wrap(upload_handler);
+        bus.subscribe_wrap(blob_handler);
         bus.subscribe_wrap(manifest_handler);
 
         cfg.service(api::list_repos);
         cfg.service(api::create_repo);
+        cfg.service(api::preview_retention);
+        cfg.service(api::get_manifest);
         cfg.service(api::get_repo);
+        cfg.service(api::update_repo);
         cfg.service(api::delete_repo);
 
+        cfg.service(replication::list_rules);
+        cfg.service(replication::create_rule);
+        cfg.service(replication::get_rule);
+        cfg.service(replication::update_rule);
+        cfg.service(replication::delete_rule);
+
+        cfg.service(notification::list_endpoints);
+        cfg.service(notification::create_endpoint);
+        cfg.service(notification::list_deliveries);
+        cfg.service(notification::get_endpoint);
+        cfg.service(notification::update_endpoint);
+        cfg.service(notification::delete_endpoint);
+
+        cfg.service(
+            web::resource("/v2/token")
+                .guard(guard::Host(host.clone()))
+                .route(web::get().to(token::issue)),
+        );
         cfg.service(
             web::scope("/v2")
                 .guard(guard::Host(host))
@@ -77,17 +160,22 @@ pub fn mount(
                 .app_data(actix_web::web::Bytes::configure(|cfg| {
                     cfg.limit(max_body_size)
                 }))
+                .app_data(upload::MaxBodySize(max_body_size))
                 .service(root)
+                // Catalog
+                .service(catalog::list)
                 // Tags
                 .service(tag::list)
                 // Manifests
                 .service(
-                    web::resource("/{group}/{name}/manifests/{reference}")
+                    web::resource("/{name:.+}/manifests/{reference}")
                         .route(web::get().to(manifest::get))
                         .route(web::head().to(manifest::get))
                         .route(web::put().to(manifest::put))
                         .route(web::delete().to(manifest::delete)),
                 )
+                // Referrers
+                .service(referrer::list)
                 // Blobs
                 .service(blob::get)
                 .service(blob::head)
@@ -102,12 +190,65 @@ pub fn mount(
     })
 }
 
-#[derive(Debug, Deserialize)]
+/// The repository name matched by the `{name:.+}` segment of a route.
+/// It can have any number of path components, and it is validated
+/// against the distribution spec grammar before reaching the handlers.
+#[derive(Debug)]
 pub struct RepoPath {
-    group: String,
     name: String,
 }
 
+impl RepoPath {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl FromRequest for RepoPath {
+    type Error = error::ErrorResponse;
+    type Future = Ready<Result<Self>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let name = req.match_info().get("name").unwrap_or_default();
+        let res = oci::name::validate(name)
+            .map(|_| RepoPath {
+                name: name.to_string(),
+            })
+            .map_err(error::ErrorResponse::from);
+        ready(res)
+    }
+}
+
+/// Checks that a repository can be pulled from with the credentials of the request, if any.
+/// Public repositories can be pulled from anonymously, while the other ones answer
+/// 401 to anonymous requests, so that clients ask the token endpoint for credentials.
+pub async fn authorize_pull(
+    proxies: &ProxyService,
+    enforcer: &tokio::sync::RwLock<Enforcer>,
+    name: &str,
+    current_user: Option<&CurrentUser>,
+    scope: Option<&OAuthScope>,
+) -> Result<()> {
+    if proxies.is_public(name).await? {
+        log::debug!("{} is public", name);
+        return Ok(());
+    }
+
+    let (current_user, scope) = match (current_user, scope) {
+        (Some(current_user), Some(scope)) => (current_user, scope),
+        _ => return Err(Error::from(ErrorCode::Unauthorized).into()),
+    };
+    token::image_scope(name, "pull").matches(scope)?;
+    let enforcer = enforcer.read().await;
+    enforcer.check_nested(
+        current_user.id(),
+        &Repo::build_guid(&Repo::build_id(name)),
+        "image:pull",
+    )?;
+    Ok(())
+}
+
 #[get("")]
 pub async fn root(session: TokenSession) -> HttpResponse {
     log::debug!("{:?}", session);
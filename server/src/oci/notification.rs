@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use actix_web::web::{Data, Json, Path, Query};
+use actix_web::{delete, get, post, put};
+use chrono::{DateTime, Utc};
+use http::header::{HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use url::Url;
+
+use enseada::couchdb::repository::{Entity, Repository};
+use enseada::guid::Guid;
+use enseada::pagination::Page;
+use oauth::scope::Scope;
+use oci::notification::{Action, Delivery, Endpoint, NotificationService};
+use rbac::Enforcer;
+
+use crate::http::error::ApiError;
+use crate::http::extractor::scope::OAuthScope;
+use crate::http::extractor::user::CurrentUser;
+use crate::http::{validate_patterns, ApiResult, PaginationQuery};
+
+/// A notification endpoint. Header values are omitted, as they usually hold credentials.
+#[derive(Debug, Serialize)]
+pub struct EndpointResponse {
+    name: String,
+    url: String,
+    headers: Vec<String>,
+    actions: Vec<Action>,
+    repositories: Vec<String>,
+    ignored_media_types: Vec<String>,
+    timeout: u64,
+    enabled: bool,
+}
+
+impl From<&Endpoint> for EndpointResponse {
+    fn from(endpoint: &Endpoint) -> Self {
+        let mut headers: Vec<String> = endpoint.headers().keys().cloned().collect();
+        headers.sort();
+        Self {
+            name: endpoint.name().to_string(),
+            url: endpoint.url().to_string(),
+            headers,
+            actions: endpoint.actions().to_vec(),
+            repositories: endpoint.repositories().to_vec(),
+            ignored_media_types: endpoint.ignored_media_types().to_vec(),
+            timeout: endpoint.timeout(),
+            enabled: endpoint.is_enabled(),
+        }
+    }
+}
+
+impl From<Endpoint> for EndpointResponse {
+    fn from(endpoint: Endpoint) -> Self {
+        Self::from(&endpoint)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeliveryResponse {
+    event: String,
+    action: Action,
+    repository: String,
+    digest: String,
+    attempts: u32,
+    delivered: bool,
+    error: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+impl From<Delivery> for DeliveryResponse {
+    fn from(delivery: Delivery) -> Self {
+        Self {
+            event: delivery.event().to_string(),
+            action: delivery.action(),
+            repository: delivery.repository().to_string(),
+            digest: delivery.digest().to_string(),
+            attempts: delivery.attempts(),
+            delivered: delivery.is_delivered(),
+            error: delivery.error().map(str::to_string),
+            created_at: *delivery.created_at(),
+        }
+    }
+}
+
+#[get("/api/oci/v1beta1/notifications/endpoints")]
+pub async fn list_endpoints(
+    service: Data<Arc<NotificationService>>,
+    enforcer: Data<Arc<RwLock<Enforcer>>>,
+    scope: OAuthScope,
+    current_user: CurrentUser,
+    list: Query<PaginationQuery>,
+) -> ApiResult<Json<Page<EndpointResponse>>> {
+    Scope::from("oci:notifications:read").matches(&scope)?;
+    let enforcer = enforcer.read().await;
+    enforcer.check(
+        current_user.id(),
+        &Guid::simple("oci_notifications"),
+        "read",
+    )?;
+    let limit = list.limit();
+    let offset = list.offset();
+
+    let page = service
+        .list(limit, offset)
+        .await?
+        .map(EndpointResponse::from);
+    Ok(Json(page))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateEndpointPayload {
+    name: String,
+    url: String,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    actions: Vec<Action>,
+    #[serde(default)]
+    repositories: Vec<String>,
+    #[serde(default)]
+    ignored_media_types: Vec<String>,
+    timeout: Option<u64>,
+    #[serde(default = "default_enabled")]
+    enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn validate_url(url: &str) -> ApiResult<()> {
+    Url::parse(url)
+        .map(|_| ())
+        .map_err(|err| ApiError::BadRequest(format!("invalid endpoint url: {}", err)))
+}
+
+fn validate_headers(headers: &HashMap<String, String>) -> ApiResult<()> {
+    for (name, value) in headers {
+        HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| ApiError::BadRequest(format!("invalid header name '{}'", name)))?;
+        HeaderValue::from_str(value)
+            .map_err(|_| ApiError::BadRequest(format!("invalid value for header '{}'", name)))?;
+    }
+    Ok(())
+}
+
+fn validate_timeout(timeout: u64) -> ApiResult<()> {
+    if timeout == 0 {
+        return Err(ApiError::BadRequest(
+            "timeout must be at least 1 second".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[post("/api/oci/v1beta1/notifications/endpoints")]
+pub async fn create_endpoint(
+    service: Data<Arc<NotificationService>>,
+    enforcer: Data<Arc<RwLock<Enforcer>>>,
+    scope: OAuthScope,
+    current_user: CurrentUser,
+    body: Json<CreateEndpointPayload>,
+) -> ApiResult<Json<EndpointResponse>> {
+    Scope::from("oci:notifications:manage").matches(&scope)?;
+    let enforcer = enforcer.read().await;
+    enforcer.check(
+        current_user.id(),
+        &Guid::simple("oci_notifications"),
+        "create",
+    )?;
+
+    if service.find_endpoint(&body.name).await?.is_some() {
+        return Err(ApiError::Conflict(format!(
+            "notification endpoint '{}' already exists",
+            &body.name
+        )));
+    }
+
+    validate_url(&body.url)?;
+    validate_headers(&body.headers)?;
+    validate_patterns(&body.repositories)?;
+    let mut endpoint = Endpoint::new(&body.name, &body.url);
+    if let Some(timeout) = body.timeout {
+        validate_timeout(timeout)?;
+        endpoint.set_timeout(timeout);
+    }
+    endpoint
+        .set_headers(body.headers.clone())
+        .set_actions(body.actions.clone())
+        .set_repositories(body.repositories.clone())
+        .set_ignored_media_types(body.ignored_media_types.clone())
+        .set_enabled(body.enabled);
+    let endpoint = service.save(endpoint).await?;
+
+    Ok(Json(EndpointResponse::from(endpoint)))
+}
+
+#[get("/api/oci/v1beta1/notifications/endpoints/{name}")]
+pub async fn get_endpoint(
+    service: Data<Arc<NotificationService>>,
+    enforcer: Data<Arc<RwLock<Enforcer>>>,
+    scope: OAuthScope,
+    current_user: CurrentUser,
+    path: Path<String>,
+) -> ApiResult<Json<EndpointResponse>> {
+    Scope::from("oci:notifications:read").matches(&scope)?;
+    let enforcer = enforcer.read().await;
+    enforcer.check(
+        current_user.id(),
+        &Guid::simple("oci_notifications"),
+        "read",
+    )?;
+
+    let name = path.as_str();
+    let endpoint = service.find_endpoint(name).await?.ok_or_else(|| {
+        ApiError::not_found(&format!("notification endpoint '{}' not found", name))
+    })?;
+
+    Ok(Json(EndpointResponse::from(endpoint)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateEndpointPayload {
+    url: Option<String>,
+    headers: Option<HashMap<String, String>>,
+    actions: Option<Vec<Action>>,
+    repositories: Option<Vec<String>>,
+    ignored_media_types: Option<Vec<String>>,
+    timeout: Option<u64>,
+    enabled: Option<bool>,
+}
+
+#[put("/api/oci/v1beta1/notifications/endpoints/{name}")]
+pub async fn update_endpoint(
+    service: Data<Arc<NotificationService>>,
+    enforcer: Data<Arc<RwLock<Enforcer>>>,
+    scope: OAuthScope,
+    current_user: CurrentUser,
+    path: Path<String>,
+    body: Json<UpdateEndpointPayload>,
+) -> ApiResult<Json<EndpointResponse>> {
+    Scope::from("oci:notifications:manage").matches(&scope)?;
+    let enforcer = enforcer.read().await;
+    enforcer.check(
+        current_user.id(),
+        &Guid::simple("oci_notifications"),
+        "update",
+    )?;
+
+    let name = path.as_str();
+    let mut endpoint = service.find_endpoint(name).await?.ok_or_else(|| {
+        ApiError::not_found(&format!("notification endpoint '{}' not found", name))
+    })?;
+
+    log::debug!("updating OCI notification endpoint {}", name);
+    if let Some(url) = &body.url {
+        validate_url(url)?;
+        endpoint.set_url(url);
+    }
+
+    if let Some(headers) = &body.headers {
+        validate_headers(headers)?;
+        endpoint.set_headers(headers.clone());
+    }
+
+    if let Some(actions) = &body.actions {
+        endpoint.set_actions(actions.clone());
+    }
+
+    if let Some(repositories) = &body.repositories {
+        validate_patterns(repositories)?;
+        endpoint.set_repositories(repositories.clone());
+    }
+
+    if let Some(media_types) = &body.ignored_media_types {
+        endpoint.set_ignored_media_types(media_types.clone());
+    }
+
+    if let Some(timeout) = body.timeout {
+        validate_timeout(timeout)?;
+        endpoint.set_timeout(timeout);
+    }
+
+    if let Some(enabled) = body.enabled {
+        endpoint.set_enabled(enabled);
+    }
+
+    let endpoint = service.save(endpoint).await?;
+    Ok(Json(EndpointResponse::from(endpoint)))
+}
+
+#[delete("/api/oci/v1beta1/notifications/endpoints/{name}")]
+pub async fn delete_endpoint(
+    service: Data<Arc<NotificationService>>,
+    enforcer: Data<Arc<RwLock<Enforcer>>>,
+    scope: OAuthScope,
+    current_user: CurrentUser,
+    path: Path<String>,
+) -> ApiResult<Json<EndpointResponse>> {
+    Scope::from("oci:notifications:manage").matches(&scope)?;
+    let enforcer = enforcer.read().await;
+    enforcer.check(
+        current_user.id(),
+        &Guid::simple("oci_notifications"),
+        "delete",
+    )?;
+
+    let name = path.as_str();
+    let endpoint = service.find_endpoint(name).await?.ok_or_else(|| {
+        ApiError::not_found(&format!("notification endpoint '{}' not found", name))
+    })?;
+
+    service.delete(&endpoint).await?;
+    Ok(Json(EndpointResponse::from(endpoint)))
+}
+
+#[get("/api/oci/v1beta1/notifications/endpoints/{name}/deliveries")]
+pub async fn list_deliveries(
+    service: Data<Arc<NotificationService>>,
+    enforcer: Data<Arc<RwLock<Enforcer>>>,
+    scope: OAuthScope,
+    current_user: CurrentUser,
+    path: Path<String>,
+    list: Query<PaginationQuery>,
+) -> ApiResult<Json<Page<DeliveryResponse>>> {
+    Scope::from("oci:notifications:read").matches(&scope)?;
+    let enforcer = enforcer.read().await;
+    enforcer.check(
+        current_user.id(),
+        &Guid::simple("oci_notifications"),
+        "read",
+    )?;
+
+    let name = path.as_str();
+    service.find_endpoint(name).await?.ok_or_else(|| {
+        ApiError::not_found(&format!("notification endpoint '{}' not found", name))
+    })?;
+
+    let page = service
+        .list_deliveries(name, list.limit(), list.offset())
+        .await?
+        .map(DeliveryResponse::from);
+    Ok(Json(page))
+}
@@ -0,0 +1,74 @@
+use std::sync::Arc;
+
+use actix_web::web::{Data, Path, Query};
+use actix_web::{get, HttpResponse};
+use serde::Deserialize;
+use tokio::sync::RwLock;
+
+use enseada::couchdb::repository::Repository;
+use oci::entity::Repo;
+use oci::error::{Error, ErrorCode};
+use oci::header;
+use oci::manifest::ImageIndex;
+use oci::mime::MediaType;
+use oci::service::{ManifestService, ProxyService, RepoService};
+use rbac::Enforcer;
+
+use crate::http::extractor::scope::OAuthScope;
+use crate::http::extractor::user::CurrentUser;
+use crate::oci::upload::DigestParam;
+use crate::oci::{authorize_pull, RepoPath, Result};
+
+#[derive(Debug, Deserialize)]
+pub struct ReferrersQuery {
+    #[serde(rename = "artifactType")]
+    artifact_type: Option<String>,
+}
+
+#[allow(clippy::too_many_arguments)]
+#[get("/{name:.+}/referrers/{digest}")]
+pub async fn list(
+    manifests: Data<ManifestService>,
+    repos: Data<RepoService>,
+    proxies: Data<ProxyService>,
+    repo: RepoPath,
+    digest: Path<DigestParam>,
+    query: Query<ReferrersQuery>,
+    enforcer: Data<Arc<RwLock<Enforcer>>>,
+    scope: Option<OAuthScope>,
+    current_user: Option<CurrentUser>,
+) -> Result<HttpResponse> {
+    let name = repo.name();
+    let digest = &digest.digest;
+    authorize_pull(
+        &proxies,
+        &enforcer,
+        name,
+        current_user.as_ref(),
+        scope.as_ref(),
+    )
+    .await?;
+    let repo_id = Repo::build_id(name);
+
+    log::debug!("looking for repo {}", name);
+    repos
+        .find(&repo_id)
+        .await?
+        .ok_or_else(|| Error::from(ErrorCode::NameUnknown))?;
+
+    let artifact_type = query.artifact_type.as_deref();
+    log::debug!("listing referrers for {}", digest);
+    let referrers = manifests
+        .list_referrers(name, digest, artifact_type)
+        .await?;
+
+    let mut res = HttpResponse::Ok();
+    res.header(
+        http::header::CONTENT_TYPE,
+        MediaType::ImageIndex.to_string(),
+    );
+    if artifact_type.is_some() {
+        res.header(header::FILTERS_APPLIED, "artifactType");
+    }
+    Ok(res.json(ImageIndex::new(referrers)))
+}
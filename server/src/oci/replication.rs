@@ -0,0 +1,225 @@
+use std::sync::Arc;
+
+use actix_web::web::{Data, Json, Path, Query};
+use actix_web::{delete, get, post, put};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use enseada::couchdb::repository::{Entity, Repository};
+use enseada::guid::Guid;
+use enseada::pagination::Page;
+use enseada::replication::ReplicationStatus;
+use oauth::scope::Scope;
+use oci::replication::{ReplicationRule, ReplicationService};
+use rbac::Enforcer;
+
+use crate::http::error::ApiError;
+use crate::http::extractor::scope::OAuthScope;
+use crate::http::extractor::user::CurrentUser;
+use crate::http::{validate_patterns, ApiResult, PaginationQuery, RemotePayload, RemoteResponse};
+
+#[derive(Debug, Serialize)]
+pub struct RuleResponse {
+    name: String,
+    repositories: Vec<String>,
+    target: RemoteResponse,
+    namespace: Option<String>,
+    enabled: bool,
+    status: ReplicationStatus,
+}
+
+impl From<&ReplicationRule> for RuleResponse {
+    fn from(rule: &ReplicationRule) -> Self {
+        Self {
+            name: rule.name().to_string(),
+            repositories: rule.repositories().to_vec(),
+            target: RemoteResponse::from(rule.target()),
+            namespace: rule.namespace().map(str::to_string),
+            enabled: rule.is_enabled(),
+            status: rule.status().clone(),
+        }
+    }
+}
+
+impl From<ReplicationRule> for RuleResponse {
+    fn from(rule: ReplicationRule) -> Self {
+        Self::from(&rule)
+    }
+}
+
+#[get("/api/oci/v1beta1/replication/rules")]
+pub async fn list_rules(
+    service: Data<ReplicationService>,
+    enforcer: Data<Arc<RwLock<Enforcer>>>,
+    scope: OAuthScope,
+    current_user: CurrentUser,
+    list: Query<PaginationQuery>,
+) -> ApiResult<Json<Page<RuleResponse>>> {
+    Scope::from("oci:replication:read").matches(&scope)?;
+    let enforcer = enforcer.read().await;
+    enforcer.check(current_user.id(), &Guid::simple("oci_replication"), "read")?;
+    let limit = list.limit();
+    let offset = list.offset();
+
+    let page = service.list(limit, offset).await?.map(RuleResponse::from);
+    Ok(Json(page))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateRulePayload {
+    name: String,
+    repositories: Vec<String>,
+    target: RemotePayload,
+    namespace: Option<String>,
+    #[serde(default = "default_enabled")]
+    enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn validate_namespace(namespace: &Option<String>) -> ApiResult<()> {
+    if let Some(namespace) = namespace {
+        oci::name::validate(namespace)?;
+    }
+    Ok(())
+}
+
+#[post("/api/oci/v1beta1/replication/rules")]
+pub async fn create_rule(
+    service: Data<ReplicationService>,
+    enforcer: Data<Arc<RwLock<Enforcer>>>,
+    scope: OAuthScope,
+    current_user: CurrentUser,
+    body: Json<CreateRulePayload>,
+) -> ApiResult<Json<RuleResponse>> {
+    Scope::from("oci:replication:manage").matches(&scope)?;
+    let enforcer = enforcer.read().await;
+    enforcer.check(
+        current_user.id(),
+        &Guid::simple("oci_replication"),
+        "create",
+    )?;
+
+    if service.find_rule(&body.name).await?.is_some() {
+        return Err(ApiError::Conflict(format!(
+            "replication rule '{}' already exists",
+            &body.name
+        )));
+    }
+
+    validate_patterns(&body.repositories)?;
+    validate_namespace(&body.namespace)?;
+    let mut rule = ReplicationRule::new(
+        &body.name,
+        body.repositories.clone(),
+        body.target.to_remote()?,
+    );
+    rule.set_namespace(body.namespace.clone())
+        .set_enabled(body.enabled);
+    let rule = service.save(rule).await?;
+
+    Ok(Json(RuleResponse::from(rule)))
+}
+
+#[get("/api/oci/v1beta1/replication/rules/{name}")]
+pub async fn get_rule(
+    service: Data<ReplicationService>,
+    enforcer: Data<Arc<RwLock<Enforcer>>>,
+    scope: OAuthScope,
+    current_user: CurrentUser,
+    path: Path<String>,
+) -> ApiResult<Json<RuleResponse>> {
+    Scope::from("oci:replication:read").matches(&scope)?;
+    let enforcer = enforcer.read().await;
+    enforcer.check(current_user.id(), &Guid::simple("oci_replication"), "read")?;
+
+    let name = path.as_str();
+    let rule = service
+        .find_rule(name)
+        .await?
+        .ok_or_else(|| ApiError::not_found(&format!("replication rule '{}' not found", name)))?;
+
+    Ok(Json(RuleResponse::from(rule)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateRulePayload {
+    repositories: Option<Vec<String>>,
+    target: Option<RemotePayload>,
+    namespace: Option<String>,
+    enabled: Option<bool>,
+}
+
+#[put("/api/oci/v1beta1/replication/rules/{name}")]
+pub async fn update_rule(
+    service: Data<ReplicationService>,
+    enforcer: Data<Arc<RwLock<Enforcer>>>,
+    scope: OAuthScope,
+    current_user: CurrentUser,
+    path: Path<String>,
+    body: Json<UpdateRulePayload>,
+) -> ApiResult<Json<RuleResponse>> {
+    Scope::from("oci:replication:manage").matches(&scope)?;
+    let enforcer = enforcer.read().await;
+    enforcer.check(
+        current_user.id(),
+        &Guid::simple("oci_replication"),
+        "update",
+    )?;
+
+    let name = path.as_str();
+    let mut rule = service
+        .find_rule(name)
+        .await?
+        .ok_or_else(|| ApiError::not_found(&format!("replication rule '{}' not found", name)))?;
+
+    log::debug!("updating OCI replication rule {}", name);
+    if let Some(repositories) = &body.repositories {
+        validate_patterns(repositories)?;
+        rule.set_repositories(repositories.clone());
+    }
+
+    if let Some(target) = &body.target {
+        rule.set_target(target.to_remote()?);
+    }
+
+    if body.namespace.is_some() {
+        validate_namespace(&body.namespace)?;
+        rule.set_namespace(body.namespace.clone());
+    }
+
+    if let Some(enabled) = body.enabled {
+        rule.set_enabled(enabled);
+    }
+
+    let rule = service.save(rule).await?;
+    Ok(Json(RuleResponse::from(rule)))
+}
+
+#[delete("/api/oci/v1beta1/replication/rules/{name}")]
+pub async fn delete_rule(
+    service: Data<ReplicationService>,
+    enforcer: Data<Arc<RwLock<Enforcer>>>,
+    scope: OAuthScope,
+    current_user: CurrentUser,
+    path: Path<String>,
+) -> ApiResult<Json<RuleResponse>> {
+    Scope::from("oci:replication:manage").matches(&scope)?;
+    let enforcer = enforcer.read().await;
+    enforcer.check(
+        current_user.id(),
+        &Guid::simple("oci_replication"),
+        "delete",
+    )?;
+
+    let name = path.as_str();
+    let rule = service
+        .find_rule(name)
+        .await?
+        .ok_or_else(|| ApiError::not_found(&format!("replication rule '{}' not found", name)))?;
+
+    service.delete(&rule).await?;
+    Ok(Json(RuleResponse::from(rule)))
+}
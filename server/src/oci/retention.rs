@@ -0,0 +1,47 @@
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use actix_rt::time::{interval_at, Instant};
+use actix_rt::Arbiter;
+
+use enseada::couchdb::db::Database;
+use events::EventBus;
+use oci::retention::RetentionService;
+
+use crate::config::Configuration;
+
+/// Applies the retention policies of OCI repositories periodically in its own arbiter.
+pub struct RetentionJob {
+    arbiter: Arbiter,
+    retention: Arc<RetentionService>,
+    interval: Duration,
+}
+
+impl RetentionJob {
+    pub fn new(cfg: &Configuration, db: Database, bus: Arc<RwLock<EventBus>>) -> Self {
+        RetentionJob {
+            arbiter: Arbiter::new(),
+            retention: Arc::new(RetentionService::new(Arc::new(db), bus)),
+            interval: Duration::from_secs(cfg.oci().retention().interval()),
+        }
+    }
+
+    pub fn start(&self) {
+        let retention = self.retention.clone();
+        let period = self.interval;
+        let fut = Box::pin(async move {
+            let mut interval = interval_at(Instant::now() + period, period);
+            loop {
+                interval.tick().await;
+                if let Err(err) = retention.run(false).await {
+                    log::error!("OCI retention policies failed: {}", err);
+                }
+            }
+        });
+        self.arbiter.send(fut);
+    }
+
+    pub fn stop(&self) {
+        self.arbiter.stop();
+    }
+}
@@ -1,21 +1,20 @@
 use std::cmp::{max, min};
 use std::sync::Arc;
 
-use actix_web::web::{Data, Path, Query};
+use actix_web::web::{Data, Query};
 use actix_web::{get, HttpResponse};
 use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
 
-use enseada::couchdb::repository::{Entity, Repository};
-use oauth::scope::Scope;
+use enseada::couchdb::repository::Repository;
 use oci::entity::Repo;
 use oci::error::{Error, ErrorCode};
-use oci::service::RepoService;
+use oci::service::{ProxyService, RepoService};
 use rbac::Enforcer;
 
 use crate::http::extractor::scope::OAuthScope;
 use crate::http::extractor::user::CurrentUser;
-use crate::oci::{RepoPath, Result};
+use crate::oci::{authorize_pull, RepoPath, Result};
 
 #[derive(Debug, Serialize)]
 pub struct TagList {
@@ -29,25 +28,31 @@ pub struct TagPagination {
     last: Option<String>,
 }
 
-#[get("/{group}/{name}/tags/list")]
+#[allow(clippy::too_many_arguments)]
+#[get("/{name:.+}/tags/list")]
 pub async fn list(
     repos: Data<RepoService>,
-    repo: Path<RepoPath>,
+    proxies: Data<ProxyService>,
+    repo: RepoPath,
     page: Option<Query<TagPagination>>,
     enforcer: Data<Arc<RwLock<Enforcer>>>,
-    scope: OAuthScope,
-    current_user: CurrentUser,
+    scope: Option<OAuthScope>,
+    current_user: Option<CurrentUser>,
 ) -> Result<HttpResponse> {
-    Scope::from("oci:image:pull").matches(&scope)?;
-    let group = &repo.group;
-    let name = &repo.name;
-    let repo_id = Repo::build_id(group, name);
-    let enforcer = enforcer.read().await;
-    enforcer.check(current_user.id(), &Repo::build_guid(&repo_id), "image:pull")?;
+    let name = repo.name();
+    authorize_pull(
+        &proxies,
+        &enforcer,
+        name,
+        current_user.as_ref(),
+        scope.as_ref(),
+    )
+    .await?;
+    let repo_id = Repo::build_id(name);
 
-    log::debug!("looking for repo {}/{}", group, name);
+    log::debug!("looking for repo {}", name);
     let repo = repos
-        .find(&Repo::build_id(group, name))
+        .find(&repo_id)
         .await?
         .ok_or_else(|| Error::from(ErrorCode::NameUnknown))?;
 
@@ -76,8 +81,8 @@ pub async fn list(
         res.header(
             http::header::LINK,
             format!(
-                "</v2/{}/{}/tags/list?n={}&last={}>; rel=\"next\"",
-                group, name, n, new_last
+                "</v2/{}/tags/list?n={}&last={}>; rel=\"next\"",
+                name, n, new_last
             ),
         )
         .json(list)
@@ -0,0 +1,327 @@
+use std::sync::Arc;
+
+use actix_web::http::header;
+use actix_web::web::Data;
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use actix_web_httpauth::headers::authorization::{Basic, Scheme};
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use tokio::sync::RwLock;
+
+use enseada::couchdb::repository::Entity;
+use enseada::guid::Guid;
+use oauth::handler::TokenIntrospectionHandler;
+use oauth::persistence::CouchStorage;
+use oauth::scope::Scope;
+use oauth::session::Session;
+use oauth::storage::TokenStorage;
+use oauth::token::{AccessToken, Token};
+use oauth::{CouchOAuthHandler, Expirable};
+use oci::entity::Repo;
+use oci::service::ProxyService;
+use rbac::Enforcer;
+use users::UserService;
+
+use crate::oci::error::UnauthorizedError;
+use crate::oci::Result;
+
+/// Registry tokens are meant to be requested for each operation, so they are short-lived.
+const TOKEN_TTL: i64 = 300;
+
+/// Client of the sessions created by logging into the registry with a username and password.
+const REGISTRY_CLIENT_ID: &str = "oci-registry";
+
+/// The username to log in with a personal access token, as for the other `/v2` endpoints.
+const TOKEN_USERNAME: &str = "x-oauth-token";
+
+/// The image actions that can be requested for a repository.
+const ACTIONS: [&str; 3] = ["pull", "push", "delete"];
+
+#[derive(Debug, Serialize)]
+struct TokenResponse {
+    token: String,
+    access_token: String,
+    expires_in: i64,
+    issued_at: DateTime<Utc>,
+}
+
+/// The scope granted to registry tokens requesting `registry:catalog:*`,
+/// allowing to list the private repositories the user can pull from.
+pub const CATALOG_SCOPE: &str = "oci:catalog:read";
+
+/// A scope requested by a Docker client, either `repository:<name>:<actions>`
+/// or `registry:catalog:*`.
+#[derive(Debug, PartialEq)]
+enum ResourceScope {
+    Repository { name: String, actions: Vec<String> },
+    Catalog,
+}
+
+impl ResourceScope {
+    /// Parses a resource scope, ignoring the unknown resource types and actions.
+    fn parse(scope: &str) -> Option<Self> {
+        if scope == "registry:catalog:*" {
+            return Some(ResourceScope::Catalog);
+        }
+
+        let scope = scope.strip_prefix("repository:")?;
+        let sep = scope.rfind(':')?;
+        let name = &scope[..sep];
+        if oci::name::validate(name).is_err() {
+            return None;
+        }
+
+        let mut actions = Vec::new();
+        for action in scope[sep + 1..].split(',') {
+            match action {
+                "*" => actions.extend(ACTIONS.iter().map(|action| action.to_string())),
+                action if ACTIONS.contains(&action) => actions.push(action.to_string()),
+                _ => {}
+            }
+        }
+        actions.sort();
+        actions.dedup();
+        Some(ResourceScope::Repository {
+            name: name.to_string(),
+            actions,
+        })
+    }
+}
+
+/// The scopes granting an action on the images of a repository: either the global
+/// one (e.g. `oci:image:pull`), or the one restricted to the repository
+/// (e.g. `oci:image:pull:library/alpine`) that registry tokens are issued with.
+pub fn image_scope(name: &str, action: &str) -> Scope {
+    Scope::from(vec![
+        format!("oci:image:{}", action),
+        format!("oci:image:{}:{}", action, name),
+    ])
+}
+
+/// Issues registry tokens, as described by the Docker token authentication spec.
+///
+/// Clients authenticate with basic auth, using either a username and password
+/// or a personal access token as the password. The token is granted the requested
+/// repository actions allowed both by the user permissions and by the scope of
+/// the personal access token, if any. Actions that are not allowed are left out,
+/// instead of failing the request. Anonymous clients can only be granted pulls
+/// from public repositories.
+pub async fn issue(
+    req: HttpRequest,
+    users: Data<UserService>,
+    proxies: Data<ProxyService>,
+    handler: Data<CouchOAuthHandler>,
+    storage: Data<CouchStorage>,
+    enforcer: Data<Arc<RwLock<Enforcer>>>,
+) -> Result<HttpResponse> {
+    let credentials = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| Basic::parse(h).ok());
+    let (mut session, user_id) = match credentials {
+        Some(credentials) => {
+            let password = credentials.password().map(|password| password.as_ref());
+            let session =
+                match authenticate(&users, &handler, credentials.user_id(), password).await {
+                    Some(session) => session,
+                    None => return Ok(unauthorized()),
+                };
+            let user_id = match session.user_id() {
+                Some(user_id) => Guid::from(user_id),
+                None => return Ok(unauthorized()),
+            };
+            (session, Some(user_id))
+        }
+        None => {
+            log::debug!("no credentials found in token request, issuing an anonymous token");
+            (Session::for_client(REGISTRY_CLIENT_ID.to_string()), None)
+        }
+    };
+
+    let enforcer = enforcer.read().await;
+    let mut granted = Vec::new();
+    for resource in requested_scopes(&req) {
+        let (name, actions) = match resource {
+            ResourceScope::Repository { name, actions } => (name, actions),
+            ResourceScope::Catalog => {
+                // anonymous catalog requests are allowed, but only list public repositories
+                let allowed = user_id.is_some()
+                    && Scope::from(vec!["oci:image:pull", CATALOG_SCOPE])
+                        .matches(session.scope())
+                        .is_ok();
+                if allowed {
+                    granted.push(CATALOG_SCOPE.to_string());
+                }
+                continue;
+            }
+        };
+        let repo_guid = Repo::build_guid(&Repo::build_id(&name));
+        for action in actions {
+            let allowed = match &user_id {
+                Some(user_id) => {
+                    image_scope(&name, &action).matches(session.scope()).is_ok()
+                        && enforcer
+                            .check_nested(user_id, &repo_guid, &format!("image:{}", action))
+                            .is_ok()
+                }
+                None => action == "pull" && proxies.is_public(&name).await?,
+            };
+            if allowed {
+                granted.push(format!("oci:image:{}:{}", action, name));
+            } else {
+                log::debug!("{} is not allowed on {}", action, name);
+            }
+        }
+    }
+    log::debug!("issuing registry token with scope {:?}", &granted);
+    session.set_scope(Scope::from(granted));
+
+    let (token, sig) = handler.generate_token_with_sig()?;
+    let issued_at = Utc::now();
+    let expiration = issued_at + Duration::seconds(TOKEN_TTL);
+    let token = storage
+        .store_token(
+            &sig.to_string(),
+            AccessToken::new(token, session, expiration),
+        )
+        .await?
+        .to_string();
+
+    Ok(HttpResponse::Ok().json(TokenResponse {
+        token: token.clone(),
+        access_token: token,
+        expires_in: TOKEN_TTL,
+        issued_at,
+    }))
+}
+
+/// Authenticates a token request, returning the session the token is issued for.
+async fn authenticate(
+    users: &UserService,
+    handler: &CouchOAuthHandler,
+    username: &str,
+    password: Option<&str>,
+) -> Option<Session> {
+    let password = password?;
+    if username != TOKEN_USERNAME {
+        if let Ok(user) = users.authenticate_user(username, password).await {
+            let mut session = Session::for_client(REGISTRY_CLIENT_ID.to_string());
+            session
+                .set_user_id(user.id().to_string())
+                .set_scope(Scope::from(vec![
+                    "oci:image:pull",
+                    "oci:image:push",
+                    "oci:image:delete",
+                ]));
+            return Some(session);
+        }
+    }
+
+    let token: AccessToken = handler.get_token(password).await.ok()?;
+    if token.is_expired() {
+        log::debug!("token used in token request is expired");
+        return None;
+    }
+    let session = token.session();
+    let owner = session.user_id()?;
+    if username != TOKEN_USERNAME && owner != users::User::build_guid(username).to_string() {
+        log::debug!("token used in token request belongs to another user");
+        return None;
+    }
+    Some(session.clone())
+}
+
+/// Extracts the resource scopes of a token request.
+/// Clients can request several scopes by repeating the `scope` parameter.
+fn requested_scopes(req: &HttpRequest) -> Vec<ResourceScope> {
+    url::form_urlencoded::parse(req.query_string().as_bytes())
+        .filter(|(key, _)| key == "scope")
+        .flat_map(|(_, value)| {
+            value
+                .split(' ')
+                .filter_map(ResourceScope::parse)
+                .collect::<Vec<ResourceScope>>()
+        })
+        .collect()
+}
+
+fn unauthorized() -> HttpResponse {
+    UnauthorizedError::basic().error_response()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn repository(name: &str, actions: &[&str]) -> Option<ResourceScope> {
+        Some(ResourceScope::Repository {
+            name: name.to_string(),
+            actions: actions.iter().map(|action| action.to_string()).collect(),
+        })
+    }
+
+    #[test]
+    fn it_parses_repository_scopes() {
+        assert_eq!(
+            ResourceScope::parse("repository:library/alpine:pull"),
+            repository("library/alpine", &["pull"])
+        );
+        assert_eq!(
+            ResourceScope::parse("repository:org/team/app:push,pull"),
+            repository("org/team/app", &["pull", "push"])
+        );
+    }
+
+    #[test]
+    fn it_expands_wildcard_actions() {
+        assert_eq!(
+            ResourceScope::parse("repository:alpine:*"),
+            repository("alpine", &["delete", "pull", "push"])
+        );
+        assert_eq!(
+            ResourceScope::parse("repository:alpine:pull,*"),
+            repository("alpine", &["delete", "pull", "push"])
+        );
+    }
+
+    #[test]
+    fn it_ignores_unknown_actions() {
+        assert_eq!(
+            ResourceScope::parse("repository:alpine:pull,tag,"),
+            repository("alpine", &["pull"])
+        );
+        assert_eq!(
+            ResourceScope::parse("repository:alpine:tag"),
+            repository("alpine", &[])
+        );
+    }
+
+    #[test]
+    fn it_parses_the_catalog_scope() {
+        assert_eq!(
+            ResourceScope::parse("registry:catalog:*"),
+            Some(ResourceScope::Catalog)
+        );
+        assert_eq!(ResourceScope::parse("registry:catalog:pull"), None);
+    }
+
+    #[test]
+    fn it_rejects_invalid_scopes() {
+        for scope in &[
+            "",
+            "repository",
+            "repository:alpine",
+            "repository:Alpine:pull",
+            "repository::pull",
+            "repository:org/-/retention:pull",
+            "image:alpine:pull",
+        ] {
+            assert_eq!(
+                ResourceScope::parse(scope),
+                None,
+                "{} should be rejected",
+                scope
+            );
+        }
+    }
+}
@@ -1,16 +1,16 @@
 use std::sync::{Arc, Mutex};
 
-use actix_web::http::HeaderMap;
-use actix_web::web::{Bytes, Data, Path, Payload, Query};
+use actix_web::web::{Data, Path, Payload, Query};
 use actix_web::{delete, get, patch, post, put, HttpRequest, HttpResponse};
-use futures::TryStreamExt;
+use futures::channel::mpsc;
+use futures::{SinkExt, Stream, StreamExt};
 use serde::Deserialize;
 use tokio::sync::RwLock;
 
 use enseada::couchdb::repository::{Entity, Repository};
-use oauth::scope::Scope;
+use enseada::storage::ByteChunk;
 use oci::digest::Digest;
-use oci::entity::{Blob, Repo, UploadChunk};
+use oci::entity::{Blob, Repo, Upload};
 use oci::error::{Error, ErrorCode};
 use oci::header;
 use oci::service::{BlobService, RepoService, UploadService};
@@ -18,15 +18,31 @@ use rbac::Enforcer;
 
 use crate::http::extractor::scope::OAuthScope;
 use crate::http::extractor::user::CurrentUser;
+use crate::oci::token::image_scope;
 use crate::oci::{RepoPath, Result};
 use std::io;
 
+/// Number of payload chunks buffered while waiting for the storage provider.
+const PAYLOAD_BUFFER_SIZE: usize = 16;
+
+/// The `oci.max_body_size` limit. Upload bodies are streamed rather than
+/// extracted as `Bytes`, so the limit is enforced while reading them.
+#[derive(Clone, Copy, Debug)]
+pub struct MaxBodySize(pub usize);
+
 #[derive(Debug, Deserialize)]
 pub struct DigestParam {
     pub digest: Digest,
 }
 
-#[post("/{group}/{name}/blobs/uploads")]
+#[derive(Debug, Deserialize)]
+pub struct StartUploadQuery {
+    digest: Option<Digest>,
+    mount: Option<Digest>,
+    from: Option<String>,
+}
+
+#[post("/{name:.+}/blobs/uploads")]
 pub async fn start(
     uploads: Data<UploadService>,
     blobs: Data<BlobService>,
@@ -34,34 +50,62 @@ pub async fn start(
     enforcer: Data<Arc<RwLock<Enforcer>>>,
     scope: OAuthScope,
     current_user: CurrentUser,
-    path: Path<RepoPath>,
-    query: Option<Query<DigestParam>>,
-    body: Bytes,
+    path: RepoPath,
+    query: Query<StartUploadQuery>,
+    payload: Payload,
     req: HttpRequest,
 ) -> Result<HttpResponse> {
-    Scope::from("oci:image:push").matches(&scope)?;
-    let group = &path.group;
-    let name = &path.name;
-    let repo_id = Repo::build_id(group, name);
+    let name = path.name();
+    image_scope(name, "push").matches(&scope)?;
+    let repo_id = Repo::build_id(name);
     let enforcer = enforcer.read().await;
-    enforcer.check(current_user.id(), &Repo::build_guid(&repo_id), "image:push")?;
+    enforcer.check_nested(current_user.id(), &Repo::build_guid(&repo_id), "image:push")?;
     let repo = &repos
         .find(&repo_id)
         .await?
         .ok_or_else(|| Error::from(ErrorCode::NameUnknown))?;
 
+    if let (Some(digest), Some(from)) = (&query.mount, &query.from) {
+        let source_id = Repo::build_id(from);
+        let can_pull = oci::name::validate(from).is_ok()
+            && image_scope(from, "pull").matches(&scope).is_ok()
+            && enforcer
+                .check_nested(
+                    current_user.id(),
+                    &Repo::build_guid(&source_id),
+                    "image:pull",
+                )
+                .is_ok();
+        let source = if can_pull {
+            blobs.find_by_digest(from, digest).await?
+        } else {
+            None
+        };
+        // a failed mount falls back to a regular upload session
+        if let Some(source) = source {
+            let blob = blobs.mount_blob(&source, name).await?;
+            return Ok(HttpResponse::Created()
+                .header(
+                    http::header::LOCATION,
+                    format!("/v2/{}/blobs/{}", repo.full_name(), blob.digest()),
+                )
+                .header(header::CONTENT_DIGEST, blob.digest().to_string())
+                .finish());
+        }
+        log::debug!("blob {} cannot be mounted from {}", digest, from);
+    }
+
     let upload = uploads.start_upload(repo).await?;
     let upload_id = upload.id().id().to_string();
-    match query {
-        Some(query) => {
-            let digest = &query.digest;
-            let chunk = chunk_from_request(req.headers(), body.len())?;
-            // TODO: check digest matches chunk
-            uploads
-                .complete_upload(upload, digest, Some((chunk, body)))
+    match &query.digest {
+        Some(digest) => {
+            let (start_range, size) = chunk_from_request(&req, &upload)?;
+            let upload = uploads
+                .push_chunk(upload, start_range, size, payload_stream(&req, payload))
                 .await?;
-            let blob = Blob::new(digest.clone(), group, name);
-            blobs.save(blob).await?;
+            let upload = uploads.complete_upload(repo, upload, digest).await?;
+            let blob = Blob::new(digest.clone(), name, upload.latest_offset());
+            blobs.push_blob(blob).await?;
             Ok(HttpResponse::Created()
                 .header(
                     http::header::LOCATION,
@@ -86,24 +130,23 @@ pub struct UploadPath {
     upload_id: String,
 }
 
-#[get("/{group}/{name}/blobs/uploads/{upload_id}")]
+#[get("/{name:.+}/blobs/uploads/{upload_id}")]
 pub async fn get(
     uploads: Data<UploadService>,
     repos: Data<RepoService>,
     enforcer: Data<Arc<RwLock<Enforcer>>>,
     scope: OAuthScope,
     current_user: CurrentUser,
-    path: Path<RepoPath>,
+    path: RepoPath,
     upload: Path<UploadPath>,
 ) -> Result<HttpResponse> {
-    Scope::from("oci:image:push").matches(&scope)?;
-    let group = &path.group;
-    let name = &path.name;
+    let name = path.name();
+    image_scope(name, "push").matches(&scope)?;
     let upload_id = &upload.upload_id;
-    let repo_id = Repo::build_id(group, name);
+    let repo_id = Repo::build_id(name);
     let enforcer = enforcer.read().await;
-    enforcer.check(current_user.id(), &Repo::build_guid(&repo_id), "image:push")?;
-    log::debug!("looking for repo {}/{}", group, name);
+    enforcer.check_nested(current_user.id(), &Repo::build_guid(&repo_id), "image:push")?;
+    log::debug!("looking for repo {}", name);
     repos
         .find(&repo_id)
         .await?
@@ -121,26 +164,25 @@ pub async fn get(
         .finish())
 }
 
-#[patch("/{group}/{name}/blobs/uploads/{upload_id}")]
+#[patch("/{name:.+}/blobs/uploads/{upload_id}")]
 pub async fn push(
     uploads: Data<UploadService>,
     repos: Data<RepoService>,
     enforcer: Data<Arc<RwLock<Enforcer>>>,
     scope: OAuthScope,
     current_user: CurrentUser,
-    path: Path<RepoPath>,
+    path: RepoPath,
     upload: Path<UploadPath>,
-    body: Bytes,
+    payload: Payload,
     req: HttpRequest,
 ) -> Result<HttpResponse> {
-    Scope::from("oci:image:push").matches(&scope)?;
-    let group = &path.group;
-    let name = &path.name;
+    let name = path.name();
+    image_scope(name, "push").matches(&scope)?;
     let upload_id = &upload.upload_id;
-    let repo_id = Repo::build_id(group, name);
+    let repo_id = Repo::build_id(name);
     let enforcer = enforcer.read().await;
-    enforcer.check(current_user.id(), &Repo::build_guid(&repo_id), "image:push")?;
-    log::debug!("looking for repo {}/{}", group, name);
+    enforcer.check_nested(current_user.id(), &Repo::build_guid(&repo_id), "image:push")?;
+    log::debug!("looking for repo {}", name);
     let repo = repos
         .find(&repo_id)
         .await?
@@ -151,11 +193,11 @@ pub async fn push(
         .await?
         .ok_or_else(|| Error::from(ErrorCode::BlobUploadUnknown))?;
 
-    log::debug!("building chunk");
-    let chunk = chunk_from_request(req.headers(), body.len())?;
-
+    let (start_range, size) = chunk_from_request(&req, &upload)?;
     log::debug!("pushing chunk");
-    let upload = uploads.push_chunk(upload, chunk, body).await?;
+    let upload = uploads
+        .push_chunk(upload, start_range, size, payload_stream(&req, payload))
+        .await?;
 
     Ok(HttpResponse::Accepted()
         .header(
@@ -167,14 +209,7 @@ pub async fn push(
         .finish())
 }
 
-#[derive(Debug, Deserialize)]
-pub struct CompletePath {
-    group: String,
-    name: String,
-    upload_id: String,
-}
-
-#[put("/{group}/{name}/blobs/uploads/{upload_id}")]
+#[put("/{name:.+}/blobs/uploads/{upload_id}")]
 pub async fn complete(
     uploads: Data<UploadService>,
     blobs: Data<BlobService>,
@@ -182,21 +217,21 @@ pub async fn complete(
     enforcer: Data<Arc<RwLock<Enforcer>>>,
     scope: OAuthScope,
     current_user: CurrentUser,
-    path: Path<CompletePath>,
+    path: RepoPath,
     digest: Query<DigestParam>,
-    body: Option<Bytes>,
+    payload: Payload,
     req: HttpRequest,
 ) -> Result<HttpResponse> {
-    Scope::from("oci:image:push").matches(&scope)?;
-    let group = &path.group;
-    let name = &path.name;
-    let upload_id = &path.upload_id;
+    let name = path.name();
+    image_scope(name, "push").matches(&scope)?;
+    // actix-web handlers are limited to ten extractors
+    let upload_id = req.match_info().query("upload_id");
     let digest = &digest.digest;
-    let repo_id = Repo::build_id(group, name);
+    let repo_id = Repo::build_id(name);
     let enforcer = enforcer.read().await;
-    enforcer.check(current_user.id(), &Repo::build_guid(&repo_id), "image:push")?;
+    enforcer.check_nested(current_user.id(), &Repo::build_guid(&repo_id), "image:push")?;
 
-    log::debug!("looking for repo {}/{}", group, name);
+    log::debug!("looking for repo {}", name);
     let repo = repos
         .find(&repo_id)
         .await?
@@ -207,20 +242,18 @@ pub async fn complete(
         .await?
         .ok_or_else(|| Error::from(ErrorCode::BlobUploadUnknown))?;
 
-    let chunk = match body {
-        Some(body) => {
-            log::debug!("building chunk");
-            Some((chunk_from_request(req.headers(), body.len())?, body))
-        }
-        None => None,
-    };
+    // the final chunk is optional, an empty body is simply not stored
+    let (start_range, size) = chunk_from_request(&req, &upload)?;
+    log::debug!("pushing final chunk");
+    let upload = uploads
+        .push_chunk(upload, start_range, size, payload_stream(&req, payload))
+        .await?;
 
     log::debug!("completing upload");
-    // TODO: check digest matches chunk
-    let upload = uploads.complete_upload(upload, &digest, chunk).await?;
+    let upload = uploads.complete_upload(&repo, upload, &digest).await?;
     let digest_s = digest.to_string();
-    let blob = Blob::new(digest.clone(), group, name);
-    blobs.save(blob).await?;
+    let blob = Blob::new(digest.clone(), name, upload.latest_offset());
+    blobs.push_blob(blob).await?;
 
     Ok(HttpResponse::Created()
         .header(
@@ -235,25 +268,24 @@ pub async fn complete(
         .finish())
 }
 
-#[delete("/{group}/{name}/blobs/uploads/{upload_id}")]
+#[delete("/{name:.+}/blobs/uploads/{upload_id}")]
 pub async fn delete(
     uploads: Data<UploadService>,
     repos: Data<RepoService>,
     enforcer: Data<Arc<RwLock<Enforcer>>>,
     scope: OAuthScope,
     current_user: CurrentUser,
-    path: Path<RepoPath>,
+    path: RepoPath,
     upload: Path<UploadPath>,
 ) -> Result<HttpResponse> {
-    Scope::from("oci:image:push").matches(&scope)?;
-    let group = &path.group;
-    let name = &path.name;
+    let name = path.name();
+    image_scope(name, "push").matches(&scope)?;
     let upload_id = &upload.upload_id;
-    let repo_id = Repo::build_id(group, name);
+    let repo_id = Repo::build_id(name);
     let enforcer = enforcer.read().await;
-    enforcer.check(current_user.id(), &Repo::build_guid(&repo_id), "image:push")?;
+    enforcer.check_nested(current_user.id(), &Repo::build_guid(&repo_id), "image:push")?;
 
-    log::debug!("looking for repo {}/{}", group, name);
+    log::debug!("looking for repo {}", name);
     repos
         .find(&repo_id)
         .await?
@@ -270,25 +302,78 @@ pub async fn delete(
     Ok(HttpResponse::NoContent().finish())
 }
 
-fn chunk_from_request(headers: &HeaderMap, size: usize) -> Result<UploadChunk> {
-    let content_length = headers
+/// Reads the chunk start offset and size from the request headers.
+/// Chunks without a `Content-Range` continue from the latest upload offset,
+/// while chunks without a `Content-Length` have an unknown size.
+/// Chunks declaring a size above the maximum body size are rejected.
+fn chunk_from_request(req: &HttpRequest, upload: &Upload) -> Result<(usize, Option<usize>)> {
+    let headers = req.headers();
+    let size = headers
         .get(http::header::CONTENT_LENGTH)
         .and_then(|h| h.to_str().ok())
-        .and_then(|h| h.parse().ok())
-        .unwrap_or(size);
+        .and_then(|h| h.parse().ok());
+
+    let max_size = max_body_size(req);
+    if let Some(size) = size.filter(|size| *size > max_size) {
+        log::debug!("chunk of {} bytes exceeds the maximum body size", size);
+        return Err(Error::new(
+            ErrorCode::SizeInvalid,
+            "request body exceeds the maximum size",
+        )
+        .with_detail("max_size", max_size)
+        .into());
+    }
 
-    let content_range = headers.get(http::header::CONTENT_RANGE);
-    let (start_range, end_range) = if let Some(hdr) = content_range {
-        let value = hdr
+    let start_range = match headers.get(http::header::CONTENT_RANGE) {
+        Some(hdr) => hdr
             .to_str()
-            .map_err(|_| Error::from(ErrorCode::Unsupported))?;
-        let range: Vec<&str> = value.split('-').collect();
-        let start_range: usize = range.first().unwrap().parse().unwrap();
-        let end_range: usize = range.last().unwrap().parse().unwrap();
-        (start_range, end_range)
-    } else {
-        (0, content_length)
+            .ok()
+            .and_then(|value| value.split('-').next())
+            .and_then(|offset| offset.trim().parse().ok())
+            .ok_or_else(|| Error::from(ErrorCode::Unsupported))?,
+        None => upload.latest_offset(),
     };
 
-    Ok(UploadChunk::new(start_range, end_range))
+    Ok((start_range, size))
+}
+
+fn max_body_size(req: &HttpRequest) -> usize {
+    req.app_data::<MaxBodySize>()
+        .map(|max_size| max_size.0)
+        .unwrap_or(usize::MAX)
+}
+
+/// Payloads are bound to the actix worker thread, while storage providers
+/// need a `Send` stream, so chunks are forwarded through a bounded channel
+/// that also applies backpressure to the client.
+/// The stream fails as soon as the body exceeds the maximum body size.
+fn payload_stream(
+    req: &HttpRequest,
+    mut payload: Payload,
+) -> impl Stream<Item = ByteChunk> + Send + Sync + 'static {
+    let max_size = max_body_size(req);
+    let (mut tx, rx) = mpsc::channel(PAYLOAD_BUFFER_SIZE);
+    actix_rt::spawn(async move {
+        let mut received = 0;
+        while let Some(chunk) = payload.next().await {
+            let chunk = chunk
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))
+                .and_then(|bytes| {
+                    received += bytes.len();
+                    if received > max_size {
+                        Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "request body exceeds the maximum size",
+                        ))
+                    } else {
+                        Ok(bytes)
+                    }
+                });
+            let failed = chunk.is_err();
+            if tx.send(chunk).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+    rx
 }
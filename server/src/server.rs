@@ -16,6 +16,7 @@ use tokio::sync::RwLock;
 use url::Url;
 
 use ::rbac::{Enforcer, Watcher};
+use enseada::secure;
 use events::EventBus;
 
 use crate::config::Configuration;
@@ -28,6 +29,9 @@ pub async fn run(cfg: Configuration) -> io::Result<()> {
     let secret_key = cfg.secret_key();
     let tls = cfg.tls();
 
+    // credentials of remote repositories are encrypted with the secret key
+    secure::init_encryption_key(&secret_key);
+
     let couch = couchdb::from_config(&cfg);
     let rbac_db = couch.database(dbname::RBAC, true);
 
@@ -38,9 +42,41 @@ pub async fn run(cfg: Configuration) -> io::Result<()> {
     watcher.start().expect("Watcher::start()");
 
     let store = Arc::new(storage::new_provider(&cfg).expect("storage provider"));
+    let presigned = Arc::new(storage::PresignedUrls::new(&cfg));
 
     let event_bus = Arc::new(std::sync::RwLock::new(EventBus::new()));
 
+    let notification = oci::subscribe(
+        &cfg,
+        couch.database(dbname::OCI, true),
+        event_bus.clone(),
+        store.clone(),
+    );
+    maven::subscribe(
+        couch.database(dbname::MAVEN, true),
+        event_bus.clone(),
+        store.clone(),
+    );
+
+    let gc = oci::gc::GcJob::new(
+        &cfg,
+        couch.database(dbname::OCI, true),
+        event_bus.clone(),
+        store.clone(),
+    );
+    if cfg.oci().gc().enabled() {
+        gc.start();
+    }
+
+    let retention = oci::retention::RetentionJob::new(
+        &cfg,
+        couch.database(dbname::OCI, true),
+        event_bus.clone(),
+    );
+    if cfg.oci().retention().enabled() {
+        retention.start();
+    }
+
     let server_cfg = cfg.clone();
     let server = HttpServer::new(move || {
         let public_host = server_cfg.public_url();
@@ -64,6 +100,7 @@ pub async fn run(cfg: Configuration) -> io::Result<()> {
             // because app_data() stuff is not accessible in nested scopes.
             // Should hopefully be fixed with Actix Web 3.0
             .data(enforcer.clone())
+            .data(presigned.clone())
             .configure(user::mount(
                 couch.database(crate::couchdb::name::USERS, true),
                 event_bus.clone(),
@@ -80,6 +117,7 @@ pub async fn run(cfg: Configuration) -> io::Result<()> {
                 couch.database(crate::couchdb::name::OCI, true),
                 event_bus.clone(),
                 store.clone(),
+                notification.clone(),
             ))
             .configure(maven::mount(
                 couch.database(crate::couchdb::name::MAVEN, true),
@@ -116,6 +154,8 @@ pub async fn run(cfg: Configuration) -> io::Result<()> {
     );
     server.run().await?;
     watcher.stop();
+    gc.stop();
+    retention.stop();
 
     Ok(())
 }
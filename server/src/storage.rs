@@ -1,7 +1,22 @@
+use std::fmt::{self, Debug, Formatter};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use hold_s3::{S3Config, S3Credentials, S3Provider};
+use rusoto_core::{HttpClient, Region, RusotoError};
+use rusoto_credential::{DefaultCredentialsProvider, ProvideAwsCredentials, StaticProvider};
+use rusoto_s3::util::{PreSignedRequest, PreSignedRequestOption};
+use rusoto_s3::{
+    CopyObjectRequest, GetObjectError, GetObjectRequest, ListObjectsV2Request, S3Client, S3,
+};
+use url::form_urlencoded::byte_serialize;
 
 use enseada::error::Error;
-use enseada::storage::Provider;
+use enseada::storage::blob::Blob;
+use enseada::storage::error::Error as StorageError;
+use enseada::storage::provider::Provider as BlobProvider;
+use enseada::storage::{stream_blob, ByteRange, Provider, StorageProvider, StoredBlob};
 
 use crate::config::{Configuration, Storage};
 
@@ -12,14 +27,15 @@ pub fn new_provider(cfg: &Configuration) -> Result<Provider, Error> {
             endpoint,
             access_key_id,
             secret_access_key,
+            ..
         } => {
-            let provider = new_s3_provider(
+            let storage = S3Storage::new(
                 bucket.clone(),
                 endpoint.clone(),
                 access_key_id.clone(),
                 secret_access_key.clone(),
             );
-            Ok(Box::new(provider))
+            Ok(Box::new(storage))
         }
         Storage::Unknown => Err(unknown_provider_error()),
     }
@@ -50,6 +66,289 @@ pub fn new_s3_provider(
     })
 }
 
+/// S3 storage. Blobs are read and written by the `hold` provider,
+/// while the operations it lacks, listing, ranged reads and copies, use the S3 API directly.
+pub struct S3Storage {
+    provider: S3Provider,
+    s3: S3Client,
+    bucket: String,
+}
+
+impl S3Storage {
+    /// Builds the S3 client with the same region and credentials as the `hold` provider.
+    pub fn new(
+        bucket: String,
+        endpoint: Option<String>,
+        access_key_id: Option<String>,
+        secret_access_key: Option<String>,
+    ) -> Self {
+        let region = region(endpoint.clone());
+        let s3 = match (access_key_id.clone(), secret_access_key.clone()) {
+            (Some(access_key_id), Some(secret_access_key)) => S3Client::new_with(
+                HttpClient::new().expect("S3 HTTP client"),
+                StaticProvider::new_minimal(access_key_id, secret_access_key),
+                region,
+            ),
+            _ => S3Client::new(region),
+        };
+        Self {
+            provider: new_s3_provider(bucket.clone(), endpoint, access_key_id, secret_access_key),
+            s3,
+            bucket,
+        }
+    }
+}
+
+#[async_trait]
+impl BlobProvider for S3Storage {
+    async fn get_blob(&self, key: &str) -> enseada::storage::Result<Option<Blob>> {
+        self.provider.get_blob(key).await
+    }
+
+    async fn store_blob(&self, blob: Blob) -> enseada::storage::Result<Blob> {
+        self.provider.store_blob(blob).await
+    }
+
+    async fn is_blob_present(&self, key: &str) -> enseada::storage::Result<bool> {
+        self.provider.is_blob_present(key).await
+    }
+
+    async fn delete_blob(&self, key: &str) -> enseada::storage::Result<()> {
+        self.provider.delete_blob(key).await
+    }
+}
+
+#[async_trait]
+impl StorageProvider for S3Storage {
+    async fn list_blobs(&self, prefix: &str) -> enseada::storage::Result<Vec<StoredBlob>> {
+        log::debug!("Listing blobs under {}", prefix);
+        let mut blobs = Vec::new();
+        let mut continuation_token = None;
+        loop {
+            let req = ListObjectsV2Request {
+                bucket: self.bucket.clone(),
+                prefix: Some(prefix.to_string()),
+                continuation_token,
+                ..ListObjectsV2Request::default()
+            };
+            let output = self
+                .s3
+                .list_objects_v2(req)
+                .await
+                .map_err(StorageError::provider)?;
+
+            for object in output.contents.unwrap_or_default() {
+                let key = match object.key {
+                    Some(key) => key,
+                    None => continue,
+                };
+                let last_modified = object
+                    .last_modified
+                    .and_then(|date| DateTime::parse_from_rfc3339(&date).ok())
+                    .map(|date| date.with_timezone(&Utc));
+                let size = object.size.unwrap_or_default() as usize;
+                blobs.push(StoredBlob::new(&key, size, last_modified));
+            }
+
+            continuation_token = output.next_continuation_token;
+            if !output.is_truncated.unwrap_or(false) || continuation_token.is_none() {
+                return Ok(blobs);
+            }
+        }
+    }
+
+    async fn get_blob_range(
+        &self,
+        key: &str,
+        range: ByteRange,
+    ) -> enseada::storage::Result<Option<Blob>> {
+        log::debug!(
+            "Fetching bytes {}-{} of blob {}",
+            range.start(),
+            range.end(),
+            key
+        );
+        let req = GetObjectRequest {
+            bucket: self.bucket.clone(),
+            key: key.to_string(),
+            range: Some(format!("bytes={}-{}", range.start(), range.end())),
+            ..GetObjectRequest::default()
+        };
+        let output = match self.s3.get_object(req).await {
+            Ok(output) => output,
+            Err(RusotoError::Service(GetObjectError::NoSuchKey(_))) => return Ok(None),
+            Err(err) => return Err(StorageError::provider(err)),
+        };
+        match output.body {
+            Some(body) => {
+                let size = output
+                    .content_length
+                    .map_or(range.len(), |size| size as usize);
+                Ok(Some(Blob::new(key, size, body)))
+            }
+            None => Err(StorageError::body_error("no body found in S3 response")),
+        }
+    }
+
+    async fn move_blob(&self, from: &str, to: &str) -> enseada::storage::Result<bool> {
+        log::debug!("Copying blob {} to {}", from, to);
+        let req = CopyObjectRequest {
+            bucket: self.bucket.clone(),
+            key: to.to_string(),
+            copy_source: copy_source(&self.bucket, from),
+            ..CopyObjectRequest::default()
+        };
+        match self.s3.copy_object(req).await {
+            Ok(_) => {}
+            // S3 reports a missing source without a service error
+            Err(RusotoError::Unknown(res)) if res.status.as_u16() == 404 => return Ok(false),
+            // a single copy is limited to 5GB, larger blobs are streamed instead
+            Err(RusotoError::Unknown(res)) if res.status.as_u16() == 400 => {
+                log::debug!("S3 refused to copy {}, streaming it instead", from);
+                return stream_blob(self, from, to).await;
+            }
+            Err(err) => return Err(StorageError::provider(err)),
+        }
+        self.provider.delete_blob(from).await?;
+        Ok(true)
+    }
+}
+
+impl Debug for S3Storage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("S3Storage")
+            .field("bucket", &self.bucket)
+            .finish()
+    }
+}
+
+/// The region of a custom S3 endpoint, e.g. MinIO, is only used to sign requests.
+fn region(endpoint: Option<String>) -> Region {
+    match endpoint {
+        Some(endpoint) => Region::Custom {
+            name: Region::default().name().to_string(),
+            endpoint,
+        },
+        None => Region::default(),
+    }
+}
+
+/// The source of a copy is the bucket and the URL-encoded key of the object.
+fn copy_source(bucket: &str, key: &str) -> String {
+    let key = key
+        .split('/')
+        .map(|segment| byte_serialize(segment.as_bytes()).collect::<String>())
+        .collect::<Vec<String>>()
+        .join("/");
+    // form encoding turns spaces into `+`, which S3 would take literally
+    format!("{}/{}", bucket, key.replace('+', "%20"))
+}
+
 pub fn unknown_provider_error() -> Error {
     Error::new("unknown storage provider")
 }
+
+/// Generates short-lived URLs to download blobs straight from the storage,
+/// so that their content doesn't go through the server.
+///
+/// Only S3 storage supports them. When they are disabled, or the URL
+/// can't be signed, downloads fall back to proxying the content.
+pub struct PresignedUrls {
+    presigner: Option<S3Presigner>,
+    ttl: Duration,
+}
+
+impl PresignedUrls {
+    pub fn new(cfg: &Configuration) -> Self {
+        let (presigner, ttl) = match cfg.storage() {
+            Storage::S3 {
+                bucket,
+                endpoint,
+                access_key_id,
+                secret_access_key,
+                presign,
+            } if presign.enabled() => {
+                let presigner = S3Presigner::new(
+                    bucket.clone(),
+                    endpoint.clone(),
+                    access_key_id.clone(),
+                    secret_access_key.clone(),
+                );
+                (presigner, presign.ttl())
+            }
+            _ => (None, 0),
+        };
+        Self {
+            presigner,
+            ttl: Duration::from_secs(ttl),
+        }
+    }
+
+    /// Returns a pre-signed URL to download a blob, or `None` if downloads
+    /// must be proxied.
+    pub async fn url(&self, key: &str) -> Option<String> {
+        let presigner = self.presigner.as_ref()?;
+        presigner.presign_get(key, self.ttl).await
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.presigner.is_some()
+    }
+}
+
+struct S3Presigner {
+    bucket: String,
+    region: Region,
+    credentials: Box<dyn ProvideAwsCredentials + Send + Sync>,
+}
+
+impl S3Presigner {
+    /// Builds the presigner with the same region and credentials as the storage provider.
+    fn new(
+        bucket: String,
+        endpoint: Option<String>,
+        access_key_id: Option<String>,
+        secret_access_key: Option<String>,
+    ) -> Option<Self> {
+        let region = region(endpoint);
+        let credentials: Box<dyn ProvideAwsCredentials + Send + Sync> =
+            match (access_key_id, secret_access_key) {
+                (Some(access_key_id), Some(secret_access_key)) => Box::new(
+                    StaticProvider::new_minimal(access_key_id, secret_access_key),
+                ),
+                _ => match DefaultCredentialsProvider::new() {
+                    Ok(provider) => Box::new(provider),
+                    Err(err) => {
+                        log::warn!("S3 URLs can't be pre-signed without credentials: {}", err);
+                        return None;
+                    }
+                },
+            };
+        Some(Self {
+            bucket,
+            region,
+            credentials,
+        })
+    }
+
+    async fn presign_get(&self, key: &str, ttl: Duration) -> Option<String> {
+        let credentials = match self.credentials.credentials().await {
+            Ok(credentials) => credentials,
+            Err(err) => {
+                log::warn!(
+                    "failed to load S3 credentials, {} won't be pre-signed: {}",
+                    key,
+                    err
+                );
+                return None;
+            }
+        };
+        let req = GetObjectRequest {
+            bucket: self.bucket.clone(),
+            key: key.to_string(),
+            ..GetObjectRequest::default()
+        };
+        let option = PreSignedRequestOption { expires_in: ttl };
+        Some(req.get_presigned_url(&self.region, &credentials, &option))
+    }
+}